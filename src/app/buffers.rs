@@ -0,0 +1,197 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::buffer::{BufferError, Document, EditSpan};
+use crate::encoding::CharEncoding;
+use crate::format::magic::{self, Match};
+use crate::format::{elf, pe, Executable};
+
+/// 名前のないバッファの名前
+const UNNAMED: &str = "[New]";
+
+/// 次に作るバッファの ID
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// 編集バッファ（ドキュメントとバッファごとのカーソル・表示状態）
+pub struct Buffer {
+    /// バッファ ID
+    /// ウィンドウが表示するバッファを指すのに使う、名前と違って変わらない
+    pub id: u64,
+    /// 編集中のドキュメント
+    pub document: Document,
+    /// バッファ名（同名のファイルを開いた場合は <2> などを付けて区別する）
+    pub name: String,
+    /// カーソル位置
+    pub cursor: usize,
+    /// 表示オフセット
+    pub offset: usize,
+    /// 選択範囲
+    pub selection: Option<(usize, usize)>,
+    /// 選択開始位置
+    pub selection_start: Option<usize>,
+    /// 文字エンコーディング
+    pub encoding: CharEncoding,
+    /// 最後に自動保存したドキュメントのリビジョン
+    pub journal_revision: u64,
+    /// 最後に自動保存した時刻
+    pub journal_time: Instant,
+    /// 開いた時に見つかったジャーナルを復元待ち（上書きしない）
+    pub recover_pending: bool,
+    /// ドキュメントの内容から調べたファイル形式
+    formats: Option<Formats>,
+}
+
+/// ドキュメントの内容から調べたファイル形式
+/// 調べる時に読んだ範囲が編集されたら調べ直す
+struct Formats {
+    /// 調べた時のドキュメントのリビジョン
+    revision: u64,
+    /// マジックナンバーで判定した形式
+    file_type: Option<Match>,
+    /// ELF・PE として解析したヘッダ
+    executable: Option<Executable>,
+    /// 判定・解析で読んだ範囲
+    /// ここに触れず、手前で長さも変えない編集なら結果は変わらない
+    watched: Vec<Range<u64>>,
+}
+
+impl Formats {
+    /// 編集された範囲 span が読んだ範囲に影響するかどうか
+    /// 長さの変わる編集はそれより後ろをずらすので、
+    /// 読んだ範囲より手前でも影響する
+    /// 空になったドキュメント全体のような空の範囲も、
+    /// 手前で変わったものとして扱う
+    fn affected_by(&self, span: EditSpan) -> bool {
+        let (start, end) = (span.start as u64, span.end as u64);
+        let shifts = span.delta != 0 || start == end;
+        self.watched.iter().any(|range| start < range.end && (shifts || range.start < end))
+    }
+}
+
+impl Buffer {
+    /// ドキュメントから新しいバッファを作成（名前はファイル名）
+    pub fn new(document: Document) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name: Self::base_name(&document).to_string(),
+            journal_revision: document.revision(),
+            document,
+            cursor: 0,
+            offset: 0,
+            selection: None,
+            selection_start: None,
+            encoding: CharEncoding::Utf8,
+            journal_time: Instant::now(),
+            recover_pending: false,
+            formats: None,
+        }
+    }
+
+    /// ドキュメントのファイル名から付けるバッファ名
+    pub fn base_name(document: &Document) -> &str {
+        document.filename().unwrap_or(UNNAMED)
+    }
+
+    /// ファイル名のない、空で未変更のバッファかどうか
+    /// ファイルを開いたら置き換えてよい
+    pub fn is_blank(&self) -> bool {
+        self.document.path().is_none() && self.document.is_empty() && !self.document.is_modified()
+    }
+
+    /// ファイル形式（ヘッダやテーブルが編集されていたら調べ直す）
+    fn formats(&mut self) -> &Formats {
+        let revision = self.document.revision();
+        let stale = match &self.formats {
+            None => true,
+            Some(formats) if formats.revision == revision => false,
+            Some(formats) => self
+                .document
+                .changes_since(formats.revision)
+                .is_some_and(|span| formats.affected_by(span)),
+        };
+        if stale {
+            let head = self
+                .document
+                .get_range(0, self.document.len().min(magic::HEAD_LEN))
+                .unwrap_or_default();
+            let executable = Executable::parse(&self.document);
+            let mut watched = executable
+                .as_ref()
+                .map_or(Vec::new(), |executable| executable.tables().to_vec());
+            watched.push(0..magic::HEAD_LEN as u64);
+            // ELF・PE に見えて解析できなかったものは、どこが直っても調べ直す
+            if executable.is_none() && (head.starts_with(elf::MAGIC) || head.starts_with(pe::MAGIC))
+            {
+                watched.push(0..u64::MAX);
+            }
+            self.formats = Some(Formats {
+                revision,
+                file_type: magic::identify(&head),
+                executable,
+                watched,
+            });
+        } else if let Some(formats) = &mut self.formats {
+            formats.revision = revision;
+        }
+        self.formats.as_ref().expect("formats set above")
+    }
+
+    /// マジックナンバーで判定したファイル形式
+    pub fn file_type(&mut self) -> Option<&Match> {
+        self.formats().file_type.as_ref()
+    }
+
+    /// ELF・PE のヘッダ（どちらでもなければ None）
+    pub fn executable(&mut self) -> Option<&Executable> {
+        self.formats().executable.as_ref()
+    }
+
+    /// 前回から interval 以上経っていて変更があれば
+    /// 自動保存ジャーナルを書き出す
+    /// 見つかったジャーナルを復元せずに編集を始めたら、
+    /// 以後はその編集で置き換える
+    pub fn auto_save(&mut self, interval: Duration) -> Result<(), BufferError> {
+        let revision = self.document.revision();
+        if revision != self.journal_revision {
+            self.recover_pending = false;
+        }
+        if self.recover_pending
+            || revision == self.journal_revision
+            || self.journal_time.elapsed() < interval
+        {
+            return Ok(());
+        }
+        self.journal_revision = revision;
+        self.journal_time = Instant::now();
+        if !self.document.is_modified() {
+            // 保存時点に戻ったらジャーナルは不要
+            self.document.remove_journal();
+            Ok(())
+        } else {
+            self.document.write_journal().map(|_| ())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_affected_by() {
+        let formats = Formats {
+            revision: 0,
+            file_type: None,
+            executable: None,
+            watched: vec![0..0x9000, 0x20000..0x20100],
+        };
+        assert!(!formats.affected_by(EditSpan::new(0x10000, 4, 4)));
+        assert!(formats.affected_by(EditSpan::new(0x10000, 0, 1)));
+        assert!(formats.affected_by(EditSpan::new(0x10000, 1, 0)));
+        assert!(!formats.affected_by(EditSpan::new(0x30000, 0, 1)));
+        assert!(formats.affected_by(EditSpan::new(0x200FF, 1, 1)));
+        assert!(formats.affected_by(EditSpan::new(0x10, 1, 1)));
+        assert!(formats.affected_by(EditSpan { start: 0, end: 0, delta: 0 }));
+    }
+}
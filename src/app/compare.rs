@@ -0,0 +1,120 @@
+use std::ops::Range;
+
+use crate::buffer::{Alignment, Comparer, Document, Hunk};
+
+/// 2つのバッファの比較 (ehx --diff / M-x compare-buffers)
+/// 左右のウィンドウに A・B を表示し、カーソルとスクロールを連動させる
+pub struct Comparison {
+    /// A・B を表示するウィンドウの ID
+    pub windows: [usize; 2],
+    /// A・B のバッファ ID
+    pub buffers: [u64; 2],
+    /// 比較方法
+    pub alignment: Alignment,
+    /// 差分のある範囲（イベント処理の合間に少しずつ求める）
+    pub diff: Comparer,
+    /// 差分を求めている A・B のドキュメントのリビジョン
+    pub revisions: [u64; 2],
+}
+
+impl Comparison {
+    /// 差分の範囲の side 側（0 = A, 1 = B）
+    fn range(hunk: &Hunk, side: usize) -> &Range<usize> {
+        if side == 0 { &hunk.a } else { &hunk.b }
+    }
+
+    /// side 側の位置 pos に対応する反対側の位置
+    /// 差分の中では先頭からの距離を保ち、反対側の範囲に収める
+    pub fn map(&self, side: usize, pos: usize) -> usize {
+        let other = 1 - side;
+        let hunks = self.diff.hunks();
+        let index = hunks.partition_point(|h| Self::range(h, side).start <= pos);
+        let Some(hunk) = index.checked_sub(1).map(|i| &hunks[i]) else {
+            return pos;
+        };
+        let (from, to) = (Self::range(hunk, side), Self::range(hunk, other));
+        if pos < from.end {
+            (to.start + (pos - from.start)).min(to.end.saturating_sub(1).max(to.start))
+        } else {
+            to.end + (pos - from.end)
+        }
+    }
+
+    /// side 側で pos より後ろにある次の差分（番号と先頭位置）
+    pub fn next(&self, side: usize, pos: usize) -> Option<(usize, usize)> {
+        self.diff
+            .hunks()
+            .iter()
+            .enumerate()
+            .map(|(i, h)| (i, Self::range(h, side).start))
+            .find(|&(_, start)| start > pos)
+    }
+
+    /// side 側で pos より前にある差分（番号と先頭位置）
+    pub fn prev(&self, side: usize, pos: usize) -> Option<(usize, usize)> {
+        self.diff
+            .hunks()
+            .iter()
+            .enumerate()
+            .map(|(i, h)| (i, Self::range(h, side).start))
+            .rfind(|&(_, start)| start < pos)
+    }
+
+    /// 差分の数（比較し終えていなければ "17+" のように表示する）
+    pub fn count(&self) -> String {
+        let more = if self.diff.is_done() { "" } else { "+" };
+        format!("{}{}", self.diff.hunks().len(), more)
+    }
+
+    /// side 側の [start, end) と重なる差分の範囲（HEXビューの強調表示用）
+    /// 同じオフセット同士の比較なら、
+    /// まだ比較していない表示範囲もその場で比較する
+    pub fn ranges(
+        &self,
+        side: usize,
+        docs: [&Document; 2],
+        start: usize,
+        end: usize,
+    ) -> Vec<Range<usize>> {
+        let hunks = self.diff.hunks();
+        let first = hunks.partition_point(|h| Self::range(h, side).end <= start);
+        let mut ranges: Vec<Range<usize>> = hunks[first..]
+            .iter()
+            .map(|h| Self::range(h, side).clone())
+            .take_while(|r| r.start < end)
+            .filter(|r| !r.is_empty())
+            .collect();
+        let progress = self.diff.progress(side);
+        if self.alignment == Alignment::Fixed && !self.diff.is_done() && progress < end {
+            let from = progress.max(start);
+            ranges.retain(|r| r.start < from);
+            ranges.extend(fixed_ranges(docs, from, end));
+        }
+        ranges
+    }
+}
+
+/// [start, end) を同じオフセット同士で比較して、一致しない範囲を返す
+/// 片方が先に終わっていれば、その後ろはすべて差分とする
+fn fixed_ranges(docs: [&Document; 2], start: usize, end: usize) -> Vec<Range<usize>> {
+    let [a, b] = docs;
+    let common = end.min(a.len()).min(b.len());
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    if start < common {
+        let (Some(x), Some(y)) = (a.get_range(start, common), b.get_range(start, common)) else {
+            return ranges;
+        };
+        for (i, _) in x.iter().zip(y.iter()).enumerate().filter(|(_, (p, q))| p != q) {
+            let pos = start + i;
+            match ranges.last_mut() {
+                Some(last) if last.end == pos => last.end += 1,
+                _ => ranges.push(pos..pos + 1),
+            }
+        }
+    }
+    let longer = end.min(a.len().max(b.len()));
+    if common.max(start) < longer {
+        ranges.push(common.max(start)..longer);
+    }
+    ranges
+}
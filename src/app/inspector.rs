@@ -0,0 +1,778 @@
+use anyhow::{anyhow, bail, Result};
+
+/// インスペクタに表示する型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// 整数（1, 2, 4, 8 バイト）
+    Int { width: usize, signed: bool, big_endian: bool },
+    /// 浮動小数点数（2, 4, 8 バイト）
+    Float { width: usize, big_endian: bool },
+    /// 1バイトの2進数
+    Binary,
+    /// 1バイトの8進数
+    Octal,
+    /// 32bit の UNIX 時刻（1970-01-01 からの秒数）
+    UnixTime { big_endian: bool },
+    /// Windows の FILETIME（1601-01-01 からの 100ns 単位、リトルエンディアン）
+    FileTime,
+    /// FAT / ZIP の DOS 日時（時刻・日付の順に 16bit ずつ、リトルエンディアン）
+    DosDateTime,
+    /// GUID（先頭の3つのフィールドがリトルエンディアンの Windows 形式）
+    Guid,
+    /// 符号なし LEB128（varint）
+    Uleb128,
+    /// 符号付き LEB128
+    Sleb128,
+    /// UTF-8 の1文字
+    Utf8,
+    /// UTF-16 の1文字（サロゲートペアを含む）
+    Utf16 { big_endian: bool },
+}
+
+/// 表示する順
+pub const FIELDS: &[Field] = &[
+    Field::Int { width: 1, signed: true, big_endian: false },
+    Field::Int { width: 1, signed: false, big_endian: false },
+    Field::Int { width: 2, signed: true, big_endian: false },
+    Field::Int { width: 2, signed: true, big_endian: true },
+    Field::Int { width: 2, signed: false, big_endian: false },
+    Field::Int { width: 2, signed: false, big_endian: true },
+    Field::Int { width: 4, signed: true, big_endian: false },
+    Field::Int { width: 4, signed: true, big_endian: true },
+    Field::Int { width: 4, signed: false, big_endian: false },
+    Field::Int { width: 4, signed: false, big_endian: true },
+    Field::Int { width: 8, signed: true, big_endian: false },
+    Field::Int { width: 8, signed: true, big_endian: true },
+    Field::Int { width: 8, signed: false, big_endian: false },
+    Field::Int { width: 8, signed: false, big_endian: true },
+    Field::Float { width: 2, big_endian: false },
+    Field::Float { width: 2, big_endian: true },
+    Field::Float { width: 4, big_endian: false },
+    Field::Float { width: 4, big_endian: true },
+    Field::Float { width: 8, big_endian: false },
+    Field::Float { width: 8, big_endian: true },
+    Field::Binary,
+    Field::Octal,
+    Field::UnixTime { big_endian: false },
+    Field::UnixTime { big_endian: true },
+    Field::FileTime,
+    Field::DosDateTime,
+    Field::Guid,
+    Field::Uleb128,
+    Field::Sleb128,
+    Field::Utf8,
+    Field::Utf16 { big_endian: false },
+    Field::Utf16 { big_endian: true },
+];
+
+/// 表示に読むバイト数（GUID と LEB128 の最大長）
+pub const READ_LEN: usize = 16;
+
+/// LEB128 の最大バイト数（64bit）
+const MAX_LEB128: usize = 10;
+
+/// FILETIME の起点（1601-01-01）から UNIX 時刻の起点までの秒数
+const FILETIME_EPOCH: i64 = 11_644_473_600;
+
+impl Field {
+    /// 表示名
+    pub fn label(self) -> String {
+        let order = |big_endian| if big_endian { "BE" } else { "LE" };
+        match self {
+            Field::Int { width: 1, signed, .. } => format!("{}8", if signed { 'i' } else { 'u' }),
+            Field::Int { width, signed, big_endian } => {
+                format!("{}{} {}", if signed { 'i' } else { 'u' }, width * 8, order(big_endian))
+            }
+            Field::Float { width, big_endian } => format!("f{} {}", width * 8, order(big_endian)),
+            Field::Binary => "binary".to_string(),
+            Field::Octal => "octal".to_string(),
+            Field::UnixTime { big_endian } => format!("time_t {}", order(big_endian)),
+            Field::FileTime => "FILETIME".to_string(),
+            Field::DosDateTime => "DOS time".to_string(),
+            Field::Guid => "GUID".to_string(),
+            Field::Uleb128 => "ULEB128".to_string(),
+            Field::Sleb128 => "SLEB128".to_string(),
+            Field::Utf8 => "UTF-8".to_string(),
+            Field::Utf16 { big_endian } => format!("UTF-16{}", order(big_endian)),
+        }
+    }
+
+    /// data（カーソル位置からのバイト列）を表示用の文字列にする
+    /// バイトが足りない・その型として不正なら None
+    pub fn decode(self, data: &[u8]) -> Option<String> {
+        match self {
+            Field::Int {
+                width,
+                signed: false,
+                big_endian,
+            } => Some(read_uint(data, width, big_endian)?.to_string()),
+            Field::Int {
+                width,
+                signed: true,
+                big_endian,
+            } => {
+                let shift = 64 - width * 8;
+                Some((((read_uint(data, width, big_endian)? << shift) as i64) >> shift).to_string())
+            }
+            Field::Float { width, big_endian } => {
+                let raw = read_uint(data, width, big_endian)?;
+                Some(match width {
+                    2 => format_float(f16_to_f64(raw as u16) as f32),
+                    4 => format_float(f32::from_bits(raw as u32)),
+                    _ => format_float(f64::from_bits(raw)),
+                })
+            }
+            Field::Binary => Some(format!("{:08b}", data.first()?)),
+            Field::Octal => Some(format!("{:03o}", data.first()?)),
+            Field::UnixTime { big_endian } => {
+                Some(format_datetime(read_uint(data, 4, big_endian)? as i64))
+            }
+            Field::FileTime => {
+                let ticks = read_uint(data, 8, false)?;
+                let secs = (ticks / 10_000_000) as i64 - FILETIME_EPOCH;
+                // 9999 年を超えるものは日時として扱わない
+                (secs < 253_402_300_800).then(|| format_datetime(secs))
+            }
+            Field::DosDateTime => {
+                let (time, date) = (
+                    read_uint(data, 2, false)?,
+                    read_uint(data.get(2..)?, 2, false)?,
+                );
+                let (year, month, day) = (1980 + (date >> 9), (date >> 5) & 0xF, date & 0x1F);
+                let (hour, min, sec) = (time >> 11, (time >> 5) & 0x3F, (time & 0x1F) * 2);
+                let valid = (1..=12).contains(&month)
+                    && (1..=31).contains(&day)
+                    && hour < 24
+                    && min < 60
+                    && sec < 60;
+                valid.then(|| {
+                    format!(
+                        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                        year, month, day, hour, min, sec
+                    )
+                })
+            }
+            Field::Guid => {
+                let bytes = data.get(..16)?;
+                Some(format!(
+                    "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}}}",
+                    read_uint(bytes, 4, false)?,
+                    read_uint(&bytes[4..], 2, false)?,
+                    read_uint(&bytes[6..], 2, false)?,
+                    bytes[8],
+                    bytes[9],
+                    bytes[10..].iter().map(|b| format!("{:02X}", b)).collect::<String>()
+                ))
+            }
+            Field::Uleb128 => {
+                let (value, len) = read_leb128(data, false)?;
+                Some(format!("{} ({} bytes)", value as u64, len))
+            }
+            Field::Sleb128 => {
+                let (value, len) = read_leb128(data, true)?;
+                Some(format!("{} ({} bytes)", value as i64, len))
+            }
+            Field::Utf8 => {
+                let len = match data.first()? {
+                    0x00..=0x7F => 1,
+                    0xC2..=0xDF => 2,
+                    0xE0..=0xEF => 3,
+                    0xF0..=0xF4 => 4,
+                    _ => return None,
+                };
+                let c = std::str::from_utf8(data.get(..len)?).ok()?.chars().next()?;
+                Some(format_char(c, len))
+            }
+            Field::Utf16 { big_endian } => {
+                let units = [
+                    read_uint(data, 2, big_endian),
+                    data.get(2..).and_then(|d| read_uint(d, 2, big_endian)),
+                ];
+                let units = units.iter().map_while(|u| u.map(|u| u as u16));
+                let c = char::decode_utf16(units).next()?.ok()?;
+                Some(format_char(c, c.len_utf16() * 2))
+            }
+        }
+    }
+
+    /// 編集プロンプトの初期値（encode で読める形、長さなどの補足は除く）
+    pub fn edit_text(self, data: &[u8]) -> Option<String> {
+        let text = self.decode(data)?;
+        Some(match self {
+            Field::Uleb128 | Field::Sleb128 | Field::Utf8 | Field::Utf16 { .. } => {
+                text.split(' ').next().unwrap_or_default().to_string()
+            }
+            _ => text,
+        })
+    }
+
+    /// 入力した値をバイト列にする
+    /// 整数なら10進か `0x` 付きの16進、日時なら `YYYY-MM-DD hh:mm:ss`
+    /// 整数ならその値をそのまま書く
+    /// data は現在のバイト列（LEB128 を同じ長さで書き戻すのに使う）
+    pub fn encode(self, text: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let text = text.trim();
+        match self {
+            Field::Int { width, signed, big_endian } => {
+                let value = parse_int(text)?;
+                let bits = width as u32 * 8;
+                let (min, max) = if signed {
+                    (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+                } else {
+                    (0, (1i128 << bits) - 1)
+                };
+                if value < min || value > max {
+                    bail!("Value {} is out of range for {}", value, self.label());
+                }
+                Ok(write_uint(value as u64, width, big_endian))
+            }
+            Field::Float { width, big_endian } => {
+                let value: f64 = text.parse().map_err(|_| anyhow!("Invalid number: {}", text))?;
+                let raw = match width {
+                    2 => u64::from(f64_to_f16(value)),
+                    4 => u64::from((value as f32).to_bits()),
+                    _ => value.to_bits(),
+                };
+                Ok(write_uint(raw, width, big_endian))
+            }
+            Field::Binary | Field::Octal => {
+                let (radix, prefix) = if self == Field::Binary {
+                    (2, "0b")
+                } else {
+                    (8, "0o")
+                };
+                let digits = text.strip_prefix(prefix).unwrap_or(text);
+                let byte = u8::from_str_radix(digits, radix)
+                    .map_err(|_| anyhow!("Invalid byte: {}", text))?;
+                Ok(vec![byte])
+            }
+            Field::UnixTime { big_endian } => {
+                let secs = parse_int(text).or_else(|_| parse_datetime(text))?;
+                if !(0..=i128::from(u32::MAX)).contains(&secs) {
+                    bail!("Time is out of range for time_t: {}", text);
+                }
+                Ok(write_uint(secs as u64, 4, big_endian))
+            }
+            Field::FileTime => {
+                let ticks = match parse_int(text) {
+                    Ok(ticks) => ticks,
+                    Err(_) => (parse_datetime(text)? + i128::from(FILETIME_EPOCH)) * 10_000_000,
+                };
+                if !(0..=i128::from(u64::MAX)).contains(&ticks) {
+                    bail!("Time is out of range for FILETIME: {}", text);
+                }
+                Ok(write_uint(ticks as u64, 8, false))
+            }
+            Field::DosDateTime => {
+                let packed = match parse_int(text) {
+                    Ok(packed) if (0..=i128::from(u32::MAX)).contains(&packed) => packed as u64,
+                    Ok(_) => bail!("Value is out of range for DOS time: {}", text),
+                    Err(_) => {
+                        let secs = parse_datetime(text)? as i64;
+                        let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+                        let secs = secs.rem_euclid(86_400);
+                        if !(1980..=2107).contains(&year) {
+                            bail!("Year must be between 1980 and 2107: {}", text);
+                        }
+                        let date =
+                            ((year - 1980) as u64) << 9 | u64::from(month) << 5 | u64::from(day);
+                        let time = (secs / 3600) << 11 | (secs / 60 % 60) << 5 | (secs % 60 / 2);
+                        time as u64 | date << 16
+                    }
+                };
+                Ok(write_uint(packed, 4, false))
+            }
+            Field::Guid => {
+                let hex: String = text.chars().filter(|c| !matches!(c, '{' | '}' | '-')).collect();
+                if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    bail!("GUID must have 32 hex digits: {}", text);
+                }
+                let mut bytes: Vec<u8> = (0..16)
+                    .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap())
+                    .collect();
+                // 先頭の3つのフィールドはリトルエンディアンで格納する
+                bytes[..4].reverse();
+                bytes[4..6].reverse();
+                bytes[6..8].reverse();
+                Ok(bytes)
+            }
+            Field::Uleb128 | Field::Sleb128 => {
+                let signed = self == Field::Sleb128;
+                let value = parse_int(text)?;
+                let in_range = if signed {
+                    i64::try_from(value).is_ok()
+                } else {
+                    u64::try_from(value).is_ok()
+                };
+                if !in_range {
+                    bail!("Value {} is out of range for {}", value, self.label());
+                }
+                // 後ろのデータを壊さないよう、
+                // 今の長さに満たなければ冗長な形で埋める
+                let current = read_leb128(data, signed).map_or(0, |(_, len)| len);
+                let bytes = write_leb128(value, signed, current);
+                if current > 0 && bytes.len() > current {
+                    bail!(
+                        "{} needs {} bytes but the current value has {}",
+                        text,
+                        bytes.len(),
+                        current
+                    );
+                }
+                Ok(bytes)
+            }
+            Field::Utf8 | Field::Utf16 { .. } => {
+                let c = parse_char(text)?;
+                Ok(match self {
+                    Field::Utf16 { big_endian } => {
+                        let mut units = [0u16; 2];
+                        c.encode_utf16(&mut units)
+                            .iter()
+                            .flat_map(|&u| write_uint(u.into(), 2, big_endian))
+                            .collect()
+                    }
+                    _ => c.to_string().into_bytes(),
+                })
+            }
+        }
+    }
+}
+
+/// C-x i で開くデータインスペクタのペイン
+/// カーソル位置のバイト列を各種の型で表示・編集する
+pub struct Inspector {
+    /// 選択中の行（FIELDS の添字）
+    pub selected: usize,
+    /// 表示している先頭の行
+    pub scroll: usize,
+    /// ペインに表示できる行数
+    pub rows: usize,
+    /// ペインにフォーカスがあるか
+    pub focused: bool,
+}
+
+impl Inspector {
+    pub fn new() -> Self {
+        Self {
+            selected: 0,
+            scroll: 0,
+            rows: 1,
+            focused: false,
+        }
+    }
+
+    /// 選択中の型
+    pub fn field(&self) -> Field {
+        FIELDS[self.selected]
+    }
+
+    /// 選択を delta 行動かす（端で止まる）
+    pub fn move_by(&mut self, delta: isize) {
+        self.selected = self.selected.saturating_add_signed(delta).min(FIELDS.len() - 1);
+    }
+
+    /// 表示できる行数を設定し、選択中の行が収まるようにスクロールする
+    pub fn set_rows(&mut self, rows: usize) {
+        self.rows = rows.max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.rows {
+            self.scroll = self.selected + 1 - self.rows;
+        }
+    }
+}
+
+/// width バイトの符号なし整数を読む
+fn read_uint(data: &[u8], width: usize, big_endian: bool) -> Option<u64> {
+    let bytes = data.get(..width)?;
+    let fold = |acc: u64, &b: &u8| (acc << 8) | u64::from(b);
+    Some(if big_endian {
+        bytes.iter().fold(0, fold)
+    } else {
+        bytes.iter().rev().fold(0, fold)
+    })
+}
+
+/// value の下位 width バイトを並べる
+fn write_uint(value: u64, width: usize, big_endian: bool) -> Vec<u8> {
+    let mut bytes = value.to_le_bytes()[..width].to_vec();
+    if big_endian {
+        bytes.reverse();
+    }
+    bytes
+}
+
+/// LEB128 を読む（値と長さ）
+fn read_leb128(data: &[u8], signed: bool) -> Option<(i128, usize)> {
+    let mut value: i128 = 0;
+    for (i, &b) in data.iter().take(MAX_LEB128).enumerate() {
+        value |= i128::from(b & 0x7F) << (7 * i);
+        if b & 0x80 == 0 {
+            let bits = 7 * (i + 1);
+            if signed && b & 0x40 != 0 {
+                value -= 1 << bits;
+            }
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// LEB128 に書く（min_len に満たなければ継続ビット付きのバイトで埋める）
+fn write_leb128(mut value: i128, signed: bool, min_len: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = if signed {
+            (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0)
+        } else {
+            value == 0
+        };
+        if done && bytes.len() + 1 >= min_len {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// 整数を解釈（10進、`0x` 付きの16進、先頭の `-`）
+fn parse_int(text: &str) -> Result<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) if !hex.starts_with(['-', '+']) => i128::from_str_radix(hex, 16).ok(),
+        None if !digits.starts_with(['-', '+']) => digits.parse().ok(),
+        _ => None,
+    };
+    let value = value.ok_or_else(|| anyhow!("Invalid integer: {}", text))?;
+    Ok(if negative { -value } else { value })
+}
+
+/// `YYYY-MM-DD[ hh:mm[:ss]]`（`T` 区切りも可、UTC）を UNIX 時刻の秒数にする
+fn parse_datetime(text: &str) -> Result<i128> {
+    let invalid = || anyhow!("Expected YYYY-MM-DD hh:mm:ss: {}", text);
+    let (date, time) = text.split_once([' ', 'T']).unwrap_or((text, "00:00:00"));
+    let date: Vec<i64> = date
+        .split('-')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let mut time: Vec<i64> = time
+        .trim()
+        .split(':')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    if time.len() == 2 {
+        time.push(0);
+    }
+    let (&[year, month, day], &[hour, min, sec]) = (date.as_slice(), time.as_slice()) else {
+        return Err(invalid());
+    };
+    // 年は4桁まで（秒数の計算が桁あふれしないように）
+    let valid = (0..=9999).contains(&year)
+        && (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && hour < 24
+        && min < 60
+        && sec < 60;
+    if !valid || hour < 0 || min < 0 || sec < 0 {
+        return Err(invalid());
+    }
+    Ok(i128::from(
+        days_from_civil(year, month as u32, day as u32) * 86_400 + hour * 3600 + min * 60 + sec,
+    ))
+}
+
+/// UNIX 時刻の秒数を `YYYY-MM-DD hh:mm:ss`（UTC）にする
+fn format_datetime(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let secs = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// 1970-01-01 からの日数をグレゴリオ暦の年月日にする
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// グレゴリオ暦の年月日を 1970-01-01 からの日数にする
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// 半精度浮動小数点数を読む
+fn f16_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = i32::from((bits >> 10) & 0x1F);
+    let frac = f64::from(bits & 0x3FF);
+    match exp {
+        0 => sign * frac * 2f64.powi(-24),
+        31 if frac == 0.0 => sign * f64::INFINITY,
+        31 => f64::NAN,
+        _ => sign * (1.0 + frac / 1024.0) * 2f64.powi(exp - 15),
+    }
+}
+
+/// 半精度浮動小数点数に丸める（最近接偶数丸め）
+fn f64_to_f16(value: f64) -> u16 {
+    let sign = if value.is_sign_negative() { 0x8000 } else { 0 };
+    let abs = value.abs();
+    if abs.is_nan() {
+        return 0x7E00;
+    }
+    if abs < 2f64.powi(-14) {
+        // 非正規化数（丸めて最小の正規化数になっても同じビット列で表せる）
+        return sign | (abs * 2f64.powi(24)).round_ties_even() as u16;
+    }
+    let mut exp = abs.log2().floor() as i32;
+    if abs / 2f64.powi(exp) >= 2.0 {
+        exp += 1;
+    } else if abs / 2f64.powi(exp) < 1.0 {
+        exp -= 1;
+    }
+    let mut frac = ((abs / 2f64.powi(exp) - 1.0) * 1024.0).round_ties_even() as u16;
+    if frac == 1024 {
+        frac = 0;
+        exp += 1;
+    }
+    if exp > 15 {
+        return sign | 0x7C00;
+    }
+    sign | ((exp + 15) as u16) << 10 | frac
+}
+
+/// 浮動小数点数を表示用にする（極端に大きい・小さい値は指数表記）
+fn format_float<T: std::fmt::Display + std::fmt::LowerExp + Into<f64> + Copy>(value: T) -> String {
+    let abs = value.into().abs();
+    if abs != 0.0 && abs.is_finite() && !(1e-4..1e15).contains(&abs) {
+        format!("{:e}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// 文字を `U+3042 'あ'` の形にする（制御文字はコードポイントだけ）
+fn format_char(c: char, len: usize) -> String {
+    if c.is_control() {
+        format!("U+{:04X} ({} bytes)", u32::from(c), len)
+    } else {
+        format!("U+{:04X} '{}' ({} bytes)", u32::from(c), c, len)
+    }
+}
+
+/// 1文字（または `U+XXXX`）を解釈
+fn parse_char(text: &str) -> Result<char> {
+    if let Some(hex) = text.strip_prefix("U+").or_else(|| text.strip_prefix("u+")) {
+        return u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| anyhow!("Invalid code point: {}", text));
+    }
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => bail!("Enter one character or U+XXXX: {}", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(field: Field, data: &[u8]) -> String {
+        field.decode(data).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_decode() {
+        let data = [0xFE, 0xFF, 0x00, 0x3C, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(
+            decode(
+                Field::Int {
+                    width: 1,
+                    signed: true,
+                    big_endian: false
+                },
+                &data
+            ),
+            "-2"
+        );
+        assert_eq!(
+            decode(
+                Field::Int {
+                    width: 2,
+                    signed: true,
+                    big_endian: false
+                },
+                &data
+            ),
+            "-2"
+        );
+        assert_eq!(
+            decode(
+                Field::Int {
+                    width: 2,
+                    signed: false,
+                    big_endian: true
+                },
+                &data
+            ),
+            "65279"
+        );
+        assert_eq!(
+            decode(
+                Field::Float {
+                    width: 2,
+                    big_endian: true
+                },
+                &data[3..]
+            ),
+            "1"
+        );
+        assert_eq!(decode(Field::Binary, &data), "11111110");
+        assert_eq!(
+            decode(
+                Field::UnixTime { big_endian: true },
+                &[0x65, 0x92, 0x00, 0x80]
+            ),
+            "2024-01-01 00:00:00"
+        );
+        assert_eq!(
+            decode(Field::FileTime, &0x01DA_3C45_7689_C000u64.to_le_bytes()),
+            "2024-01-01 00:00:00"
+        );
+        assert_eq!(
+            decode(Field::DosDateTime, &[0x00, 0x60, 0x21, 0x58]),
+            "2024-01-01 12:00:00"
+        );
+        assert_eq!(
+            decode(Field::Uleb128, &[0xE5, 0x8E, 0x26]),
+            "624485 (3 bytes)"
+        );
+        assert_eq!(
+            decode(Field::Sleb128, &[0xC0, 0xBB, 0x78]),
+            "-123456 (3 bytes)"
+        );
+        assert_eq!(
+            decode(Field::Utf8, "あ".as_bytes()),
+            "U+3042 'あ' (3 bytes)"
+        );
+        assert_eq!(
+            decode(
+                Field::Utf16 { big_endian: false },
+                &[0x3D, 0xD8, 0x00, 0xDE]
+            ),
+            "U+1F600 '😀' (4 bytes)"
+        );
+        let guid = [
+            0x33, 0x22, 0x11, 0x00, 0x55, 0x44, 0x77, 0x66, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ];
+        assert_eq!(
+            decode(Field::Guid, &guid),
+            "{00112233-4455-6677-8899-AABBCCDDEEFF}"
+        );
+        assert!(
+            Field::Int {
+                width: 4,
+                signed: false,
+                big_endian: false
+            }
+            .decode(&data[..3])
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // 終端のない LEB128（長さの制約なし）
+        let data = [0xFFu8; READ_LEN];
+        for (field, text) in [
+            (Field::Int { width: 4, signed: true, big_endian: true }, "-2"),
+            (Field::Int { width: 8, signed: false, big_endian: false }, "18446744073709551615"),
+            (Field::Float { width: 2, big_endian: false }, "-0.333"),
+            (Field::Float { width: 4, big_endian: true }, "3.5"),
+            (Field::Octal, "377"),
+            (Field::UnixTime { big_endian: false }, "2038-01-19 03:14:07"),
+            (Field::FileTime, "1999-12-31 23:59:59"),
+            (Field::DosDateTime, "2024-02-29 13:45:10"),
+            (Field::Guid, "{00112233-4455-6677-8899-AABBCCDDEEFF}"),
+            (Field::Sleb128, "-123456"),
+            (Field::Utf16 { big_endian: true }, "U+1F600"),
+        ] {
+            let bytes = field.encode(text, &data).unwrap();
+            let decoded = decode(field, &bytes);
+            // f16 は丸めた値、LEB128・文字は長さ付きで表示される
+            assert!(
+                decoded.starts_with(text)
+                    || field
+                        == Field::Float {
+                            width: 2,
+                            big_endian: false
+                        },
+                "{text} {decoded}"
+            );
+        }
+        assert_eq!(
+            decode(
+                Field::Float {
+                    width: 2,
+                    big_endian: false
+                },
+                &f64_to_f16(-0.333).to_le_bytes()
+            ),
+            "-0.3330078"
+        );
+        assert!(
+            Field::Int {
+                width: 1,
+                signed: false,
+                big_endian: false
+            }
+            .encode("256", &data)
+            .is_err()
+        );
+        for field in [
+            Field::UnixTime { big_endian: false },
+            Field::FileTime,
+            Field::DosDateTime,
+        ] {
+            let err = field.encode("100000000000000-01-01", &data).unwrap_err();
+            assert!(err.to_string().starts_with("Expected YYYY-MM-DD"), "{err}");
+        }
+        // 今の LEB128 の長さに合わせて埋める
+        assert_eq!(
+            Field::Uleb128.encode("1", &[0xE5, 0x8E, 0x26]).unwrap(),
+            vec![0x81, 0x80, 0x00]
+        );
+        assert!(Field::Uleb128.encode("624485", &[0x01]).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::ops::Range;
+use std::time::Instant;
+
+use crate::buffer::{Document, Pattern};
+
+/// 一度に数える範囲（この単位で時間切れを確認する）
+const COUNT_STEP: usize = 1 << 20;
+
+/// 記録する一致の上限（これを超えたら数えるのをやめる）
+const MAX_MATCHES: usize = 1 << 20;
+
+/// 強調表示で、表示範囲の手前から探し始めるバイト数
+const VISIBLE_LOOKBEHIND: usize = 256;
+
+/// 検索中の一致の数え上げ（C-s の "match 3/17"）
+/// 大きなファイルでも入力が止まらないように、
+/// イベント処理の合間に先頭から少しずつ数える
+pub struct MatchCounter {
+    /// 数えているクエリ
+    pub query: String,
+    /// クエリを正規表現として扱うか
+    pub regex: bool,
+    /// 数え始めた時のドキュメントのリビジョン
+    pub revision: u64,
+    /// 検索パターン
+    pub pattern: Pattern,
+    /// 見つかった一致の先頭位置（昇順）
+    positions: Vec<usize>,
+    /// 次に探し始める位置
+    next: usize,
+    /// 数え終わったか
+    done: bool,
+}
+
+impl MatchCounter {
+    pub fn new(query: &str, regex: bool, revision: u64, pattern: Pattern) -> Self {
+        Self {
+            query: query.to_string(),
+            regex,
+            revision,
+            pattern,
+            positions: Vec::new(),
+            next: 0,
+            done: false,
+        }
+    }
+
+    /// 同じクエリ・同じ内容のドキュメントを数えているか
+    pub fn is_for(&self, query: &str, regex: bool, revision: u64) -> bool {
+        self.query == query && self.regex == regex && self.revision == revision
+    }
+
+    /// deadline まで数え進める
+    pub fn step(&mut self, doc: &Document, deadline: Instant) {
+        while !self.done {
+            let start = self.next;
+            let limit = (start + COUNT_STEP).min(doc.len());
+            let end = (limit + self.pattern.overlap()).min(doc.len());
+            let Some(data) = doc.get_range(start, end) else {
+                self.done = true;
+                return;
+            };
+            // 重ねて読んだ部分から始まる一致は次のチャンクで数える
+            for found in self.pattern.matches_in(&data).take_while(|r| start + r.start < limit) {
+                let found = start + found.start..start + found.end;
+                self.next = self.pattern.next_start(&found);
+                self.positions.push(found.start);
+                if self.positions.len() >= MAX_MATCHES {
+                    self.done = true;
+                    return;
+                }
+            }
+            self.next = self.next.max(limit);
+            self.done = self.next >= doc.len();
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+    }
+
+    /// 数え終わったか
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// ステータス表示（"match 3/17"、数えている途中は "match 3/17+"）
+    /// pos が数え終わった範囲の一致の先頭でなければ番号は "?"
+    pub fn status(&self, pos: usize) -> String {
+        let index = match self.positions.binary_search(&pos) {
+            Ok(i) => (i + 1).to_string(),
+            Err(_) => "?".to_string(),
+        };
+        let more = if self.done && self.positions.len() < MAX_MATCHES { "" } else { "+" };
+        format!("match {}/{}{}", index, self.positions.len(), more)
+    }
+
+    /// [start, end) に重なる一致の範囲（HEXビューの強調表示用）
+    pub fn visible(&self, doc: &Document, start: usize, end: usize) -> Vec<Range<usize>> {
+        // 表示範囲の手前から始まって表示範囲に入る一致も拾う
+        let from = start.saturating_sub(VISIBLE_LOOKBEHIND);
+        let to = (end + self.pattern.overlap().min(VISIBLE_LOOKBEHIND)).min(doc.len());
+        let Some(data) = doc.get_range(from, to) else {
+            return Vec::new();
+        };
+        self.pattern
+            .matches_in(&data)
+            .map(|r| from + r.start..from + r.end)
+            .take_while(|r| r.start < end)
+            .filter(|r| r.end > start)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Masked;
+
+    #[test]
+    fn test_count_across_steps() {
+        let mut data = vec![0u8; COUNT_STEP * 2 + 10];
+        for pos in [5, COUNT_STEP - 1, COUNT_STEP * 2 + 4] {
+            data[pos..pos + 2].copy_from_slice(&[0xAB, 0xCD]);
+        }
+        let doc = Document::from_bytes(data);
+        let pattern = Pattern::from(Masked::parse("AB C?").unwrap());
+        let mut counter = MatchCounter::new("AB C?", false, doc.revision(), pattern);
+        counter.step(&doc, Instant::now());
+        assert!(!counter.is_done());
+        assert_eq!(counter.status(5), "match 1/2+");
+        while !counter.is_done() {
+            counter.step(&doc, Instant::now());
+        }
+        assert_eq!(counter.status(COUNT_STEP - 1), "match 2/3");
+        assert_eq!(counter.status(6), "match ?/3");
+        assert_eq!(
+            counter.visible(&doc, 6, COUNT_STEP),
+            vec![5..7, COUNT_STEP - 1..COUNT_STEP + 1]
+        );
+    }
+}
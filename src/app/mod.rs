@@ -1,4 +1,11 @@
+mod buffers;
+mod compare;
+mod inspector;
+mod matches;
+mod occur;
 mod state;
+mod template;
+mod window;
 
 pub use state::App;
 
@@ -28,6 +35,8 @@ pub enum PrefixKey {
     None,
     /// C-x を押した状態
     CtrlX,
+    /// M-s を押した状態
+    MetaS,
 }
 
 /// アプリケーションアクション
@@ -82,12 +91,16 @@ pub enum Action {
     // 検索
     StartSearch,     // C-s: 検索モード開始
     StartSearchBack, // C-r: 後方検索モード開始
+    StartRegexSearch,     // C-M-s: 正規表現検索モード開始
+    StartRegexSearchBack, // C-M-r: 正規表現の後方検索モード開始
     Search(Vec<u8>),
     SearchNext,
     SearchPrev,
+    Occur,           // M-s o: 一致の一覧
 
     // 置換
     StartReplace,    // M-%: query-replace開始
+    StartRegexReplace, // C-M-%: query-replace-regexp開始
 
     // その他
     Undo,
@@ -95,6 +108,7 @@ pub enum Action {
 
     // プレフィックスキー
     EnterCtrlX,  // C-x を押した
+    EnterMetaS,  // M-s を押した
     Cancel,      // C-g でキャンセル
 
     // コマンド
@@ -105,6 +119,27 @@ pub enum Action {
     OpenFile,    // C-x C-f: ファイルを開く
     SaveAs,      // C-x C-w: 別名保存
     KillBuffer,  // C-x k: バッファを閉じる
+    SwitchBuffer, // C-x b: バッファを切り替える
+    ListBuffers, // C-x C-b: バッファ一覧
+
+    // ウィンドウ
+    SplitWindowBelow,   // C-x 2: 上下に分割
+    SplitWindowRight,   // C-x 3: 左右に分割
+    OtherWindow,        // C-x o: 次のウィンドウへ
+    DeleteWindow,       // C-x 0: このウィンドウを閉じる
+    DeleteOtherWindows, // C-x 1: 他のウィンドウを閉じる
+
+    // 編集箇所
+    NextChange, // C-x ]: 次の編集箇所へ
+    PrevChange, // C-x [: 前の編集箇所へ
+
+    // 比較
+    NextDifference, // M-n: 次の差分へ
+    PrevDifference, // M-p: 前の差分へ
+
+    // 表示設定
+    StartSetBytesPerRow, // C-x f: 1行のバイト数を入力
+    ToggleInspector,     // C-x i: データインスペクタの表示切り替え
 
     None,
 }
@@ -118,6 +153,27 @@ pub struct KeyMod {
 }
 
 impl Action {
+    /// バッファを編集・保存するアクションかどうか
+    /// 読み取り専用時に禁止する
+    pub fn is_edit(&self) -> bool {
+        matches!(
+            self,
+            Action::Save
+                | Action::SaveAs
+                | Action::InputHex(_)
+                | Action::InputAscii(_)
+                | Action::Delete
+                | Action::Backspace
+                | Action::Cut
+                | Action::Paste
+                | Action::PasteHex
+                | Action::StartReplace
+                | Action::StartRegexReplace
+                | Action::Undo
+                | Action::Redo
+        )
+    }
+
     /// キーコードからアクションに変換（Emacsキーバインド）
     pub fn from_key(key: KeyCode, mods: KeyMod) -> Self {
         let KeyMod { ctrl, shift, alt } = mods;
@@ -197,12 +253,24 @@ impl Action {
             (KeyCode::Char('s'), true, false, false) => Action::StartSearch,
             (KeyCode::Char('r'), true, false, false) => Action::StartSearchBack,
 
-            // 置換: M-% (query-replace)
+            // 正規表現検索: C-M-s (前方), C-M-r (後方)
+            (KeyCode::Char('s'), true, true, false) => Action::StartRegexSearch,
+            (KeyCode::Char('r'), true, true, false) => Action::StartRegexSearchBack,
+
+            // M-s: 検索プレフィックス（M-s o で一致の一覧）
+            (KeyCode::Char('s'), false, true, false) => Action::EnterMetaS,
+
+            // 置換: M-% (query-replace), C-M-% (query-replace-regexp)
             (KeyCode::Char('%'), false, true, _) => Action::StartReplace,
+            (KeyCode::Char('%'), true, true, _) => Action::StartRegexReplace,
 
             // コマンド: M-x
             (KeyCode::Char('x'), false, true, false) => Action::ExecuteCommand,
 
+            // 比較中の差分移動: M-n / M-p
+            (KeyCode::Char('n'), false, true, false) => Action::NextDifference,
+            (KeyCode::Char('p'), false, true, false) => Action::PrevDifference,
+
             // ジャンプ: M-g (goto-address)
             (KeyCode::Char('g'), false, true, false) => Action::StartGoto,
 
@@ -228,6 +296,26 @@ impl Action {
             (KeyCode::Char('w'), true) => Action::SaveAs,
             // C-x k: バッファを閉じる
             (KeyCode::Char('k'), false) => Action::KillBuffer,
+            // C-x b: バッファを切り替える
+            (KeyCode::Char('b'), false) => Action::SwitchBuffer,
+            // C-x C-b: バッファ一覧
+            (KeyCode::Char('b'), true) => Action::ListBuffers,
+            // C-x 2 / C-x 3: ウィンドウを上下・左右に分割
+            (KeyCode::Char('2'), false) => Action::SplitWindowBelow,
+            (KeyCode::Char('3'), false) => Action::SplitWindowRight,
+            // C-x o: 次のウィンドウへ
+            (KeyCode::Char('o'), false) => Action::OtherWindow,
+            // C-x 0 / C-x 1: このウィンドウ・他のウィンドウを閉じる
+            (KeyCode::Char('0'), false) => Action::DeleteWindow,
+            (KeyCode::Char('1'), false) => Action::DeleteOtherWindows,
+            // C-x f: 1行のバイト数を設定（Emacs の set-fill-column に相当）
+            (KeyCode::Char('f'), false) => Action::StartSetBytesPerRow,
+            // C-x i: データインスペクタ
+            (KeyCode::Char('i'), false) => Action::ToggleInspector,
+
+            // C-x ] / C-x [: 保存時点から編集した次・前の箇所へ
+            (KeyCode::Char(']'), false) => Action::NextChange,
+            (KeyCode::Char('['), false) => Action::PrevChange,
 
             // C-g: キャンセル
             (KeyCode::Char('g'), true) => Action::Cancel,
@@ -237,4 +325,14 @@ impl Action {
             _ => Action::Cancel,
         }
     }
+
+    /// M-s の後のキーを処理
+    pub fn from_key_after_meta_s(key: KeyCode, mods: KeyMod) -> Self {
+        match (key, mods.ctrl) {
+            // M-s o: 一致の一覧 (occur)
+            (KeyCode::Char('o'), false) => Action::Occur,
+            // その他は無効（C-g を含む）
+            _ => Action::Cancel,
+        }
+    }
 }
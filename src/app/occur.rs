@@ -0,0 +1,150 @@
+use std::ops::Range;
+
+use ratatui::{
+    style::Style,
+    text::{Line, Span},
+};
+
+use crate::buffer::Document;
+use crate::encoding::{decode_for_display, CharEncoding};
+use crate::ui::Colors;
+
+/// 一覧に並べる一致の上限
+pub const MAX_OCCURRENCES: usize = 10_000;
+
+/// 各行で一致の前後に表示するバイト数
+const CONTEXT: usize = 4;
+
+/// 各行に表示する一致のバイト数の上限（超えた分は省略）
+const MAX_SHOWN: usize = 16;
+
+/// M-s o / M-x occur の結果（一致の一覧を表示するペイン）
+pub struct Occur {
+    /// 検索したバッファの ID
+    pub buffer: u64,
+    /// 検索したクエリ
+    pub query: String,
+    /// 一致した範囲（先頭から順）
+    pub matches: Vec<Range<usize>>,
+    /// 上限で打ち切ったか
+    pub truncated: bool,
+    /// 選択中の行
+    pub selected: usize,
+    /// 表示している先頭の行
+    pub scroll: usize,
+    /// ペインに表示できる行数（ページ送りの単位）
+    pub rows: usize,
+    /// ペインにフォーカスがあるか
+    pub focused: bool,
+}
+
+impl Occur {
+    pub fn new(buffer: u64, query: String, matches: Vec<Range<usize>>, truncated: bool) -> Self {
+        Self {
+            buffer,
+            query,
+            matches,
+            truncated,
+            selected: 0,
+            scroll: 0,
+            rows: 1,
+            focused: true,
+        }
+    }
+
+    /// 選択中の一致
+    pub fn current(&self) -> Option<&Range<usize>> {
+        self.matches.get(self.selected)
+    }
+
+    /// 選択を delta 行動かす（端で止まる）
+    pub fn move_by(&mut self, delta: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// 表示できる行数を設定し、選択中の行が収まるようにスクロールする
+    pub fn set_rows(&mut self, rows: usize) {
+        self.rows = rows.max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.rows {
+            self.scroll = self.selected + 1 - self.rows;
+        }
+    }
+
+    /// 見出し行
+    pub fn header(&self, buffer_name: &str) -> String {
+        format!(
+            " {}{} matches for \"{}\" in {}",
+            self.matches.len(),
+            if self.truncated { "+" } else { "" },
+            self.query,
+            buffer_name
+        )
+    }
+
+    /// 一致の行（オフセット、前後を含むバイト列、デコードした文字列）
+    pub fn line(&self, index: usize, doc: &Document, encoding: CharEncoding) -> Line<'static> {
+        let found = &self.matches[index];
+        let start = found.start.saturating_sub(CONTEXT);
+        let shown_end = found.end.min(found.start + MAX_SHOWN);
+        let end = (shown_end + CONTEXT).min(doc.len());
+        let data = doc.get_range(start, end.max(start)).unwrap_or_default();
+
+        let mut spans = vec![Span::styled(
+            format!(" {:08X}  ", found.start),
+            Style::default().fg(Colors::ADDR),
+        )];
+        // 一致の前の空きを詰めずに揃える
+        spans.push(Span::raw("   ".repeat(CONTEXT - (found.start - start))));
+        for (i, byte) in data.iter().enumerate() {
+            let pos = start + i;
+            let style = if found.contains(&pos) {
+                Style::default().bg(Colors::MATCH_BG).fg(Colors::MATCH)
+            } else {
+                Style::default()
+            };
+            spans.push(Span::styled(format!("{:02X}", byte), style));
+            spans.push(Span::raw(
+                if pos + 1 == shown_end && shown_end < found.end {
+                    "…"
+                } else {
+                    " "
+                },
+            ));
+        }
+        let text: String = decode_for_display(&data, encoding)
+            .into_iter()
+            .flatten()
+            .map(|c| c.display)
+            .collect();
+        spans.push(Span::raw(format!(" |{}|", text)));
+        Line::from(spans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_and_scroll() {
+        let mut occur = Occur::new(
+            0,
+            "AB".to_string(),
+            (0..10).map(|i| i * 4..i * 4 + 2).collect(),
+            false,
+        );
+        occur.move_by(-1);
+        assert_eq!(occur.selected, 0);
+        occur.move_by(7);
+        occur.set_rows(3);
+        assert_eq!((occur.selected, occur.scroll), (7, 5));
+        occur.move_by(100);
+        assert_eq!(occur.current(), Some(&(36..38)));
+        occur.move_by(-9);
+        occur.set_rows(3);
+        assert_eq!(occur.scroll, 0);
+    }
+}
@@ -1,16 +1,47 @@
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
 use ratatui::{
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Style},
-    widgets::Paragraph,
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
     Frame,
 };
 
+use super::buffers::Buffer;
+use super::compare::Comparison;
+use super::inspector::{Inspector, FIELDS, READ_LEN};
+use super::matches::MatchCounter;
+use super::occur::{Occur, MAX_OCCURRENCES};
+use super::template::TemplateView;
+use super::window::{Window, WindowTree};
 use super::{Action, EditMode, InputState, KeyMod, PrefixKey};
 
+/// 自動保存ジャーナルを書き出す間隔
+const AUTO_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 1行のバイト数の上限
+const MAX_BYTES_PER_ROW: usize = 64;
+
+/// データインスペクタのペインの幅（項目名 + GUID が収まる幅）
+const INSPECTOR_WIDTH: u16 = 51;
+
+/// 構造体テンプレートのペインの幅
+const TEMPLATE_WIDTH: u16 = 60;
+
+/// ミニバッファに並べる補完候補の数
+const MAX_CANDIDATES: usize = 20;
+
+/// 検索中の一致を1回の定期処理で数える時間
+const MATCH_COUNT_BUDGET: Duration = Duration::from_millis(20);
+
+/// 1回のイベント処理・定期処理で比較中の差分を求める時間
+const COMPARE_BUDGET: Duration = Duration::from_millis(20);
+
 /// 置換モード状態
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum ReplaceMode {
@@ -35,10 +66,16 @@ pub enum PromptMode {
     OpenFile,
     /// ファイルパス入力中（別名保存）
     SaveAs,
+    /// バッファ名入力中（切り替え）
+    SwitchBuffer,
     /// コマンド入力中 (M-x)
     Command,
     /// コマンド引数入力中
     CommandArg,
+    /// 一致の一覧 (M-s o) の検索パターン入力中
+    Occur,
+    /// データインスペクタ (C-x i) の値の入力中
+    InspectorEdit,
 }
 
 /// 確認モード（未保存変更時）
@@ -46,26 +83,81 @@ pub enum PromptMode {
 pub enum ConfirmMode {
     #[default]
     Off,
-    /// 終了確認
+    /// 終了確認（変更のあるバッファがある時）
     Quit,
-    /// ファイルを開く確認（パスを保持）
-    OpenFile(String),
     /// バッファを閉じる確認
     KillBuffer,
+    /// ファイルが外部で変更された時の確認
+    FileChanged {
+        /// 保存しようとしていたかどうか
+        saving: bool,
+        /// (d)iff で求めた比較結果の表示
+        diff: Option<String>,
+    },
 }
-use crate::buffer::Document;
+use crate::buffer::{
+    compile_regex, expand_replacement, Alignment, BufferError, Comparer, Document, Masked, Numeric,
+    Pattern, PatternError, SaveStrategy,
+};
 use crate::clipboard::{self, HexFormat};
 use crate::encoding::{self, CharEncoding};
-use crate::ui::{HexView, ViewMode};
+use crate::format::{Executable, Target};
+use crate::template::Template;
+use crate::ui::{Colors, HexView, ViewMode};
+
+/// ウィンドウの後に並ぶペイン
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Occur,
+    Template,
+    Inspector,
+}
+
+/// C-x o でペインを巡る順
+const PANES: [Pane; 3] = [Pane::Occur, Pane::Template, Pane::Inspector];
+
+/// 名前の共通する先頭部分（候補がなければ None）
+fn common_prefix<'a>(names: &[&'a str]) -> Option<&'a str> {
+    let first = names.first()?;
+    let mut common = first.len();
+    for name in &names[1..] {
+        common = first
+            .bytes()
+            .zip(name.bytes())
+            .take(common)
+            .take_while(|(a, b)| a == b)
+            .count();
+    }
+    while !first.is_char_boundary(common) {
+        common -= 1;
+    }
+    Some(&first[..common])
+}
+
+/// 先頭の `~/` をホームディレクトリに展開する
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
 
 /// アプリケーション状態
 pub struct App {
-    /// 編集中のドキュメント
-    document: Document,
-    /// カーソル位置
-    cursor: usize,
-    /// 表示オフセット
-    offset: usize,
+    /// 表示中のバッファ
+    buf: Buffer,
+    /// 他のバッファ（最近表示した順）
+    buffers: Vec<Buffer>,
+    /// ウィンドウ（フォーカスのあるウィンドウの表示位置は buf が持つ）
+    windows: Vec<Window>,
+    /// ウィンドウの分割状態
+    window_tree: WindowTree,
+    /// フォーカスのあるウィンドウの ID
+    active_window: usize,
+    /// 次に作るウィンドウの ID
+    next_window_id: usize,
+    /// 2つのバッファを比較中の状態
+    comparison: Option<Comparison>,
     /// 1行あたりのバイト数
     bytes_per_row: usize,
     /// 表示可能な行数
@@ -78,12 +170,6 @@ pub struct App {
     input_state: InputState,
     /// プレフィックスキー状態（C-x等）
     prefix_key: PrefixKey,
-    /// 選択範囲
-    selection: Option<(usize, usize)>,
-    /// 選択開始位置
-    selection_start: Option<usize>,
-    /// 文字エンコーディング
-    encoding: CharEncoding,
     /// 終了フラグ
     should_quit: bool,
     /// ステータスメッセージ
@@ -96,6 +182,14 @@ pub struct App {
     last_search_query: String,
     /// 検索開始位置（検索キャンセル時に戻る位置）
     search_start_pos: usize,
+    /// 検索・置換のクエリを正規表現として扱う（C-M-s / C-M-%）
+    search_regex: bool,
+    /// 入力中の正規表現が不完全
+    search_invalid: bool,
+    /// 検索で ASCII の大文字・小文字を区別しない（検索中の M-c で切り替え）
+    search_fold_case: bool,
+    /// 検索中の一致の数え上げ（検索モードの間だけ持つ）
+    match_counter: Option<MatchCounter>,
     /// 置換モード
     replace_mode: ReplaceMode,
     /// 置換先パターン
@@ -106,45 +200,78 @@ pub struct App {
     prompt_input: String,
     /// 確認モード
     confirm_mode: ConfirmMode,
+    /// バッファ一覧 (C-x C-b) の選択行（表示中のみ Some）
+    buffer_menu: Option<usize>,
+    /// 一致の一覧 (M-s o)（ペインを表示している間だけ持つ）
+    occur: Option<Occur>,
+    /// データインスペクタ (C-x i)（ペインを表示している間だけ持つ）
+    inspector: Option<Inspector>,
+    /// 構造体テンプレート (M-x apply-template)（ペインを表示している間だけ持つ）
+    template: Option<TemplateView>,
     /// 実行中のコマンド名（引数入力用）
     current_command: String,
+    /// M-x goto-section / goto-symbol で選べる名前と、ファイル上の位置・長さ
+    name_targets: Vec<Target>,
+    /// 保存時にバックアップファイル（file~）を作るか
+    make_backup: bool,
+    /// 通常ファイルも直接書き込みモードで開くか
+    in_place: bool,
+    /// Undo 履歴をセッションをまたいで保存するか
+    persistent_undo: bool,
+    /// 読み取り専用モード（開くバッファをすべて読み取り専用にする）
+    readonly: bool,
 }
 
 impl App {
     /// 新しいアプリケーションを作成
     pub fn new() -> Self {
+        let buf = Buffer::new(Document::new());
         Self {
-            document: Document::new(),
-            cursor: 0,
-            offset: 0,
+            windows: vec![Window::new(0, &buf)],
+            window_tree: WindowTree::Leaf(0),
+            active_window: 0,
+            next_window_id: 1,
+            comparison: None,
+            buf,
+            buffers: Vec::new(),
             bytes_per_row: 16,
             visible_rows: 24,
             hex_mode: true,
             edit_mode: EditMode::Overwrite,
             input_state: InputState::Normal,
             prefix_key: PrefixKey::None,
-            selection: None,
-            selection_start: None,
-            encoding: CharEncoding::Utf8,
             should_quit: false,
             status_message: None,
             search_mode: false,
             search_query: String::new(),
             last_search_query: String::new(),
             search_start_pos: 0,
+            search_regex: false,
+            search_invalid: false,
+            search_fold_case: false,
+            match_counter: None,
             replace_mode: ReplaceMode::Off,
             replace_with: String::new(),
             prompt_mode: PromptMode::Off,
             prompt_input: String::new(),
             confirm_mode: ConfirmMode::Off,
+            buffer_menu: None,
+            occur: None,
+            inspector: None,
+            template: None,
             current_command: String::new(),
+            name_targets: Vec::new(),
+            make_backup: false,
+            in_place: false,
+            persistent_undo: false,
+            readonly: false,
         }
     }
 
     /// 全角英数記号（U+FF01〜U+FF5E）を半角（U+0021〜U+007E）に変換
     fn normalize_fullwidth(c: char) -> char {
         let cp = c as u32;
-        if cp >= 0xFF01 && cp <= 0xFF5E {
+        if (0xFF01..=0xFF5E).contains(&cp) {
             char::from_u32(cp - 0xFF00 + 0x20).unwrap_or(c)
         } else if c == '　' {
             ' ' // 全角スペース → 半角スペース
@@ -153,21 +280,436 @@ impl App {
         }
     }
 
+    /// 保存時にバックアップファイルを作るかを設定
+    pub fn set_make_backup(&mut self, make_backup: bool) {
+        self.make_backup = make_backup;
+        for buf in self.all_buffers_mut() {
+            buf.document.set_make_backup(make_backup);
+        }
+    }
+
+    /// 通常ファイルも直接書き込みモードで開くかを設定
+    /// 直接書き込みモードは inode を保つ部分書き込み
+    pub fn set_in_place(&mut self, in_place: bool) {
+        self.in_place = in_place;
+    }
+
+    /// 読み取り専用モードを設定
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+        for buf in self.all_buffers_mut() {
+            buf.document.set_readonly(readonly);
+        }
+    }
+
+    /// 1行のバイト数を設定（1〜MAX_BYTES_PER_ROW に丸める）
+    pub fn set_bytes_per_row(&mut self, bytes_per_row: usize) {
+        self.bytes_per_row = bytes_per_row.clamp(1, MAX_BYTES_PER_ROW);
+        // 表示開始位置を行の先頭に揃え直す
+        // 表示していないバッファは切り替え時に揃える
+        self.buf.offset -= self.buf.offset % self.bytes_per_row;
+        self.ensure_cursor_visible();
+    }
+
+    /// Undo 履歴をセッションをまたいで保存するかを設定
+    pub fn set_persistent_undo(&mut self, persistent_undo: bool) {
+        self.persistent_undo = persistent_undo;
+    }
+
     /// ファイルを開く
+    /// 新しいバッファで開き、既に開いていればそのバッファに切り替える
+    /// デバイスは自動的に直接書き込みモードになる
     pub fn open(&mut self, path: impl Into<PathBuf>) -> Result<()> {
-        self.document = Document::open(path)?;
-        self.cursor = 0;
-        self.offset = 0;
-        self.selection = None;
+        let path = path.into();
+        if let Some(index) = self.find_buffer_by_path(&path) {
+            self.switch_to_buffer(index);
+            self.status_message = Some(format!("Switched to buffer {}", self.buf.name));
+            return Ok(());
+        }
+        let mut document = Document::open(path)?;
+        if self.in_place {
+            document.set_save_strategy(SaveStrategy::InPlace)?;
+        }
+        self.push_buffer(document);
+        // 履歴ファイルが壊れていても開くこと自体は続ける
+        match self.buf.document.set_persistent_undo(self.persistent_undo) {
+            Ok(0) => {}
+            Ok(steps) => {
+                self.status_message = Some(format!("Restored {} undo steps", steps));
+            }
+            Err(e) => {
+                self.status_message = Some(format!("Undo history not restored: {}", e));
+            }
+        }
+        self.buf.recover_pending = self.buf.document.find_journal().is_some();
+        if self.buf.recover_pending {
+            self.status_message =
+                Some("Auto-save data found; M-x recover-file to restore it".to_string());
+        }
         Ok(())
     }
 
     /// バイト列から読み込み（標準入力用）
     pub fn load_bytes(&mut self, data: Vec<u8>) {
-        self.document = Document::from_bytes(data);
-        self.cursor = 0;
-        self.offset = 0;
-        self.selection = None;
+        self.push_buffer(Document::from_bytes(data));
+    }
+
+    /// 表示中のバッファと他のバッファすべて
+    fn all_buffers_mut(&mut self) -> impl Iterator<Item = &mut Buffer> {
+        std::iter::once(&mut self.buf).chain(self.buffers.iter_mut())
+    }
+
+    /// 表示中のバッファと他のバッファすべて
+    fn all_buffers(&self) -> impl Iterator<Item = &Buffer> {
+        std::iter::once(&self.buf).chain(self.buffers.iter())
+    }
+
+    /// 他のバッファと重ならないバッファ名を付ける（foo.bin, foo.bin<2>, ...）
+    fn unique_buffer_name(&self, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut n = 2;
+        while self.all_buffers().any(|b| b.name == name) {
+            name = format!("{}<{}>", base, n);
+            n += 1;
+        }
+        name
+    }
+
+    /// 同じファイルを開いている他のバッファを探す
+    fn find_buffer_by_path(&self, path: &Path) -> Option<usize> {
+        let target = path.canonicalize().ok()?;
+        self.buffers.iter().position(|b| {
+            b.document
+                .path()
+                .and_then(|p| p.canonicalize().ok())
+                .is_some_and(|p| p == target)
+        })
+    }
+
+    /// ドキュメントを新しいバッファにして表示する
+    /// 表示中のバッファが空の無名バッファなら置き換える
+    fn push_buffer(&mut self, mut document: Document) {
+        document.set_make_backup(self.make_backup);
+        document.set_readonly(self.readonly);
+        let blank = self.buf.is_blank();
+        if blank {
+            // 置き換える名前と衝突しないよう先に外しておく
+            self.buf.name.clear();
+        }
+        let mut buf = Buffer::new(document);
+        buf.name = self.unique_buffer_name(&buf.name);
+        let previous = self.show_buffer(buf);
+        if blank {
+            self.retarget_windows(previous.id);
+        } else {
+            self.buffers.insert(0, previous);
+        }
+    }
+
+    /// 他のバッファ（buffers の index 番目）に切り替える
+    fn switch_to_buffer(&mut self, index: usize) {
+        let buf = self.buffers.remove(index);
+        let previous = self.show_buffer(buf);
+        self.buffers.insert(0, previous);
+    }
+
+    /// バッファを表示し、それまで表示していたバッファを返す
+    fn show_buffer(&mut self, buf: Buffer) -> Buffer {
+        let previous = std::mem::replace(&mut self.buf, buf);
+        let (active, id) = (self.active_window, self.buf.id);
+        if let Some(window) = self.windows.iter_mut().find(|w| w.id == active) {
+            window.buffer = id;
+        }
+        self.input_state = InputState::Normal;
+        // 非表示中に 1行のバイト数が変わっていることがある
+        self.buf.offset -= self.buf.offset % self.bytes_per_row;
+        self.ensure_cursor_visible();
+        previous
+    }
+
+    /// なくなったバッファを表示していたウィンドウに、
+    /// 表示中のバッファを表示させる
+    fn retarget_windows(&mut self, gone: u64) {
+        for window in self.windows.iter_mut().filter(|w| w.buffer == gone) {
+            window.save(&self.buf);
+        }
+    }
+
+    /// フォーカスのあるウィンドウを分割する
+    /// 新しいウィンドウは同じバッファの同じ位置を表示
+    /// 戻り値: 新しいウィンドウの ID
+    fn split_window(&mut self, direction: Direction) -> usize {
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.window_tree.split(self.active_window, id, direction);
+        self.windows.push(Window::new(id, &self.buf));
+        id
+    }
+
+    /// ウィンドウにフォーカスを移す（表示しているバッファに切り替える）
+    fn select_window(&mut self, id: usize) {
+        if id == self.active_window {
+            return;
+        }
+        let active = self.active_window;
+        if let Some(window) = self.windows.iter_mut().find(|w| w.id == active) {
+            window.save(&self.buf);
+        }
+        let Some(target) = self.windows.iter().find(|w| w.id == id).cloned() else {
+            return;
+        };
+        self.active_window = id;
+        if let Some(index) = self.buffers.iter().position(|b| b.id == target.buffer) {
+            self.switch_to_buffer(index);
+        }
+        target.restore(&mut self.buf);
+        // 他のウィンドウでの編集で短くなっていることがある
+        self.buf.cursor = self.buf.cursor.min(self.buf.document.len());
+        self.buf.offset -= self.buf.offset % self.bytes_per_row;
+        self.ensure_cursor_visible();
+    }
+
+    /// C-x o: 次のウィンドウにフォーカスを移す
+    /// 一致の一覧などのペインを表示中は、
+    /// 最後のウィンドウの次にそのペインを挟む
+    fn other_window(&mut self) {
+        let ids = self.window_tree.ids();
+        let pos = ids.iter().position(|&id| id == self.active_window).unwrap_or(0);
+        if pos + 1 == ids.len() {
+            self.focus_next_pane(None);
+            return;
+        }
+        self.select_window(ids[pos + 1]);
+    }
+
+    /// after の次に並ぶ開いているペインにフォーカスを移す
+    /// なければ最初のウィンドウへ
+    /// フォーカスを外すペインの focused は呼び出し側で戻しておく
+    fn focus_next_pane(&mut self, after: Option<Pane>) {
+        let from = after.map_or(0, |after| {
+            PANES.iter().position(|&p| p == after).map_or(0, |i| i + 1)
+        });
+        for &pane in &PANES[from..] {
+            let focused = match pane {
+                Pane::Occur => self.occur.as_mut().map(|o| &mut o.focused),
+                Pane::Template => self.template.as_mut().map(|t| &mut t.focused),
+                Pane::Inspector => self.inspector.as_mut().map(|i| &mut i.focused),
+            };
+            if let Some(focused) = focused {
+                *focused = true;
+                return;
+            }
+        }
+        let first = self.window_tree.ids()[0];
+        self.select_window(first);
+    }
+
+    /// C-x 0: フォーカスのあるウィンドウを閉じる
+    fn delete_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.status_message = Some("Attempt to delete the sole window".to_string());
+            return;
+        }
+        let id = self.active_window;
+        self.other_window();
+        self.window_tree.remove(id);
+        self.windows.retain(|w| w.id != id);
+    }
+
+    /// C-x 1: フォーカスのあるウィンドウ以外を閉じる
+    fn delete_other_windows(&mut self) {
+        let active = self.active_window;
+        self.window_tree = WindowTree::Leaf(active);
+        self.windows.retain(|w| w.id == active);
+    }
+
+    /// 2つのファイルを開いて比較する (ehx --diff)
+    pub fn open_comparison(
+        &mut self,
+        a: impl Into<PathBuf>,
+        b: impl Into<PathBuf>,
+        alignment: Alignment,
+    ) -> Result<()> {
+        self.open(a)?;
+        let a_id = self.buf.id;
+        self.open(b)?;
+        let b_id = self.buf.id;
+        if let Some(index) = self.buffers.iter().position(|buf| buf.id == a_id) {
+            self.switch_to_buffer(index);
+        }
+        self.start_comparison(b_id, alignment);
+        Ok(())
+    }
+
+    /// M-x compare-buffers: 表示中のバッファと、
+    /// 隣のウィンドウ（なければ直前）のバッファを比較する
+    fn compare_buffers(&mut self, alignment: Alignment) {
+        let other = self
+            .windows
+            .iter()
+            .map(|w| w.buffer)
+            .find(|&id| id != self.buf.id)
+            .or_else(|| self.buffers.first().map(|buf| buf.id));
+        match other {
+            Some(other) => self.start_comparison(other, alignment),
+            None => self.status_message = Some("No other buffer to compare".to_string()),
+        }
+    }
+
+    /// 表示中のバッファ (A) と other (B) の比較を始める
+    /// 左右のウィンドウに並べる
+    fn start_comparison(&mut self, other: u64, alignment: Alignment) {
+        let Some((view, view_revision)) = self
+            .buffers
+            .iter()
+            .find(|buf| buf.id == other)
+            .map(|buf| (Window::new(0, buf), buf.document.revision()))
+        else {
+            self.status_message = Some("Cannot compare a buffer with itself".to_string());
+            return;
+        };
+        self.delete_other_windows();
+        let right = self.split_window(Direction::Horizontal);
+        if let Some(window) = self.windows.iter_mut().find(|w| w.id == right) {
+            *window = Window { id: right, ..view };
+        }
+        let revisions = [self.buf.document.revision(), view_revision];
+        self.comparison = Some(Comparison {
+            windows: [self.active_window, right],
+            buffers: [self.buf.id, other],
+            alignment,
+            diff: Comparer::new(alignment),
+            revisions,
+        });
+        self.sync_comparison();
+        let count = self.comparison.as_ref().map_or("0".to_string(), Comparison::count);
+        self.status_message = Some(match count.as_str() {
+            "0" => "No differences".to_string(),
+            n => format!("{} differences (M-n / M-p to move)", n),
+        });
+    }
+
+    /// 比較中なら差分を求め直し、
+    /// 反対側のウィンドウのカーソルとスクロールを合わせる
+    /// 比較していたウィンドウが閉じられたり、
+    /// 別のバッファを表示していたら比較をやめる
+    fn sync_comparison(&mut self) {
+        let Some(mut cmp) = self.comparison.take() else {
+            return;
+        };
+        let shown = |side: usize| {
+            let id = cmp.windows[side];
+            if id == self.active_window {
+                self.buf.id == cmp.buffers[side]
+            } else {
+                self.windows.iter().any(|w| w.id == id && w.buffer == cmp.buffers[side])
+            }
+        };
+        if !shown(0) || !shown(1) {
+            return;
+        }
+        let Some([a, b]) = self.compared_documents(&cmp) else {
+            return;
+        };
+        // 編集された所から求め直し、表示に必要な分だけ先に進める
+        // 残りは tick で進める
+        let revisions = [a.revision(), b.revision()];
+        if revisions != cmp.revisions {
+            cmp.diff.invalidate([
+                a.changes_since(cmp.revisions[0]),
+                b.changes_since(cmp.revisions[1]),
+            ]);
+            cmp.revisions = revisions;
+        }
+        cmp.diff.step(a, b, Instant::now() + COMPARE_BUDGET);
+
+        if let Some(side) = cmp.windows.iter().position(|&id| id == self.active_window) {
+            let pos = cmp.map(side, self.buf.cursor);
+            // 同じ画面上の行に表示する
+            let row = (self.buf.cursor / self.bytes_per_row)
+                .saturating_sub(self.buf.offset / self.bytes_per_row);
+            let offset = (pos / self.bytes_per_row).saturating_sub(row) * self.bytes_per_row;
+            let other = cmp.windows[1 - side];
+            if let Some(window) = self.windows.iter_mut().find(|w| w.id == other) {
+                window.cursor = pos;
+                window.offset = offset;
+                window.selection = None;
+                window.selection_start = None;
+            }
+        }
+        self.comparison = Some(cmp);
+    }
+
+    /// 比較している A・B のドキュメント
+    fn compared_documents(&self, cmp: &Comparison) -> Option<[&Document; 2]> {
+        let document = |id: u64| {
+            self.all_buffers()
+                .find(|buf| buf.id == id)
+                .map(|buf| &buf.document)
+        };
+        Some([document(cmp.buffers[0])?, document(cmp.buffers[1])?])
+    }
+
+    /// 比較中の差分を budget の間だけ求め進める
+    fn step_comparison(&mut self, budget: Duration) {
+        let Some(mut cmp) = self.comparison.take() else {
+            return;
+        };
+        if let Some([a, b]) = self.compared_documents(&cmp) {
+            cmp.diff.step(a, b, Instant::now() + budget);
+        }
+        self.comparison = Some(cmp);
+    }
+
+    /// C-x ] / C-x [: 保存時点から編集した次・前の箇所に移動
+    fn goto_change(&mut self, forward: bool) {
+        let ranges = self.buf.document.dirty_ranges();
+        let cursor = self.buf.cursor;
+        let found = if forward {
+            ranges.iter().position(|r| r.start > cursor)
+        } else {
+            ranges.iter().rposition(|r| r.start < cursor)
+        };
+        match found {
+            Some(index) => {
+                self.buf.cursor = ranges[index].start;
+                self.ensure_cursor_visible();
+                self.status_message = Some(format!("Change {}/{}", index + 1, ranges.len()));
+            }
+            None if ranges.is_empty() => {
+                self.status_message = Some("No changes since last save".to_string());
+            }
+            None => {
+                self.status_message = Some("No more changes".to_string());
+            }
+        }
+    }
+
+    /// M-n / M-p: 比較中の次・前の差分に移動
+    fn goto_difference(&mut self, forward: bool) {
+        let Some(cmp) = &self.comparison else {
+            self.status_message = Some("Not comparing buffers".to_string());
+            return;
+        };
+        let Some(side) = cmp.windows.iter().position(|&id| id == self.active_window) else {
+            self.status_message = Some("Not comparing buffers".to_string());
+            return;
+        };
+        let found = if forward {
+            cmp.next(side, self.buf.cursor)
+        } else {
+            cmp.prev(side, self.buf.cursor)
+        };
+        match found {
+            Some((index, pos)) => {
+                self.status_message = Some(format!("Difference {}/{}", index + 1, cmp.count()));
+                self.buf.cursor = pos.min(self.buf.document.len());
+                self.ensure_cursor_visible();
+            }
+            None => {
+                self.status_message = Some("No more differences".to_string());
+            }
+        }
     }
 
     /// 終了すべきかどうか
@@ -177,78 +719,79 @@ impl App {
 
     /// ファイル名を取得
     pub fn filename(&self) -> Option<&str> {
-        self.document.filename()
+        self.buf.document.filename()
     }
 
     /// 変更されているかどうか
     pub fn is_modified(&self) -> bool {
-        self.document.is_modified()
+        self.buf.document.is_modified()
     }
 
     /// 表示可能行数を設定
+    /// rows はフォーカスのあるウィンドウの HEXビューの高さ
     pub fn set_visible_rows(&mut self, rows: usize) {
-        self.visible_rows = rows.saturating_sub(1); // ステータスバー分
+        self.visible_rows = rows.saturating_sub(1).max(1); // ヘッダー行分
     }
 
     /// カーソルを上に移動
     fn cursor_up(&mut self) {
-        if self.cursor >= self.bytes_per_row {
-            self.cursor -= self.bytes_per_row;
+        if self.buf.cursor >= self.bytes_per_row {
+            self.buf.cursor -= self.bytes_per_row;
             self.ensure_cursor_visible();
         }
     }
 
     /// カーソルを下に移動
     fn cursor_down(&mut self) {
-        let new_pos = self.cursor + self.bytes_per_row;
-        if new_pos < self.document.len() {
-            self.cursor = new_pos;
+        let new_pos = self.buf.cursor + self.bytes_per_row;
+        if new_pos < self.buf.document.len() {
+            self.buf.cursor = new_pos;
             self.ensure_cursor_visible();
         }
     }
 
     /// カーソルを左に移動
     fn cursor_left(&mut self) {
-        if self.cursor > 0 {
-            self.cursor -= 1;
+        if self.buf.cursor > 0 {
+            self.buf.cursor -= 1;
             self.ensure_cursor_visible();
         }
     }
 
     /// カーソルを右に移動（EOF位置まで移動可能）
     fn cursor_right(&mut self) {
-        if self.cursor < self.document.len() {
-            self.cursor += 1;
+        if self.buf.cursor < self.buf.document.len() {
+            self.buf.cursor += 1;
             self.ensure_cursor_visible();
         }
     }
 
     /// カーソル位置が表示範囲内になるようにスクロール
     fn ensure_cursor_visible(&mut self) {
-        let cursor_row = self.cursor / self.bytes_per_row;
-        let offset_row = self.offset / self.bytes_per_row;
+        let cursor_row = self.buf.cursor / self.bytes_per_row;
+        let offset_row = self.buf.offset / self.bytes_per_row;
 
         if cursor_row < offset_row {
-            self.offset = cursor_row * self.bytes_per_row;
+            self.buf.offset = cursor_row * self.bytes_per_row;
         } else if cursor_row >= offset_row + self.visible_rows {
-            self.offset = (cursor_row - self.visible_rows + 1) * self.bytes_per_row;
+            self.buf.offset = (cursor_row - self.visible_rows + 1) * self.bytes_per_row;
         }
     }
 
     /// ページアップ
     fn page_up(&mut self) {
         let page_size = self.visible_rows * self.bytes_per_row;
-        self.cursor = self.cursor.saturating_sub(page_size);
-        self.offset = self.offset.saturating_sub(page_size);
+        self.buf.cursor = self.buf.cursor.saturating_sub(page_size);
+        self.buf.offset = self.buf.offset.saturating_sub(page_size);
     }
 
     /// ページダウン
     fn page_down(&mut self) {
         let page_size = self.visible_rows * self.bytes_per_row;
-        let max_pos = self.document.len(); // EOF位置まで移動可能
-        self.cursor = (self.cursor + page_size).min(max_pos);
-        self.offset = (self.offset + page_size).min(
-            (self.document.len() / self.bytes_per_row).saturating_sub(self.visible_rows)
+        let max_pos = self.buf.document.len(); // EOF位置まで移動可能
+        self.buf.cursor = (self.buf.cursor + page_size).min(max_pos);
+        self.buf.offset = (self.buf.offset + page_size).min(
+            (self.buf.document.len() / self.bytes_per_row).saturating_sub(self.visible_rows)
                 * self.bytes_per_row,
         );
         self.ensure_cursor_visible();
@@ -256,14 +799,35 @@ impl App {
 
     /// 行頭に移動
     fn cursor_home(&mut self) {
-        self.cursor = (self.cursor / self.bytes_per_row) * self.bytes_per_row;
+        self.buf.cursor = (self.buf.cursor / self.bytes_per_row) * self.bytes_per_row;
     }
 
     /// 行末に移動（EOF位置まで移動可能）
     fn cursor_end(&mut self) {
-        let row_start = (self.cursor / self.bytes_per_row) * self.bytes_per_row;
-        let row_end = (row_start + self.bytes_per_row).min(self.document.len());
-        self.cursor = row_end;
+        let row_start = (self.buf.cursor / self.bytes_per_row) * self.bytes_per_row;
+        let row_end = (row_start + self.bytes_per_row).min(self.buf.document.len());
+        self.buf.cursor = row_end;
+    }
+
+    /// 読み取り専用の場合はメッセージを出して true を返す
+    fn refuse_readonly(&mut self) -> bool {
+        if self.buf.document.is_readonly() {
+            self.status_message = Some(BufferError::ReadOnly.to_string());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 直接書き込みモードで長さが変わる編集ができなければ、
+    /// メッセージを出して true を返す
+    fn refuse_length_change(&mut self) -> bool {
+        if self.buf.document.save_strategy() == SaveStrategy::InPlace {
+            self.status_message = Some(BufferError::LengthChange.to_string());
+            true
+        } else {
+            false
+        }
     }
 
     /// HEX入力処理
@@ -281,22 +845,28 @@ impl App {
                 match self.edit_mode {
                     EditMode::Overwrite => {
                         // 上書きモード：既存バイトの下位ニブルは保持
-                        let low_nibble = if self.cursor < self.document.len() {
-                            self.document.get(self.cursor).unwrap_or(0) & 0x0F
+                        let low_nibble = if self.buf.cursor < self.buf.document.len() {
+                            self.buf.document.get(self.buf.cursor).unwrap_or(0) & 0x0F
                         } else {
                             0
                         };
                         let value = (digit << 4) | low_nibble;
-                        if self.cursor < self.document.len() {
-                            let _ = self.document.set(self.cursor, value);
+                        if self.buf.cursor < self.buf.document.len() {
+                            let _ = self.buf.document.set(self.buf.cursor, value);
                         } else {
-                            let _ = self.document.insert(self.cursor, value);
+                            if self.refuse_length_change() {
+                                return;
+                            }
+                            let _ = self.buf.document.insert(self.buf.cursor, value);
                         }
                     }
                     EditMode::Insert => {
                         // 挿入モード：新しいバイトを挿入
+                        if self.refuse_length_change() {
+                            return;
+                        }
                         let value = digit << 4;
-                        let _ = self.document.insert(self.cursor, value);
+                        let _ = self.buf.document.insert(self.buf.cursor, value);
                     }
                 }
                 self.input_state = InputState::HexFirstDigit(digit);
@@ -305,7 +875,7 @@ impl App {
                 // 2桁目：下位ニブルを更新して次へ
                 let value = (first << 4) | digit;
                 // 1桁目で既にバイトが存在するので上書き
-                let _ = self.document.set(self.cursor, value);
+                let _ = self.buf.document.set(self.buf.cursor, value);
                 self.cursor_right();
                 self.input_state = InputState::Normal;
             }
@@ -355,14 +925,14 @@ impl App {
     /// ASCII入力処理（文字をバッファのエンコーディングに変換して入力）
     fn input_ascii(&mut self, ch: char) {
         // 文字をバッファのエンコーディングに変換
-        let bytes = match encoding::encode_char(ch, self.encoding) {
+        let bytes = match encoding::encode_char(ch, self.buf.encoding) {
             Some(bytes) => bytes,
             None => {
                 // エンコードできない文字
                 self.status_message = Some(format!(
                     "Cannot encode '{}' in {}",
                     ch,
-                    self.encoding.name()
+                    self.buf.encoding.name()
                 ));
                 return;
             }
@@ -371,24 +941,33 @@ impl App {
         if bytes.is_empty() {
             return;
         }
+        let grows = self.edit_mode == EditMode::Insert
+            || self.buf.cursor + bytes.len() > self.buf.document.len();
+        if grows && self.refuse_length_change() {
+            return;
+        }
 
         match self.edit_mode {
             EditMode::Overwrite => {
                 // 上書きモード：各バイトを順番に上書き（EOFを超えた分は追加）
-                for (i, &byte) in bytes.iter().enumerate() {
-                    let pos = self.cursor + i;
-                    if pos < self.document.len() {
-                        let _ = self.document.set(pos, byte);
-                    } else {
-                        let _ = self.document.insert(pos, byte);
-                    }
-                }
+                let overlap = self
+                    .buf
+                    .document
+                    .len()
+                    .saturating_sub(self.buf.cursor)
+                    .min(bytes.len());
+                let _ = self
+                    .buf
+                    .document
+                    .set_range(self.buf.cursor, &bytes[..overlap]);
+                let _ = self
+                    .buf
+                    .document
+                    .insert_bytes(self.buf.cursor + overlap, &bytes[overlap..]);
             }
             EditMode::Insert => {
                 // 挿入モード：バイト列を挿入
-                for (i, &byte) in bytes.iter().enumerate() {
-                    let _ = self.document.insert(self.cursor + i, byte);
-                }
+                let _ = self.buf.document.insert_bytes(self.buf.cursor, &bytes);
             }
         }
 
@@ -400,33 +979,33 @@ impl App {
 
     /// 選択開始（マークを設定）
     fn start_selection(&mut self) {
-        self.selection_start = Some(self.cursor);
-        self.selection = Some((self.cursor, self.cursor));
+        self.buf.selection_start = Some(self.buf.cursor);
+        self.buf.selection = Some((self.buf.cursor, self.buf.cursor));
         self.status_message = Some("Mark set".to_string());
     }
 
     /// 選択解除
     fn clear_selection(&mut self) {
-        self.selection_start = None;
-        self.selection = None;
+        self.buf.selection_start = None;
+        self.buf.selection = None;
     }
 
     /// 選択範囲を更新（カーソル移動後に呼ぶ）
     fn update_selection(&mut self) {
-        if let Some(start) = self.selection_start {
-            let (sel_start, sel_end) = if start <= self.cursor {
-                (start, self.cursor)
+        if let Some(start) = self.buf.selection_start {
+            let (sel_start, sel_end) = if start <= self.buf.cursor {
+                (start, self.buf.cursor)
             } else {
-                (self.cursor, start)
+                (self.buf.cursor, start)
             };
-            self.selection = Some((sel_start, sel_end));
+            self.buf.selection = Some((sel_start, sel_end));
         }
     }
 
     /// 選択しながら上に移動
     fn select_up(&mut self) {
-        if self.selection_start.is_none() {
-            self.selection_start = Some(self.cursor);
+        if self.buf.selection_start.is_none() {
+            self.buf.selection_start = Some(self.buf.cursor);
         }
         self.cursor_up();
         self.update_selection();
@@ -434,8 +1013,8 @@ impl App {
 
     /// 選択しながら下に移動
     fn select_down(&mut self) {
-        if self.selection_start.is_none() {
-            self.selection_start = Some(self.cursor);
+        if self.buf.selection_start.is_none() {
+            self.buf.selection_start = Some(self.buf.cursor);
         }
         self.cursor_down();
         self.update_selection();
@@ -443,8 +1022,8 @@ impl App {
 
     /// 選択しながら左に移動
     fn select_left(&mut self) {
-        if self.selection_start.is_none() {
-            self.selection_start = Some(self.cursor);
+        if self.buf.selection_start.is_none() {
+            self.buf.selection_start = Some(self.buf.cursor);
         }
         self.cursor_left();
         self.update_selection();
@@ -452,8 +1031,8 @@ impl App {
 
     /// 選択しながら右に移動
     fn select_right(&mut self) {
-        if self.selection_start.is_none() {
-            self.selection_start = Some(self.cursor);
+        if self.buf.selection_start.is_none() {
+            self.buf.selection_start = Some(self.buf.cursor);
         }
         self.cursor_right();
         self.update_selection();
@@ -462,10 +1041,10 @@ impl App {
     /// 選択範囲をコピー (M-w)
     /// システムクリップボード + OSC 52 (ターミナルクリップボード)
     fn copy(&mut self) {
-        if let Some((start, end)) = self.selection {
-            if let Some(data) = self.document.get_range(start, end + 1) {
+        if let Some((start, end)) = self.buf.selection {
+            if let Some(data) = self.buf.document.get_range(start, end + 1) {
                 // 両方のクリップボードにコピー
-                let _ = clipboard::copy_hex_to_all(data, HexFormat::Spaced);
+                let _ = clipboard::copy_hex_to_all(&data, HexFormat::Spaced);
                 self.status_message = Some(format!("Copied {} bytes", end - start + 1));
                 self.clear_selection();
             }
@@ -476,14 +1055,14 @@ impl App {
 
     /// 選択範囲をHEX形式でコピー
     fn copy_hex(&mut self) {
-        if let Some((start, end)) = self.selection {
-            if let Some(data) = self.document.get_range(start, end + 1) {
+        if let Some((start, end)) = self.buf.selection {
+            if let Some(data) = self.buf.document.get_range(start, end + 1) {
                 // 両方のクリップボードにコピー
-                let _ = clipboard::copy_hex_to_all(data, HexFormat::Spaced);
+                let _ = clipboard::copy_hex_to_all(&data, HexFormat::Spaced);
                 self.status_message = Some("Copied as HEX".to_string());
                 self.clear_selection();
             }
-        } else if let Some(byte) = self.document.get(self.cursor) {
+        } else if let Some(byte) = self.buf.document.get(self.buf.cursor) {
             let _ = clipboard::copy_hex_to_all(&[byte], HexFormat::Spaced);
         }
     }
@@ -491,15 +1070,16 @@ impl App {
     /// 選択範囲をカット (C-w)
     /// システムクリップボード + OSC 52 (ターミナルクリップボード)
     fn cut(&mut self) {
-        if let Some((start, end)) = self.selection {
-            if let Some(data) = self.document.get_range(start, end + 1) {
+        if self.buf.selection.is_some() && self.refuse_length_change() {
+            return;
+        }
+        if let Some((start, end)) = self.buf.selection {
+            if let Some(data) = self.buf.document.get_range(start, end + 1) {
                 // 両方のクリップボードにコピー
-                let _ = clipboard::copy_hex_to_all(data, HexFormat::Spaced);
-                // 選択範囲を削除（末尾から削除）
-                for i in (start..=end).rev() {
-                    let _ = self.document.delete(i);
-                }
-                self.cursor = start;
+                let _ = clipboard::copy_hex_to_all(&data, HexFormat::Spaced);
+                // 選択範囲を削除
+                let _ = self.buf.document.delete_range(start, end + 1);
+                self.buf.cursor = start;
                 self.status_message = Some(format!("Cut {} bytes", end - start + 1));
                 self.clear_selection();
             }
@@ -524,6 +1104,9 @@ impl App {
     /// ターミナルからのペースト（Bracketed Paste）を処理
     /// ペーストされた内容をバイト列としてカーソル位置に挿入
     fn paste_from_terminal(&mut self, content: &str) {
+        if self.refuse_readonly() {
+            return;
+        }
         // HEX文字列かどうかを判定（全角文字も正規化して判定）
         let trimmed = content.trim();
         let bytes = if Self::looks_like_hex(trimmed) {
@@ -537,13 +1120,17 @@ impl App {
         if bytes.is_empty() {
             return;
         }
+        let grows = self.buf.selection.is_some()
+            || self.edit_mode == EditMode::Insert
+            || self.buf.cursor + bytes.len() > self.buf.document.len();
+        if grows && self.refuse_length_change() {
+            return;
+        }
 
         // 選択範囲があれば削除してから挿入
-        if let Some((start, end)) = self.selection {
-            for i in (start..=end).rev() {
-                let _ = self.document.delete(i);
-            }
-            self.cursor = start;
+        if let Some((start, end)) = self.buf.selection {
+            let _ = self.buf.document.delete_range(start, end + 1);
+            self.buf.cursor = start;
             self.clear_selection();
         }
 
@@ -551,62 +1138,175 @@ impl App {
         match self.edit_mode {
             EditMode::Overwrite => {
                 // 上書きモード：既存バイトを上書き、EOFを超えた分は追加
-                for (i, &byte) in bytes.iter().enumerate() {
-                    let pos = self.cursor + i;
-                    if pos < self.document.len() {
-                        let _ = self.document.set(pos, byte);
-                    } else {
-                        let _ = self.document.insert(pos, byte);
-                    }
-                }
+                let overlap = self
+                    .buf
+                    .document
+                    .len()
+                    .saturating_sub(self.buf.cursor)
+                    .min(bytes.len());
+                let _ = self
+                    .buf
+                    .document
+                    .set_range(self.buf.cursor, &bytes[..overlap]);
+                let _ = self
+                    .buf
+                    .document
+                    .insert_bytes(self.buf.cursor + overlap, &bytes[overlap..]);
             }
             EditMode::Insert => {
                 // 挿入モード：カーソル位置にバイト列を挿入
-                for (i, &byte) in bytes.iter().enumerate() {
-                    let _ = self.document.insert(self.cursor + i, byte);
-                }
+                let _ = self.buf.document.insert_bytes(self.buf.cursor, &bytes);
             }
         }
 
-        self.cursor += bytes.len();
+        self.buf.cursor += bytes.len();
         self.ensure_cursor_visible();
         self.status_message = Some(format!("Pasted {} bytes", bytes.len()));
     }
 
-    /// 検索クエリをバイト列に変換
-    fn search_query_to_bytes(&self) -> Vec<u8> {
-        let trimmed = self.search_query.trim();
-        if Self::looks_like_hex(trimmed) {
-            Self::normalized_hex_to_bytes(trimmed).unwrap_or_else(|| self.search_query.as_bytes().to_vec())
+    /// 検索クエリを検索パターンに変換
+    /// HEXなら `??` / `?` をワイルドカードとして扱う
+    fn search_query_to_pattern(&self) -> Result<Pattern, PatternError> {
+        self.query_to_pattern(&self.search_query)
+    }
+
+    /// クエリを検索パターンに変換
+    /// エンコーディング指定のテキスト・数値・HEX・テキストの順に解釈する
+    /// テキストはバッファのエンコーディングでバイト列にする
+    fn query_to_pattern(&self, query: &str) -> Result<Pattern, PatternError> {
+        if let Some((encoding, text)) = Self::encoded_text(query) {
+            return Self::text_pattern(text, encoding, self.search_fold_case).map(Pattern::from);
+        }
+        if let Some(numeric) = Numeric::parse(query) {
+            return numeric.map(Pattern::Numeric);
+        }
+        match Self::hex_pattern(query.trim()) {
+            Some(masked) => Ok(masked.into()),
+            None => Self::text_pattern(query, self.buf.encoding, self.search_fold_case)
+                .map(Pattern::from),
+        }
+    }
+
+    /// `u16le:"text"` のようにエンコーディングを指定したテキストなら、
+    /// そのエンコーディングと文字列
+    /// 入力途中でも探せるよう、閉じる `"` は省略できる
+    fn encoded_text(query: &str) -> Option<(CharEncoding, &str)> {
+        let (name, text) = query.trim_start().split_once(':')?;
+        let text = text.strip_prefix('"')?;
+        let text = text.strip_suffix('"').unwrap_or(text);
+        Some((CharEncoding::from_name(name)?, text))
+    }
+
+    /// テキストを encoding でエンコードした検索パターン
+    /// fold_case なら ASCII 英字の大文字・小文字の違い（0x20 のビット）を無視する
+    fn text_pattern(
+        text: &str,
+        encoding: CharEncoding,
+        fold_case: bool,
+    ) -> Result<Masked, PatternError> {
+        let unencodable = |c| PatternError::Unencodable(c, encoding.name());
+        // ISO-2022-JP はエスケープシーケンスで状態が変わるので、
+        // 文字ごとには変換できない
+        if encoding == CharEncoding::Iso2022Jp && !text.is_ascii() {
+            let bytes = encoding::encode_string(text, encoding);
+            return match text.chars().find(|&c| encoding::encode_char(c, encoding).is_none()) {
+                Some(c) => Err(unencodable(c)),
+                None => Ok(Masked::exact(bytes)),
+            };
+        }
+        let mut pattern = Masked::exact(Vec::new());
+        for c in text.chars() {
+            let bytes = encoding::encode_char(c, encoding).ok_or_else(|| unencodable(c))?;
+            for b in bytes {
+                // 英字そのもののバイト（UTF-16 なら下位バイト）だけを対象にする
+                let mask = if fold_case && c.is_ascii_alphabetic() && b == c as u8 {
+                    0xDF
+                } else {
+                    0xFF
+                };
+                pattern.bytes.push(b & mask);
+                pattern.mask.push(mask);
+            }
+        }
+        Ok(pattern)
+    }
+
+    /// 検索クエリを正規表現としてコンパイル
+    /// 大文字・小文字を区別しない時は `(?i)` を付ける
+    fn compile_search_regex(&self) -> Result<regex::bytes::Regex, regex::Error> {
+        if self.search_fold_case {
+            compile_regex(&format!("(?i){}", self.search_query))
+        } else {
+            compile_regex(&self.search_query)
+        }
+    }
+
+    /// 検索クエリを検索パターンに変換（空・不完全な正規表現なら None）
+    fn search_pattern(&mut self) -> Option<Pattern> {
+        self.search_invalid = false;
+        if self.search_query.is_empty() {
+            return None;
+        }
+        if !self.search_regex {
+            return match self.search_query_to_pattern() {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    self.search_invalid = true;
+                    self.status_message = Some(e.to_string());
+                    None
+                }
+            };
+        }
+        match self.compile_search_regex() {
+            Ok(re) => Some(Pattern::Regex(re)),
+            Err(_) => {
+                self.search_invalid = true;
+                self.status_message = Some(format!("Invalid regexp: {}", self.search_query));
+                None
+            }
+        }
+    }
+
+    /// pos から前方（forward）または後方に検索して一致範囲を返す
+    /// 折り返さない
+    fn find_pattern(&self, pattern: &Pattern, pos: usize, forward: bool) -> Option<Range<usize>> {
+        if forward {
+            pattern.find(&self.buf.document, pos)
         } else {
-            self.search_query.as_bytes().to_vec()
+            pattern.rfind(&self.buf.document, pos)
         }
     }
 
     /// 前方検索（現在位置から後ろへ）
     fn find_next(&mut self) {
-        let pattern = self.search_query_to_bytes();
-        if pattern.is_empty() {
+        let Some(pattern) = self.search_pattern() else {
             return;
-        }
+        };
 
-        let data = self.document.data();
-        let start = self.cursor + 1;
+        // 正規表現は現在位置の一致の途中から探し直さない
+        let cursor = self.buf.cursor;
+        let start = match pattern {
+            Pattern::Regex(_) => self
+                .find_pattern(&pattern, cursor, true)
+                .filter(|found| found.start == cursor)
+                .map_or(cursor + 1, |found| found.end),
+            Pattern::Bytes(_) | Pattern::Masked(_) | Pattern::Numeric(_) => cursor + 1,
+        };
 
         // 現在位置から末尾まで検索
-        if let Some(pos) = Self::find_pattern(data, &pattern, start) {
-            self.cursor = pos;
+        if let Some(found) = self.find_pattern(&pattern, start, true) {
+            self.buf.cursor = found.start;
             self.ensure_cursor_visible();
-            self.status_message = Some(format!("Found at {:08X}", pos));
+            self.status_message = Some(format!("Found at {:08X}", found.start));
             return;
         }
 
         // 先頭から現在位置まで検索（ラップアラウンド）
-        if let Some(pos) = Self::find_pattern(data, &pattern, 0) {
-            if pos < start {
-                self.cursor = pos;
+        if let Some(found) = self.find_pattern(&pattern, 0, true) {
+            if found.start < start {
+                self.buf.cursor = found.start;
                 self.ensure_cursor_visible();
-                self.status_message = Some(format!("Wrapped, found at {:08X}", pos));
+                self.status_message = Some(format!("Wrapped, found at {:08X}", found.start));
                 return;
             }
         }
@@ -616,28 +1316,26 @@ impl App {
 
     /// 後方検索（現在位置から前へ）
     fn find_prev(&mut self) {
-        let pattern = self.search_query_to_bytes();
-        if pattern.is_empty() {
+        let Some(pattern) = self.search_pattern() else {
             return;
-        }
+        };
 
-        let data = self.document.data();
-        let end = self.cursor;
+        let end = self.buf.cursor;
 
         // 現在位置から先頭まで検索
-        if let Some(pos) = Self::find_pattern_reverse(data, &pattern, end) {
-            self.cursor = pos;
+        if let Some(found) = self.find_pattern(&pattern, end, false) {
+            self.buf.cursor = found.start;
             self.ensure_cursor_visible();
-            self.status_message = Some(format!("Found at {:08X}", pos));
+            self.status_message = Some(format!("Found at {:08X}", found.start));
             return;
         }
 
         // 末尾から現在位置まで検索（ラップアラウンド）
-        if let Some(pos) = Self::find_pattern_reverse(data, &pattern, data.len()) {
-            if pos > end {
-                self.cursor = pos;
+        if let Some(found) = self.find_pattern(&pattern, self.buf.document.len(), false) {
+            if found.start > end {
+                self.buf.cursor = found.start;
                 self.ensure_cursor_visible();
-                self.status_message = Some(format!("Wrapped, found at {:08X}", pos));
+                self.status_message = Some(format!("Wrapped, found at {:08X}", found.start));
                 return;
             }
         }
@@ -645,26 +1343,6 @@ impl App {
         self.status_message = Some("Not found".to_string());
     }
 
-    /// パターンを前方検索
-    fn find_pattern(data: &[u8], pattern: &[u8], start: usize) -> Option<usize> {
-        if pattern.is_empty() || start + pattern.len() > data.len() {
-            return None;
-        }
-        data[start..].windows(pattern.len()).position(|w| w == pattern).map(|p| p + start)
-    }
-
-    /// パターンを後方検索
-    fn find_pattern_reverse(data: &[u8], pattern: &[u8], end: usize) -> Option<usize> {
-        if pattern.is_empty() || end == 0 {
-            return None;
-        }
-        let search_end = end.min(data.len());
-        if search_end < pattern.len() {
-            return None;
-        }
-        data[..search_end].windows(pattern.len()).rposition(|w| w == pattern)
-    }
-
     /// 文字列がHEX形式かどうかを判定（全角文字も考慮）
     fn looks_like_hex(s: &str) -> bool {
         if s.is_empty() {
@@ -679,12 +1357,57 @@ impl App {
             && normalized.chars().all(|c| c.is_ascii_hexdigit())
     }
 
+    /// HEXパターン（`??` / `?` のワイルドカード付き）として解釈できればその内容
+    fn hex_pattern(s: &str) -> Option<Masked> {
+        let normalized = Self::normalize_hex_query(s)?;
+        if normalized.len() < 2 || !normalized.chars().any(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Masked::parse(&normalized).ok()
+    }
+
+    /// HEX として書かれたクエリを、
+    /// 区切りと `0x` を除いた正規化済みのニブル列にする
+    /// 区切りごとに、先頭の `0x` を除いて
+    /// 16進数字と `?` だけからなる必要がある。
+    /// `?` を含む区切りは1バイト分（`??` / `4?`）の時だけ HEX とみなすので、
+    /// "what?" や "bad?" はテキストとして扱う（None）
+    fn normalize_hex_query(s: &str) -> Option<String> {
+        let mut normalized = String::new();
+        for token in s.split(Self::is_hex_separator) {
+            let mut chars: Vec<char> = token.chars().collect();
+            if chars.len() > 2
+                && Self::normalize_hex_char(chars[0]) == Some('0')
+                && matches!(chars[1], 'x' | 'X' | 'ｘ' | 'Ｘ')
+            {
+                chars.drain(..2);
+            }
+            let nibbles = chars
+                .iter()
+                .map(|&c| match c {
+                    '?' | '？' => Some('?'),
+                    _ => Self::normalize_hex_char(c),
+                })
+                .collect::<Option<String>>()?;
+            if nibbles.contains('?') && nibbles.len() != 2 {
+                return None;
+            }
+            normalized.push_str(&nibbles);
+        }
+        Some(normalized)
+    }
+
+    /// HEX文字列の区切り文字かどうか
+    fn is_hex_separator(c: char) -> bool {
+        matches!(c, ' ' | ',' | '{' | '}' | '\n' | '\r' | '\t')
+    }
+
     /// HEX文字列を正規化（全角→半角、小文字→大文字、区切り文字除去）
     fn normalize_hex_string(s: &str) -> String {
         s.chars()
             .filter_map(|c| {
                 // 区切り文字をスキップ
-                if c == ' ' || c == ',' || c == '{' || c == '}' || c == '\n' || c == '\r' || c == '\t' {
+                if Self::is_hex_separator(c) {
                     return None;
                 }
                 // 0x プレフィックスをスキップ
@@ -714,26 +1437,34 @@ impl App {
     }
 
     /// アクションを実行
+    /// アクション中の編集は1回のUndoで取り消せるようにまとめる
     pub fn execute(&mut self, action: Action) {
+        self.buf.document.begin_group();
+        self.execute_action(action);
+        self.buf.document.commit_group();
+        self.sync_comparison();
+    }
+
+    /// アクションを実行（本体）
+    fn execute_action(&mut self, action: Action) {
         // ステータスメッセージをクリア（一部のアクションを除く）
-        if !matches!(action, Action::EnterCtrlX) {
+        if !matches!(action, Action::EnterCtrlX | Action::EnterMetaS) {
             self.status_message = None;
         }
+        if action.is_edit() && self.refuse_readonly() {
+            return;
+        }
 
         match action {
             Action::Quit => {
-                if self.document.is_modified() {
+                if self.all_buffers().any(|b| b.document.is_modified()) {
                     self.confirm_mode = ConfirmMode::Quit;
                 } else {
                     self.should_quit = true;
                 }
             }
             Action::Save => {
-                if let Err(e) = self.document.save() {
-                    self.status_message = Some(format!("Save failed: {}", e));
-                } else {
-                    self.status_message = Some("Saved".to_string());
-                }
+                self.save_document();
             }
             // カーソル移動（選択開始中は選択範囲を更新）
             Action::CursorUp => {
@@ -769,12 +1500,12 @@ impl App {
                 self.update_selection();
             }
             Action::GotoBeginning => {
-                self.cursor = 0;
-                self.offset = 0;
+                self.buf.cursor = 0;
+                self.buf.offset = 0;
                 self.update_selection();
             }
             Action::GotoEnd => {
-                self.cursor = self.document.len(); // EOF位置
+                self.buf.cursor = self.buf.document.len(); // EOF位置
                 self.ensure_cursor_visible();
                 self.update_selection();
             }
@@ -799,8 +1530,8 @@ impl App {
                 };
             }
             Action::ToggleEncoding => {
-                self.encoding = self.encoding.next();
-                self.status_message = Some(format!("Encoding: {}", self.encoding.name()));
+                self.buf.encoding = self.buf.encoding.next();
+                self.status_message = Some(format!("Encoding: {}", self.buf.encoding.name()));
             }
             // 入力
             Action::InputHex(ch) => self.input_hex(ch),
@@ -810,6 +1541,10 @@ impl App {
                 self.prefix_key = PrefixKey::CtrlX;
                 self.status_message = Some("C-x-".to_string());
             }
+            Action::EnterMetaS => {
+                self.prefix_key = PrefixKey::MetaS;
+                self.status_message = Some("M-s-".to_string());
+            }
             Action::Cancel => {
                 self.prefix_key = PrefixKey::None;
                 self.input_state = InputState::Normal;
@@ -818,8 +1553,8 @@ impl App {
             }
             // Undo/Redo
             Action::Undo => {
-                if let Some(pos) = self.document.undo() {
-                    self.cursor = pos.min(self.document.len().saturating_sub(1));
+                if let Some(pos) = self.buf.document.undo() {
+                    self.buf.cursor = pos.min(self.buf.document.len().saturating_sub(1));
                     self.ensure_cursor_visible();
                     self.status_message = Some("Undo".to_string());
                 } else {
@@ -827,8 +1562,8 @@ impl App {
                 }
             }
             Action::Redo => {
-                if let Some(pos) = self.document.redo() {
-                    self.cursor = pos.min(self.document.len().saturating_sub(1));
+                if let Some(pos) = self.buf.document.redo() {
+                    self.buf.cursor = pos.min(self.buf.document.len().saturating_sub(1));
                     self.ensure_cursor_visible();
                     self.status_message = Some("Redo".to_string());
                 } else {
@@ -836,32 +1571,27 @@ impl App {
                 }
             }
             // 検索
-            Action::StartSearch => {
+            Action::StartSearch | Action::StartSearchBack => {
                 self.search_mode = true;
+                self.search_regex = false;
                 self.search_query.clear();
-                self.search_start_pos = self.cursor;
+                self.search_start_pos = self.buf.cursor;
             }
-            Action::StartSearchBack => {
+            Action::StartRegexSearch | Action::StartRegexSearchBack => {
                 self.search_mode = true;
+                self.search_regex = true;
                 self.search_query.clear();
-                self.search_start_pos = self.cursor;
-            }
-            Action::SearchNext => {
-                if !self.search_query.is_empty() {
-                    self.find_next();
-                }
-            }
-            Action::SearchPrev => {
-                if !self.search_query.is_empty() {
-                    self.find_prev();
-                }
+                self.search_start_pos = self.buf.cursor;
             }
+            Action::SearchNext if !self.search_query.is_empty() => self.find_next(),
+            Action::SearchPrev if !self.search_query.is_empty() => self.find_prev(),
             // 置換
-            Action::StartReplace => {
+            Action::StartReplace | Action::StartRegexReplace => {
                 self.replace_mode = ReplaceMode::EnteringSearch;
+                self.search_regex = action == Action::StartRegexReplace;
                 self.search_query.clear();
                 self.replace_with.clear();
-                self.search_start_pos = self.cursor;
+                self.search_start_pos = self.buf.cursor;
             }
             // ジャンプ
             Action::StartGoto => {
@@ -877,16 +1607,62 @@ impl App {
             Action::SaveAs => {
                 self.prompt_mode = PromptMode::SaveAs;
                 // 現在のファイル名をデフォルトに
-                self.prompt_input = self.document.filename().unwrap_or("").to_string();
+                self.prompt_input = self.buf.document.filename().unwrap_or("").to_string();
             }
             // バッファを閉じる
             Action::KillBuffer => {
-                if self.document.is_modified() {
+                if self.buf.document.is_modified() {
                     self.confirm_mode = ConfirmMode::KillBuffer;
                 } else {
                     self.do_kill_buffer();
                 }
             }
+            // 1行のバイト数
+            Action::StartSetBytesPerRow => {
+                self.current_command = "bytes-per-row".to_string();
+                self.prompt_mode = PromptMode::CommandArg;
+                self.prompt_input = self.bytes_per_row.to_string();
+            }
+            Action::SetBytesPerRow(bytes_per_row) => {
+                self.set_bytes_per_row(bytes_per_row);
+                self.status_message = Some(format!("Bytes per row: {}", self.bytes_per_row));
+            }
+            // ウィンドウ
+            Action::SplitWindowBelow => {
+                self.split_window(Direction::Vertical);
+            }
+            Action::SplitWindowRight => {
+                self.split_window(Direction::Horizontal);
+            }
+            Action::OtherWindow => self.other_window(),
+            Action::DeleteWindow => self.delete_window(),
+            Action::DeleteOtherWindows => self.delete_other_windows(),
+            // 編集箇所
+            Action::NextChange => self.goto_change(true),
+            Action::PrevChange => self.goto_change(false),
+            // 比較
+            Action::NextDifference => self.goto_difference(true),
+            Action::PrevDifference => self.goto_difference(false),
+            // バッファ切り替え・一覧
+            Action::SwitchBuffer => {
+                self.prompt_mode = PromptMode::SwitchBuffer;
+                self.prompt_input.clear();
+            }
+            Action::ListBuffers => {
+                self.buffer_menu = Some(0);
+            }
+            // 一致の一覧
+            Action::Occur => {
+                self.prompt_mode = PromptMode::Occur;
+                self.prompt_input.clear();
+            }
+            // データインスペクタ
+            Action::ToggleInspector => {
+                self.inspector = match self.inspector {
+                    Some(_) => None,
+                    None => Some(Inspector::new()),
+                };
+            }
             // コマンド実行 (M-x)
             Action::ExecuteCommand => {
                 self.prompt_mode = PromptMode::Command;
@@ -898,101 +1674,218 @@ impl App {
     }
 
     /// イベントを処理
+    /// 1イベント（置換の確定やM-xコマンドを含む）の編集は1回のUndoで取り消せる
     pub fn handle_event(&mut self) -> Result<()> {
-        if event::poll(std::time::Duration::from_millis(100))? {
-            match event::read()? {
-                // ペーストイベント（Bracketed Paste Mode）
-                Event::Paste(content) => {
-                    if self.search_mode {
-                        // 検索モード中はクエリに追加
-                        self.search_query.push_str(&content);
-                        self.do_incremental_search();
-                    } else {
-                        self.paste_from_terminal(&content);
-                    }
-                }
-                // キーイベント
-                Event::Key(key) => {
-                    if key.kind != KeyEventKind::Press {
-                        return Ok(());
-                    }
-
-                    // 検索モード中は特別な処理
-                    if self.search_mode {
-                        self.handle_search_key(key);
-                        return Ok(());
-                    }
-
-                    // 置換モード中は特別な処理
-                    if self.replace_mode != ReplaceMode::Off {
-                        self.handle_replace_key(key);
-                        return Ok(());
-                    }
+        // 一致を数えている途中は待たずに続きを数える
+        let counting = self.match_counter.as_ref().is_some_and(|c| !c.is_done())
+            || self.comparison.as_ref().is_some_and(|cmp| !cmp.diff.is_done());
+        let timeout = if counting { Duration::ZERO } else { Duration::from_millis(100) };
+        if event::poll(timeout)? {
+            let event = event::read()?;
+            self.buf.document.begin_group();
+            self.dispatch_event(event);
+            self.buf.document.commit_group();
+            self.sync_comparison();
+        }
+        self.tick();
+        Ok(())
+    }
 
-                    // プロンプトモード中は特別な処理
-                    if self.prompt_mode != PromptMode::Off {
-                        self.handle_prompt_key(key);
-                        return Ok(());
-                    }
+    /// イベント処理の合間に呼ばれる定期処理
+    /// 表示していないバッファも自動保存する
+    fn tick(&mut self) {
+        for buf in self.all_buffers() {
+            buf.document.check_mapping();
+        }
+        self.count_matches(MATCH_COUNT_BUDGET);
+        self.step_comparison(COMPARE_BUDGET);
+        let mut failed = None;
+        for buf in self.all_buffers_mut() {
+            if let Err(e) = buf.auto_save(AUTO_SAVE_INTERVAL) {
+                failed = Some(format!("Auto-save failed for {}: {}", buf.name, e));
+            }
+        }
+        if failed.is_some() {
+            self.status_message = failed;
+        }
+    }
 
-                    // 確認モード中は特別な処理
-                    if self.confirm_mode != ConfirmMode::Off {
-                        self.handle_confirm_key(key);
-                        return Ok(());
-                    }
+    /// 検索中の一致を budget の間だけ数え進める
+    /// クエリや内容が変わったら数え直す
+    fn count_matches(&mut self, budget: Duration) {
+        if !self.search_mode || self.search_query.is_empty() {
+            self.match_counter = None;
+            return;
+        }
+        let revision = self.buf.document.revision();
+        let current = self
+            .match_counter
+            .as_ref()
+            .is_some_and(|c| c.is_for(&self.search_query, self.search_regex, revision));
+        if !current {
+            let pattern = if self.search_regex {
+                self.compile_search_regex().ok().map(Pattern::Regex)
+            } else {
+                self.search_query_to_pattern().ok()
+            };
+            self.match_counter = pattern
+                .map(|p| MatchCounter::new(&self.search_query, self.search_regex, revision, p));
+        }
+        if let Some(counter) = self.match_counter.as_mut() {
+            counter.step(&self.buf.document, Instant::now() + budget);
+        }
+    }
 
-                    let mods = KeyMod {
-                        ctrl: key.modifiers.contains(KeyModifiers::CONTROL),
-                        shift: key.modifiers.contains(KeyModifiers::SHIFT),
-                        alt: key.modifiers.contains(KeyModifiers::ALT),
-                    };
-
-                    // プレフィックスキー状態に応じて処理を分岐
-                    let action = match self.prefix_key {
-                        PrefixKey::None => Action::from_key(key.code, mods),
-                        PrefixKey::CtrlX => {
-                            self.prefix_key = PrefixKey::None; // プレフィックス状態をリセット
-                            Action::from_key_after_ctrl_x(key.code, mods)
-                        }
-                    };
-
-                    if action != Action::None {
-                        self.execute(action);
-                    } else if let KeyCode::Char(ch) = key.code {
-                        // 修飾キーがなければ文字入力
-                        if !mods.ctrl && !mods.alt {
-                            if self.hex_mode {
-                                self.execute(Action::InputHex(ch));
-                            } else {
-                                self.execute(Action::InputAscii(ch));
-                            }
-                        }
-                    }
-                }
-                // フォーカスイベント
-                Event::FocusGained => {
-                    // フォーカス復帰時：将来的にファイルの外部変更チェックを行う
-                    self.status_message = Some("Focus gained".to_string());
-                }
-                Event::FocusLost => {
-                    // フォーカス喪失時：特に何もしない
+    /// M-x recover-file: 自動保存ジャーナルから編集内容を復元する
+    fn recover_file(&mut self) {
+        if self.buf.document.find_journal().is_none() {
+            self.status_message = Some("No auto-save data for this buffer".to_string());
+            return;
+        }
+        match self.buf.document.recover_journal() {
+            Ok(pos) => {
+                self.buf.recover_pending = false;
+                self.buf.selection = None;
+                self.buf.selection_start = None;
+                if let Some(pos) = pos {
+                    self.buf.cursor = pos.min(self.buf.document.len().saturating_sub(1));
+                    self.ensure_cursor_visible();
                 }
-                // その他のイベントは無視
-                _ => {}
+                self.status_message = Some("Recovered from auto-save data".to_string());
+            }
+            Err(e) => {
+                // 復元できないジャーナルは残さず、以後の編集を自動保存する
+                self.buf.recover_pending = false;
+                self.status_message = Some(format!("Recover failed: {}", e));
             }
         }
-        Ok(())
     }
 
-    /// 検索モード中のキー処理
-    fn handle_search_key(&mut self, key: crossterm::event::KeyEvent) {
-        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
-
-        match key.code {
+    /// イベントを振り分ける
+    fn dispatch_event(&mut self, event: Event) {
+        match event {
+            // ペーストイベント（Bracketed Paste Mode）
+            Event::Paste(content) => {
+                if self.search_mode {
+                    // 検索モード中はクエリに追加
+                    self.search_query.push_str(&content);
+                    self.do_incremental_search();
+                } else {
+                    self.paste_from_terminal(&content);
+                }
+            }
+            // キーイベント
+            Event::Key(key) => {
+                if key.kind != KeyEventKind::Press {
+                    return;
+                }
+
+                // バッファ一覧表示中は特別な処理
+                if self.buffer_menu.is_some() {
+                    self.handle_buffer_menu_key(key);
+                    return;
+                }
+
+                // 一致の一覧にフォーカスがある時は特別な処理
+                if self.occur.as_ref().is_some_and(|o| o.focused) {
+                    self.handle_occur_key(key);
+                    return;
+                }
+
+                // 構造体テンプレートにフォーカスがある時は特別な処理
+                if self.template.as_ref().is_some_and(|t| t.focused) {
+                    self.handle_template_key(key);
+                    return;
+                }
+
+                // データインスペクタにフォーカスがある時は特別な処理
+                // 値の入力中を除く
+                if self.inspector.as_ref().is_some_and(|i| i.focused)
+                    && self.prompt_mode == PromptMode::Off
+                {
+                    self.handle_inspector_key(key);
+                    return;
+                }
+
+                // 検索モード中は特別な処理
+                if self.search_mode {
+                    self.handle_search_key(key);
+                    return;
+                }
+
+                // 置換モード中は特別な処理
+                if self.replace_mode != ReplaceMode::Off {
+                    self.handle_replace_key(key);
+                    return;
+                }
+
+                // プロンプトモード中は特別な処理
+                if self.prompt_mode != PromptMode::Off {
+                    self.handle_prompt_key(key);
+                    return;
+                }
+
+                // 確認モード中は特別な処理
+                if self.confirm_mode != ConfirmMode::Off {
+                    self.handle_confirm_key(key);
+                    return;
+                }
+
+                let mods = KeyMod {
+                    ctrl: key.modifiers.contains(KeyModifiers::CONTROL),
+                    shift: key.modifiers.contains(KeyModifiers::SHIFT),
+                    alt: key.modifiers.contains(KeyModifiers::ALT),
+                };
+
+                // プレフィックスキー状態に応じて処理を分岐
+                let action = match self.prefix_key {
+                    PrefixKey::None => Action::from_key(key.code, mods),
+                    PrefixKey::CtrlX => {
+                        self.prefix_key = PrefixKey::None; // プレフィックス状態をリセット
+                        Action::from_key_after_ctrl_x(key.code, mods)
+                    }
+                    PrefixKey::MetaS => {
+                        self.prefix_key = PrefixKey::None;
+                        Action::from_key_after_meta_s(key.code, mods)
+                    }
+                };
+
+                if action != Action::None {
+                    self.execute(action);
+                } else if let KeyCode::Char(ch) = key.code {
+                    // 修飾キーがなければ文字入力
+                    if !mods.ctrl && !mods.alt {
+                        if self.hex_mode {
+                            self.execute(Action::InputHex(ch));
+                        } else {
+                            self.execute(Action::InputAscii(ch));
+                        }
+                    }
+                }
+            }
+            // フォーカス復帰時：ファイルが外部で変更されていないか確認
+            Event::FocusGained
+                if self.prompt_mode == PromptMode::Off && self.confirm_mode == ConfirmMode::Off =>
+            {
+                self.check_disk_changes();
+            }
+            Event::FocusLost => {
+                // フォーカス喪失時：特に何もしない
+            }
+            // その他のイベントは無視
+            _ => {}
+        }
+    }
+
+    /// 検索モード中のキー処理
+    fn handle_search_key(&mut self, key: crossterm::event::KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
             // Escape / C-g: 検索キャンセル
             KeyCode::Esc | KeyCode::Char('g') if ctrl => {
                 self.search_mode = false;
-                self.cursor = self.search_start_pos;
+                self.buf.cursor = self.search_start_pos;
                 self.ensure_cursor_visible();
                 self.status_message = Some("Cancelled".to_string());
             }
@@ -1023,11 +1916,23 @@ impl App {
                 }
                 self.find_prev();
             }
+            // M-r: 正規表現検索との切り替え
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::ALT) => {
+                self.search_regex = !self.search_regex;
+                self.do_incremental_search();
+            }
+            // M-c: 大文字・小文字の区別を切り替え
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::ALT) => {
+                self.search_fold_case = !self.search_fold_case;
+                self.match_counter = None;
+                self.do_incremental_search();
+            }
             // Backspace: 1文字削除
             KeyCode::Backspace => {
                 self.search_query.pop();
                 if self.search_query.is_empty() {
-                    self.cursor = self.search_start_pos;
+                    self.search_invalid = false;
+                    self.buf.cursor = self.search_start_pos;
                     self.ensure_cursor_visible();
                 } else {
                     self.do_incremental_search();
@@ -1044,19 +1949,17 @@ impl App {
 
     /// インクリメンタル検索を実行
     fn do_incremental_search(&mut self) {
-        let pattern = self.search_query_to_bytes();
-        if pattern.is_empty() {
+        let Some(pattern) = self.search_pattern() else {
             return;
-        }
+        };
 
-        let data = self.document.data();
         // 検索開始位置から検索
-        if let Some(pos) = Self::find_pattern(data, &pattern, self.search_start_pos) {
-            self.cursor = pos;
+        if let Some(found) = self.find_pattern(&pattern, self.search_start_pos, true) {
+            self.buf.cursor = found.start;
             self.ensure_cursor_visible();
-        } else if let Some(pos) = Self::find_pattern(data, &pattern, 0) {
+        } else if let Some(found) = self.find_pattern(&pattern, 0, true) {
             // ラップアラウンド
-            self.cursor = pos;
+            self.buf.cursor = found.start;
             self.ensure_cursor_visible();
         }
     }
@@ -1071,7 +1974,7 @@ impl App {
                     // Escape / C-g: キャンセル
                     KeyCode::Esc | KeyCode::Char('g') if ctrl => {
                         self.replace_mode = ReplaceMode::Off;
-                        self.cursor = self.search_start_pos;
+                        self.buf.cursor = self.search_start_pos;
                         self.ensure_cursor_visible();
                         self.status_message = Some("Cancelled".to_string());
                     }
@@ -1080,6 +1983,8 @@ impl App {
                         if self.search_query.is_empty() {
                             self.replace_mode = ReplaceMode::Off;
                             self.status_message = Some("Empty search pattern".to_string());
+                        } else if self.search_pattern().is_none() {
+                            self.replace_mode = ReplaceMode::Off;
                         } else {
                             self.replace_mode = ReplaceMode::EnteringReplace;
                         }
@@ -1100,7 +2005,7 @@ impl App {
                     // Escape / C-g: キャンセル
                     KeyCode::Esc | KeyCode::Char('g') if ctrl => {
                         self.replace_mode = ReplaceMode::Off;
-                        self.cursor = self.search_start_pos;
+                        self.buf.cursor = self.search_start_pos;
                         self.ensure_cursor_visible();
                         self.status_message = Some("Cancelled".to_string());
                     }
@@ -1128,8 +2033,11 @@ impl App {
                 match normalized {
                     // y: この箇所を置換して次へ
                     KeyCode::Char('y') | KeyCode::Char('Y') | KeyCode::Char(' ') => {
-                        self.do_replace_current();
-                        self.find_next_for_replace();
+                        if self.search_pattern().is_some_and(|p| self.do_replace_current(&p)) {
+                            self.find_next_for_replace();
+                        } else {
+                            self.replace_mode = ReplaceMode::Off;
+                        }
                     }
                     // n: スキップして次へ
                     KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Delete => {
@@ -1157,21 +2065,17 @@ impl App {
 
     /// 置換用の次のマッチを検索
     fn find_next_for_replace(&mut self) {
-        let pattern = self.search_query_to_bytes();
-        if pattern.is_empty() {
+        let Some(pattern) = self.search_pattern() else {
             self.replace_mode = ReplaceMode::Off;
             return;
-        }
-
-        let data = self.document.data();
-        let start = self.cursor;
+        };
 
-        if let Some(pos) = Self::find_pattern(data, &pattern, start) {
-            self.cursor = pos;
+        if let Some(found) = self.find_pattern(&pattern, self.buf.cursor, true) {
+            self.buf.cursor = found.start;
             self.ensure_cursor_visible();
             self.status_message = Some(format!(
                 "Replace? (y/n/!/q) at {:08X}",
-                pos
+                found.start
             ));
         } else {
             // 見つからなかった
@@ -1181,64 +2085,92 @@ impl App {
     }
 
     /// 現在位置を置換
-    fn do_replace_current(&mut self) {
-        let from_bytes = self.search_query_to_bytes();
-        let to_bytes = self.replace_with_to_bytes();
-
-        if from_bytes.is_empty() {
-            return;
-        }
-
+    /// 戻り値: 置換できたかどうか
+    fn do_replace_current(&mut self, pattern: &Pattern) -> bool {
         // 現在位置が検索パターンとマッチするか確認
-        if let Some(data) = self.document.get_range(self.cursor, self.cursor + from_bytes.len()) {
-            if data == from_bytes {
-                // 削除（末尾から）
-                for i in (0..from_bytes.len()).rev() {
-                    let _ = self.document.delete(self.cursor + i);
-                }
-                // 挿入
-                for (i, &byte) in to_bytes.iter().enumerate() {
-                    let _ = self.document.insert(self.cursor + i, byte);
+        let cursor = self.buf.cursor;
+        let Some(found) = self
+            .find_pattern(pattern, cursor, true)
+            .filter(|found| found.start == cursor)
+        else {
+            return false;
+        };
+        let Some(matched) = self.buf.document.get_range(found.start, found.end) else {
+            return false;
+        };
+        let to_bytes = match pattern {
+            Pattern::Bytes(_) | Pattern::Masked(_) | Pattern::Numeric(_) => {
+                match self.replace_with_to_pattern() {
+                    Ok(to) => match to.apply(&matched) {
+                        Ok(bytes) => bytes,
+                        Err(e) => {
+                            self.status_message = Some(e.to_string());
+                            return false;
+                        }
+                    },
+                    Err(e) => {
+                        self.status_message = Some(e.to_string());
+                        return false;
+                    }
                 }
-                // カーソルを置換後の末尾に移動
-                self.cursor += to_bytes.len();
             }
+            Pattern::Regex(re) => {
+                // 一致した範囲だけを対象にキャプチャを取り直して参照を展開
+                let Some(caps) = re.captures(&matched) else {
+                    return false;
+                };
+                expand_replacement(&self.replace_with, &caps)
+            }
+        };
+        if found.len() == to_bytes.len() {
+            // 同じ長さなら上書き
+            let _ = self.buf.document.set_range(cursor, &to_bytes);
+        } else {
+            if self.refuse_length_change() {
+                return false;
+            }
+            // 削除して挿入
+            let _ = self.buf.document.delete_range(found.start, found.end);
+            let _ = self.buf.document.insert_bytes(cursor, &to_bytes);
         }
+        // カーソルを置換後の末尾に移動
+        self.buf.cursor += to_bytes.len();
+        true
     }
 
     /// 残り全てを置換
     fn do_replace_all_remaining(&mut self) {
         let mut count = 0;
-        loop {
-            let from_bytes = self.search_query_to_bytes();
-            if from_bytes.is_empty() {
-                break;
-            }
-
-            let data = self.document.data();
-            let start = self.cursor;
-
-            if let Some(pos) = Self::find_pattern(data, &from_bytes, start) {
-                self.cursor = pos;
-                self.do_replace_current();
+        if let Some(pattern) = self.search_pattern() {
+            while let Some(found) = self.find_pattern(&pattern, self.buf.cursor, true) {
+                self.buf.cursor = found.start;
+                if !self.do_replace_current(&pattern) {
+                    break;
+                }
                 count += 1;
-            } else {
-                break;
             }
         }
 
         self.replace_mode = ReplaceMode::Off;
-        self.status_message = Some(format!("Replaced {} occurrences", count));
+        if count > 0 || self.status_message.is_none() {
+            self.status_message = Some(format!("Replaced {} occurrences", count));
+        }
     }
 
-    /// 置換パターンをバイト列に変換
-    fn replace_with_to_bytes(&self) -> Vec<u8> {
-        let trimmed = self.replace_with.trim();
-        if Self::looks_like_hex(trimmed) {
-            Self::normalized_hex_to_bytes(trimmed).unwrap_or_else(|| self.replace_with.as_bytes().to_vec())
-        } else {
-            self.replace_with.as_bytes().to_vec()
+    /// 置換パターンを変換
+    /// HEXのワイルドカードの部分は一致したバイトの値を残す
+    /// テキストは検索クエリと同じエンコーディングにする
+    /// 指定がなければバッファのエンコーディング
+    fn replace_with_to_pattern(&self) -> Result<Masked, PatternError> {
+        if let Some((encoding, text)) = Self::encoded_text(&self.replace_with) {
+            return Self::text_pattern(text, encoding, false);
+        }
+        if let Some(masked) = Self::hex_pattern(self.replace_with.trim()) {
+            return Ok(masked);
         }
+        let encoding = Self::encoded_text(&self.search_query)
+            .map_or(self.buf.encoding, |(encoding, _)| encoding);
+        Self::text_pattern(&self.replace_with, encoding, false)
     }
 
     /// プロンプトモード中のキー処理
@@ -1249,12 +2181,23 @@ impl App {
             // Escape / C-g: キャンセル
             KeyCode::Esc | KeyCode::Char('g') if ctrl => {
                 self.prompt_mode = PromptMode::Off;
+                self.name_targets.clear();
                 self.status_message = Some("Cancelled".to_string());
             }
             // Enter: 確定
             KeyCode::Enter => {
                 self.execute_prompt();
             }
+            // Tab: バッファ名を補完
+            KeyCode::Tab if self.prompt_mode == PromptMode::SwitchBuffer => {
+                self.complete_buffer_name();
+            }
+            // Tab: セクション・シンボル名を補完
+            KeyCode::Tab
+                if self.prompt_mode == PromptMode::CommandArg && !self.name_targets.is_empty() =>
+            {
+                self.complete_target_name();
+            }
             // Backspace
             KeyCode::Backspace => {
                 self.prompt_input.pop();
@@ -1267,6 +2210,460 @@ impl App {
         }
     }
 
+    /// C-x b の補完候補（他のバッファのうち入力で始まる名前）
+    fn buffer_candidates(&self, prefix: &str) -> Vec<&str> {
+        self.buffers
+            .iter()
+            .map(|b| b.name.as_str())
+            .filter(|name| name.starts_with(prefix))
+            .collect()
+    }
+
+    /// C-x b の入力を候補に共通する部分まで補完
+    fn complete_buffer_name(&mut self) {
+        if let Some(common) = common_prefix(&self.buffer_candidates(&self.prompt_input)) {
+            self.prompt_input = common.to_string();
+        }
+    }
+
+    /// M-x goto-section / goto-symbol の補完候補（入力で始まる名前）
+    fn name_candidates(&self, prefix: &str) -> Vec<&str> {
+        self.name_targets
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| name.starts_with(prefix))
+            .collect()
+    }
+
+    /// M-x goto-section / goto-symbol の入力を候補に共通する部分まで補完
+    fn complete_target_name(&mut self) {
+        if let Some(common) = common_prefix(&self.name_candidates(&self.prompt_input)) {
+            self.prompt_input = common.to_string();
+        }
+    }
+
+    /// M-x goto-section / goto-symbol: ELF・PE のセクション・シンボルの名前を聞く
+    fn start_goto_name(&mut self, command: &str) {
+        let Some(executable) = self.buf.executable().cloned() else {
+            self.status_message = Some("Not an ELF or PE file".to_string());
+            return;
+        };
+        let mut targets = if command == "goto-section" {
+            executable.sections()
+        } else {
+            match executable.symbols(&self.buf.document) {
+                Ok(symbols) => symbols,
+                Err(e) => {
+                    self.status_message = Some(e.to_string());
+                    return;
+                }
+            }
+        };
+        // .symtab と .dynsym の両方にある名前は1つにする
+        targets.sort_by(|a, b| (&a.0, a.1.start).cmp(&(&b.0, b.1.start)));
+        targets.dedup_by(|a, b| a.0 == b.0);
+        if targets.is_empty() {
+            self.status_message = Some(format!(
+                "No {}s",
+                if command == "goto-section" {
+                    "section"
+                } else {
+                    "symbol"
+                }
+            ));
+            return;
+        }
+        self.name_targets = targets;
+        self.current_command = command.to_string();
+        self.prompt_mode = PromptMode::CommandArg;
+        self.prompt_input.clear();
+    }
+
+    /// 名前で指定したセクション・シンボルの先頭に移動する
+    /// 完全一致か、前方一致が1つだけの時
+    fn goto_name(&mut self, kind: &str, name: &str) {
+        let targets = std::mem::take(&mut self.name_targets);
+        let name = name.trim();
+        let target = targets.iter().find(|(n, _)| n == name).or_else(|| {
+            let mut matches = targets.iter().filter(|(n, _)| n.starts_with(name));
+            match (matches.next(), matches.next()) {
+                (Some(target), None) => Some(target),
+                _ => None,
+            }
+        });
+        let Some((name, range)) = target else {
+            self.status_message = Some(format!("No {} named {}", kind.to_lowercase(), name));
+            return;
+        };
+        if range.start > self.buf.document.len() as u64 {
+            self.status_message = Some(format!("{} {} is past the end of the file", kind, name));
+            return;
+        }
+        self.buf.cursor = range.start as usize;
+        self.ensure_cursor_visible();
+        let len = range.end - range.start;
+        self.status_message = Some(if kind == "Section" {
+            format!("Section {}: {:08X}-{:08X} ({} bytes)", name, range.start, range.end, len)
+        } else {
+            format!("Symbol {} at {:08X} ({} bytes)", name, range.start, len)
+        });
+    }
+
+    /// C-x b: 名前で指定したバッファに切り替える（空入力なら直前のバッファ）
+    fn switch_buffer_by_name(&mut self, name: &str) {
+        if name.is_empty() {
+            if self.buffers.is_empty() {
+                self.status_message = Some("No other buffer".to_string());
+            } else {
+                self.switch_to_buffer(0);
+            }
+            return;
+        }
+        if name == self.buf.name {
+            return;
+        }
+        // 完全一致を優先し、なければ前方一致が1つだけの時に切り替える
+        let index = self.buffers.iter().position(|b| b.name == name).or_else(|| {
+            let mut matches = self
+                .buffers
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.name.starts_with(name));
+            match (matches.next(), matches.next()) {
+                (Some((i, _)), None) => Some(i),
+                _ => None,
+            }
+        });
+        match index {
+            Some(index) => self.switch_to_buffer(index),
+            None => self.status_message = Some(format!("No buffer named {}", name)),
+        }
+    }
+
+    /// バッファ一覧 (C-x C-b) 表示中のキー処理
+    fn handle_buffer_menu_key(&mut self, key: crossterm::event::KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let Some(row) = self.buffer_menu else {
+            return;
+        };
+        // 0 行目が表示中のバッファ、以降は buffers の順
+        let last = self.buffers.len();
+
+        match key.code {
+            KeyCode::Down | KeyCode::Char('n') => self.buffer_menu = Some((row + 1).min(last)),
+            KeyCode::Up | KeyCode::Char('p') => self.buffer_menu = Some(row.saturating_sub(1)),
+            // Enter / f / Space: 選択したバッファに切り替える
+            KeyCode::Enter | KeyCode::Char('f') | KeyCode::Char(' ') if !ctrl => {
+                self.buffer_menu = None;
+                if row > 0 {
+                    self.switch_to_buffer(row - 1);
+                }
+            }
+            // q / Escape / C-g: 閉じる
+            KeyCode::Char('g') if ctrl => self.buffer_menu = None,
+            KeyCode::Char('q') | KeyCode::Esc => self.buffer_menu = None,
+            _ => {}
+        }
+    }
+
+    /// M-s o: バッファ全体から一致をすべて探し、一覧のペインを開く
+    /// 空入力なら前回の検索クエリを使う
+    fn occur(&mut self, input: &str) {
+        let query = if input.is_empty() {
+            self.last_search_query.clone()
+        } else {
+            input.to_string()
+        };
+        if query.is_empty() {
+            self.status_message = Some("No search pattern".to_string());
+            return;
+        }
+        let pattern = match self.query_to_pattern(&query) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                self.status_message = Some(e.to_string());
+                return;
+            }
+        };
+        let mut matches = Vec::new();
+        let mut pos = 0;
+        let mut truncated = false;
+        while let Some(found) = self.find_pattern(&pattern, pos, true) {
+            pos = pattern.next_start(&found);
+            matches.push(found);
+            if matches.len() >= MAX_OCCURRENCES {
+                truncated = pattern.find(&self.buf.document, pos).is_some();
+                break;
+            }
+        }
+        if matches.is_empty() {
+            self.status_message = Some(format!("No matches for {}", query));
+            return;
+        }
+        self.status_message = Some(format!(
+            "{}{} matches (Enter to jump, q to close)",
+            matches.len(),
+            if truncated { "+" } else { "" }
+        ));
+        self.last_search_query = query.clone();
+        self.occur = Some(Occur::new(self.buf.id, query, matches, truncated));
+    }
+
+    /// 一致の一覧で選択中の一致にカーソルを移す
+    /// 一覧を作ったバッファに切り替える
+    fn goto_occurrence(&mut self) {
+        let Some((buffer, index, found)) = self
+            .occur
+            .as_ref()
+            .and_then(|o| Some((o.buffer, o.selected, o.current()?.clone())))
+        else {
+            return;
+        };
+        if buffer != self.buf.id {
+            match self.buffers.iter().position(|b| b.id == buffer) {
+                Some(index) => self.switch_to_buffer(index),
+                None => return,
+            }
+        }
+        self.clear_selection();
+        self.buf.cursor = found.start.min(self.buf.document.len());
+        self.ensure_cursor_visible();
+        let total = self.occur.as_ref().map_or(0, |o| o.matches.len());
+        self.status_message = Some(format!("Match {}/{} at {:08X}", index + 1, total, found.start));
+    }
+
+    /// 一致の一覧 (M-s o) にフォーカスがある時のキー処理
+    fn handle_occur_key(&mut self, key: crossterm::event::KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let Some(occur) = self.occur.as_mut() else {
+            return;
+        };
+        let page = occur.rows as isize;
+
+        match key.code {
+            KeyCode::Down | KeyCode::Char('n') if !alt => occur.move_by(1),
+            KeyCode::Up | KeyCode::Char('p') if !alt => occur.move_by(-1),
+            KeyCode::PageDown => occur.move_by(page),
+            KeyCode::PageUp => occur.move_by(-page),
+            KeyCode::Char('v') if ctrl => occur.move_by(page),
+            KeyCode::Char('v') if alt => occur.move_by(-page),
+            KeyCode::Char('<') if alt => occur.selected = 0,
+            KeyCode::Char('>') if alt => occur.move_by(isize::MAX),
+            // Enter: 一致へ移動してHEXビューに戻る
+            KeyCode::Enter => {
+                occur.focused = false;
+                self.goto_occurrence();
+            }
+            // Space / C-o: 一致を表示して一覧に留まる
+            KeyCode::Char(' ') if !ctrl => self.goto_occurrence(),
+            KeyCode::Char('o') if ctrl => self.goto_occurrence(),
+            // o / C-x o: 次のペインか、HEXビュー（最初のウィンドウ）へ
+            KeyCode::Char('o') => {
+                occur.focused = false;
+                self.focus_next_pane(Some(Pane::Occur));
+            }
+            KeyCode::Char('x') if ctrl => self.status_message = Some("C-x-".to_string()),
+            // q / Escape / C-g: 一覧を閉じる
+            KeyCode::Char('g') if ctrl => self.occur = None,
+            KeyCode::Char('q') | KeyCode::Esc => self.occur = None,
+            _ => {}
+        }
+    }
+
+    /// apply-template コマンド: テンプレートファイルをカーソル位置に当てはめる
+    fn apply_template(&mut self, path: &str) {
+        let path = path.trim();
+        if path.is_empty() {
+            self.status_message = Some("No file specified".to_string());
+            return;
+        }
+        let expanded = expand_home(path);
+        let source = match std::fs::read_to_string(&expanded) {
+            Ok(source) => source,
+            Err(e) => {
+                self.status_message = Some(format!("Failed to read template: {}", e));
+                return;
+            }
+        };
+        let name = expanded
+            .file_name()
+            .map_or(path.to_string(), |n| n.to_string_lossy().into_owned());
+        let view = Template::parse(&source).and_then(|template| {
+            let root = template.root_name().to_string();
+            let view = TemplateView::new(
+                name.clone(),
+                template,
+                self.buf.id,
+                &self.buf.document,
+                self.buf.cursor,
+            )?;
+            Ok((root, view))
+        });
+        match view {
+            Ok((root, view)) => {
+                self.status_message = Some(format!("Applied {} at {:08X}", root, view.start));
+                self.template = Some(view);
+            }
+            Err(e) => self.status_message = Some(format!("{}: {}", name, e)),
+        }
+    }
+
+    /// テンプレートのペインで選択中のフィールドのバイト列を選択する
+    /// 当てはめたバッファに切り替える
+    fn select_template_field(&mut self) {
+        let Some((buffer, name, range)) = self
+            .template
+            .as_ref()
+            .and_then(|t| t.current().map(|node| (t.buffer, node.name.clone(), node.range.clone())))
+        else {
+            return;
+        };
+        if buffer != self.buf.id {
+            match self.buffers.iter().position(|b| b.id == buffer) {
+                Some(index) => self.switch_to_buffer(index),
+                None => return,
+            }
+        }
+        self.clear_selection();
+        self.buf.cursor = range.start.min(self.buf.document.len());
+        if !range.is_empty() {
+            self.buf.selection_start = Some(range.end - 1);
+            self.buf.selection = Some((range.start, range.end - 1));
+        }
+        self.ensure_cursor_visible();
+        if let Some(template) = self.template.as_mut() {
+            template.mark_followed(self.buf.cursor);
+        }
+        self.status_message = Some(format!(
+            "{}: {:08X}-{:08X} ({} bytes)",
+            name,
+            range.start,
+            range.end,
+            range.len()
+        ));
+    }
+
+    /// 構造体テンプレートのペインにフォーカスがある時のキー処理
+    fn handle_template_key(&mut self, key: crossterm::event::KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let Some(template) = self.template.as_mut() else {
+            return;
+        };
+        let page = template.rows as isize;
+
+        match key.code {
+            KeyCode::Down | KeyCode::Char('n') if !alt => template.move_by(1),
+            KeyCode::Up | KeyCode::Char('p') if !alt => template.move_by(-1),
+            KeyCode::PageDown => template.move_by(page),
+            KeyCode::PageUp => template.move_by(-page),
+            KeyCode::Char('v') if ctrl => template.move_by(page),
+            KeyCode::Char('v') if alt => template.move_by(-page),
+            KeyCode::Char('<') if alt => template.selected = 0,
+            KeyCode::Char('>') if alt => template.move_by(isize::MAX),
+            // Tab: 折りたたみの切り替え、Right / Left: 展開・折りたたみ
+            KeyCode::Tab => template.set_expanded(None),
+            KeyCode::Right => template.set_expanded(Some(true)),
+            KeyCode::Left => template.set_expanded(Some(false)),
+            // Enter: フィールドを選択してHEXビューに戻る
+            KeyCode::Enter => {
+                template.focused = false;
+                self.select_template_field();
+                let first = self.window_tree.ids()[0];
+                self.select_window(first);
+            }
+            // Space / C-o: フィールドを選択してペインに留まる
+            KeyCode::Char(' ') if !ctrl => self.select_template_field(),
+            KeyCode::Char('o') if ctrl => self.select_template_field(),
+            // o / C-x o: 次のペインか、HEXビュー（最初のウィンドウ）へ
+            KeyCode::Char('o') => {
+                template.focused = false;
+                self.focus_next_pane(Some(Pane::Template));
+            }
+            KeyCode::Char('x') if ctrl => self.status_message = Some("C-x-".to_string()),
+            // q / Escape / C-g: ペインを閉じる
+            KeyCode::Char('g') if ctrl => self.template = None,
+            KeyCode::Char('q') | KeyCode::Esc => self.template = None,
+            _ => {}
+        }
+    }
+
+    /// カーソル位置からデータインスペクタで読むバイト列
+    fn inspector_bytes(&self) -> Vec<u8> {
+        let end = (self.buf.cursor + READ_LEN).min(self.buf.document.len());
+        self.buf
+            .document
+            .get_range(self.buf.cursor, end.max(self.buf.cursor))
+            .map(|data| data.into_owned())
+            .unwrap_or_default()
+    }
+
+    /// データインスペクタ (C-x i) にフォーカスがある時のキー処理
+    fn handle_inspector_key(&mut self, key: crossterm::event::KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let Some(inspector) = self.inspector.as_mut() else {
+            return;
+        };
+
+        match key.code {
+            KeyCode::Down | KeyCode::Char('n') if !alt => inspector.move_by(1),
+            KeyCode::Up | KeyCode::Char('p') if !alt => inspector.move_by(-1),
+            KeyCode::Char('<') if alt => inspector.selected = 0,
+            KeyCode::Char('>') if alt => inspector.move_by(isize::MAX),
+            // Enter / e: 選択中の型で値を入力して書き込む
+            KeyCode::Enter | KeyCode::Char('e') if !ctrl && !alt => {
+                let field = inspector.field();
+                if self.refuse_readonly() {
+                    return;
+                }
+                self.prompt_input = field.edit_text(&self.inspector_bytes()).unwrap_or_default();
+                self.prompt_mode = PromptMode::InspectorEdit;
+            }
+            // o / C-x o: HEXビューに戻る（最初のウィンドウへ）
+            KeyCode::Char('o') if !ctrl => {
+                inspector.focused = false;
+                self.focus_next_pane(Some(Pane::Inspector));
+            }
+            KeyCode::Char('x') if ctrl => self.status_message = Some("C-x-".to_string()),
+            // q / Escape / C-g: インスペクタを閉じる
+            KeyCode::Char('g') if ctrl => self.inspector = None,
+            KeyCode::Char('q') | KeyCode::Esc => self.inspector = None,
+            _ => {}
+        }
+    }
+
+    /// データインスペクタで選択中の型として入力した値を
+    /// カーソル位置に書き込む
+    fn inspector_write(&mut self, input: &str) {
+        if self.refuse_readonly() {
+            return;
+        }
+        let Some(field) = self.inspector.as_ref().map(Inspector::field) else {
+            return;
+        };
+        let bytes = match field.encode(input, &self.inspector_bytes()) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.status_message = Some(e.to_string());
+                return;
+            }
+        };
+        if self.buf.cursor + bytes.len() > self.buf.document.len() {
+            self.status_message = Some(format!(
+                "{} needs {} bytes at the cursor",
+                field.label(),
+                bytes.len()
+            ));
+            return;
+        }
+        self.status_message = Some(match self.buf.document.set_range(self.buf.cursor, &bytes) {
+            Ok(()) => format!("Wrote {} as {}", input.trim(), field.label()),
+            Err(e) => e.to_string(),
+        });
+    }
+
     /// プロンプト入力を実行
     fn execute_prompt(&mut self) {
         let input = self.prompt_input.clone();
@@ -1278,22 +2675,26 @@ impl App {
                 self.goto_address(&input);
             }
             PromptMode::OpenFile => {
-                // 未保存の変更があれば確認
-                if self.document.is_modified() {
-                    self.confirm_mode = ConfirmMode::OpenFile(input);
-                } else {
-                    self.open_file(&input);
-                }
+                self.open_file(&input);
             }
             PromptMode::SaveAs => {
                 self.save_as(&input);
             }
+            PromptMode::SwitchBuffer => {
+                self.switch_buffer_by_name(&input);
+            }
             PromptMode::Command => {
                 self.dispatch_command(&input);
             }
             PromptMode::CommandArg => {
                 self.execute_command_with_arg(&input);
             }
+            PromptMode::Occur => {
+                self.occur(&input);
+            }
+            PromptMode::InspectorEdit => {
+                self.inspector_write(&input);
+            }
             PromptMode::Off => {}
         }
     }
@@ -1301,6 +2702,13 @@ impl App {
     /// コマンドをディスパッチ
     fn dispatch_command(&mut self, cmd: &str) {
         let cmd = cmd.trim().to_lowercase();
+        let edits = matches!(
+            cmd.as_str(),
+            "save" | "s" | "recover-file" | "fill" | "f" | "insert" | "i"
+        );
+        if edits && self.refuse_readonly() {
+            return;
+        }
         match cmd.as_str() {
             // 引数不要なコマンド
             "goto" | "g" => {
@@ -1308,18 +2716,53 @@ impl App {
                 self.prompt_input.clear();
             }
             "save" | "s" => {
-                if let Err(e) = self.document.save() {
-                    self.status_message = Some(format!("Save failed: {}", e));
-                } else {
-                    self.status_message = Some("Saved".to_string());
-                }
+                self.save_document();
             }
             "quit" | "q" => {
                 self.execute(Action::Quit);
             }
+            "recover-file" => {
+                self.recover_file();
+            }
+            "next-change" => {
+                self.goto_change(true);
+            }
+            "previous-change" => {
+                self.goto_change(false);
+            }
+            "compare-buffers" => {
+                self.compare_buffers(Alignment::Fixed);
+            }
+            "compare-buffers-aligned" => {
+                self.compare_buffers(Alignment::Insertions);
+            }
+            "query-replace-regexp" => {
+                self.execute(Action::StartRegexReplace);
+            }
+            "occur" => {
+                self.execute(Action::Occur);
+            }
+            "inspector" => {
+                self.execute(Action::ToggleInspector);
+            }
+            "apply-template" => {
+                self.current_command = "apply-template".to_string();
+                self.prompt_mode = PromptMode::CommandArg;
+                self.prompt_input.clear();
+            }
+            "goto-section" | "goto-symbol" => {
+                self.start_goto_name(&cmd);
+            }
+            "toggle-case-fold-search" => {
+                self.search_fold_case = !self.search_fold_case;
+                self.status_message = Some(format!(
+                    "Case-insensitive search {}",
+                    if self.search_fold_case { "enabled" } else { "disabled" }
+                ));
+            }
             // 引数が必要なコマンド
             "fill" | "f" => {
-                if self.selection.is_none() {
+                if self.buf.selection.is_none() {
                     self.status_message = Some("No selection".to_string());
                 } else {
                     self.current_command = "fill".to_string();
@@ -1332,9 +2775,16 @@ impl App {
                 self.prompt_mode = PromptMode::CommandArg;
                 self.prompt_input.clear();
             }
+            "bytes-per-row" | "w" => {
+                self.execute(Action::StartSetBytesPerRow);
+            }
             "help" | "?" | "h" => {
                 self.status_message = Some(
-                    "Commands: fill(f) insert(i) goto(g) save(s) quit(q) help(?)".to_string()
+                    "Commands: fill(f) insert(i) goto(g) bytes-per-row(w) save(s) recover-file \
+                     next-change previous-change compare-buffers[-aligned] query-replace-regexp \
+                     occur inspector apply-template goto-section goto-symbol \
+                     toggle-case-fold-search quit(q) help(?)"
+                        .to_string(),
                 );
             }
             "" => {
@@ -1358,6 +2808,19 @@ impl App {
             "insert" => {
                 self.cmd_insert(arg);
             }
+            "apply-template" => {
+                self.apply_template(arg);
+            }
+            "goto-section" => {
+                self.goto_name("Section", arg);
+            }
+            "goto-symbol" => {
+                self.goto_name("Symbol", arg);
+            }
+            "bytes-per-row" => match arg.trim().parse() {
+                Ok(n) if n > 0 => self.execute_action(Action::SetBytesPerRow(n)),
+                _ => self.status_message = Some("Invalid row width".to_string()),
+            },
             _ => {
                 self.status_message = Some(format!("Unknown command: {}", cmd));
             }
@@ -1382,17 +2845,14 @@ impl App {
             return;
         };
 
-        let Some((start, end)) = self.selection else {
+        let Some((start, end)) = self.buf.selection else {
             self.status_message = Some("No selection".to_string());
             return;
         };
 
         // 選択範囲を埋める
-        for i in start..=end {
-            if i < self.document.len() {
-                let _ = self.document.set(i, byte);
-            }
-        }
+        let fill_end = (end + 1).min(self.buf.document.len());
+        let _ = self.buf.document.set_range(start, &vec![byte; fill_end.saturating_sub(start)]);
 
         let count = end - start + 1;
         self.status_message = Some(format!("Filled {} bytes with {:02X}", count, byte));
@@ -1402,7 +2862,7 @@ impl App {
     /// insert コマンド: 指定サイズのバイトを挿入
     fn cmd_insert(&mut self, arg: &str) {
         // フォーマット: "count byte" or "count" (デフォルト 00)
-        let parts: Vec<&str> = arg.trim().split_whitespace().collect();
+        let parts: Vec<&str> = arg.split_whitespace().collect();
 
         let (count, byte) = match parts.len() {
             1 => {
@@ -1435,11 +2895,13 @@ impl App {
             return;
         }
 
-        // カーソル位置に挿入
-        for i in 0..count {
-            let _ = self.document.insert(self.cursor + i, byte);
+        if self.refuse_length_change() {
+            return;
         }
 
+        // カーソル位置に挿入
+        let _ = self.buf.document.insert_bytes(self.buf.cursor, &vec![byte; count]);
+
         self.status_message = Some(format!("Inserted {} bytes of {:02X}", count, byte));
     }
 
@@ -1471,6 +2933,17 @@ impl App {
             return;
         }
 
+        // va: を付けた16進数は仮想アドレス、
+        // rva: は PE のイメージ先頭からの相対アドレス
+        if let Some(vaddr) = input.strip_prefix("va:") {
+            self.goto_vaddr(vaddr.trim(), false);
+            return;
+        }
+        if let Some(rva) = input.strip_prefix("rva:") {
+            self.goto_vaddr(rva.trim(), true);
+            return;
+        }
+
         // 0x プレフィックスまたは h サフィックスで16進数
         let addr = if input.starts_with("0x") || input.starts_with("0X") {
             usize::from_str_radix(&input[2..], 16)
@@ -1486,15 +2959,15 @@ impl App {
 
         match addr {
             Ok(addr) => {
-                if addr <= self.document.len() {
-                    self.cursor = addr;
+                if addr <= self.buf.document.len() {
+                    self.buf.cursor = addr;
                     self.ensure_cursor_visible();
                     self.status_message = Some(format!("Jumped to {:08X}", addr));
                 } else {
                     self.status_message = Some(format!(
                         "Address {:X} exceeds file size {:X}",
                         addr,
-                        self.document.len()
+                        self.buf.document.len()
                     ));
                 }
             }
@@ -1504,6 +2977,66 @@ impl App {
         }
     }
 
+    /// ELF・PE の仮想アドレス（va:）または PE の RVA（rva:）に対応する
+    /// ファイルオフセットに移動する
+    fn goto_vaddr(&mut self, input: &str, relative: bool) {
+        let digits = input.strip_prefix("0x").or_else(|| input.strip_prefix("0X")).unwrap_or(input);
+        let Ok(addr) = u64::from_str_radix(digits, 16) else {
+            self.status_message = Some("Invalid address".to_string());
+            return;
+        };
+        let offset = match (self.buf.executable(), relative) {
+            (None, _) => {
+                self.status_message = Some("Not an ELF or PE file".to_string());
+                return;
+            }
+            (Some(Executable::Pe(pe)), true) => u32::try_from(addr)
+                .ok()
+                .and_then(|rva| pe.rva_to_offset(rva)),
+            (Some(_), true) => {
+                self.status_message = Some("RVA needs a PE file".to_string());
+                return;
+            }
+            (Some(executable), false) => executable.vaddr_to_offset(addr),
+        };
+        let kind = if relative { "rva" } else { "va" };
+        match offset {
+            Some(offset) if offset <= self.buf.document.len() as u64 => {
+                self.buf.cursor = offset as usize;
+                self.ensure_cursor_visible();
+                self.status_message =
+                    Some(format!("Jumped to {:08X} ({} {:#x})", offset, kind, addr));
+            }
+            _ => {
+                self.status_message = Some(format!(
+                    "Address {} {:#x} is not mapped from the file",
+                    kind, addr
+                ))
+            }
+        }
+    }
+
+    /// ステータス行に表示するファイル形式と、
+    /// カーソル位置のセクション名・仮想アドレス
+    /// ELF・PE の時だけ
+    fn format_file_info(&mut self) -> String {
+        let cursor = self.buf.cursor as u64;
+        let file_type = match self.buf.file_type() {
+            Some(found) => format!(" | {}", found.name),
+            None => String::new(),
+        };
+        let Some(executable) = self.buf.executable() else {
+            return file_type;
+        };
+        let location = match (executable.section_at(cursor), executable.offset_to_vaddr(cursor)) {
+            (Some(section), Some(vaddr)) => format!(" | {} @ {:#x}", section, vaddr),
+            (Some(section), None) => format!(" | {}", section),
+            (None, Some(vaddr)) => format!(" | @ {:#x}", vaddr),
+            (None, None) => String::new(),
+        };
+        file_type + &location
+    }
+
     /// ファイルを開く
     fn open_file(&mut self, path: &str) {
         let path = path.trim();
@@ -1512,18 +3045,12 @@ impl App {
             return;
         }
 
-        // チルダ展開
-        let expanded = if path.starts_with("~/") {
-            if let Some(home) = std::env::var_os("HOME") {
-                PathBuf::from(home).join(&path[2..])
-            } else {
-                PathBuf::from(path)
-            }
-        } else {
-            PathBuf::from(path)
-        };
+        let expanded = expand_home(path);
 
+        self.status_message = None;
         match self.open(&expanded) {
+            // 自動保存データ・Undo 履歴の案内を優先する
+            Ok(()) if self.status_message.is_some() => {}
             Ok(()) => {
                 self.status_message = Some(format!("Opened: {}", expanded.display()));
             }
@@ -1533,17 +3060,87 @@ impl App {
         }
     }
 
+    /// 外部で変更されたファイルがあれば確認する
+    /// 表示中のバッファになければ、
+    /// 変更されたファイルのバッファに切り替えて確認する
+    fn check_disk_changes(&mut self) {
+        if !self.buf.document.changed_on_disk() {
+            let Some(index) = self.buffers.iter().position(|b| b.document.changed_on_disk()) else {
+                return;
+            };
+            self.switch_to_buffer(index);
+        }
+        self.confirm_mode = ConfirmMode::FileChanged {
+            saving: false,
+            diff: None,
+        };
+    }
+
+    /// 保存（ファイルが外部で変更されていれば先に確認する）
+    fn save_document(&mut self) {
+        if self.buf.document.changed_on_disk() {
+            self.confirm_mode = ConfirmMode::FileChanged {
+                saving: true,
+                diff: None,
+            };
+            return;
+        }
+        if let Err(e) = self.buf.document.save() {
+            self.status_message = Some(format!("Save failed: {}", e));
+        } else {
+            self.buf.recover_pending = false;
+            self.status_message = Some(match self.buf.document.take_history_error() {
+                Some(e) => format!("Saved, but undo history not written: {}", e),
+                None => "Saved".to_string(),
+            });
+        }
+    }
+
     /// 確認モード中のキー処理
     fn handle_confirm_key(&mut self, key: crossterm::event::KeyEvent) {
         let normalized = match key.code {
             KeyCode::Char(c) => KeyCode::Char(Self::normalize_fullwidth(c)),
             other => other,
         };
+        if let ConfirmMode::FileChanged { saving, .. } = self.confirm_mode {
+            self.handle_file_changed_key(normalized, key.modifiers, saving);
+            return;
+        }
         match normalized {
             // y: 保存して実行
             KeyCode::Char('y') | KeyCode::Char('Y') => {
-                // まず保存
-                if let Err(e) = self.document.save() {
+                // 外部で変更されたファイルは確認なしに上書きしない
+                let quit = self.confirm_mode == ConfirmMode::Quit;
+                let changed: Vec<&str> = self
+                    .all_buffers()
+                    .take(if quit { usize::MAX } else { 1 })
+                    .filter(|b| b.document.is_modified() && b.document.changed_on_disk())
+                    .map(|b| b.name.as_str())
+                    .collect();
+                if !changed.is_empty() {
+                    self.status_message = Some(format!(
+                        "Not saved: {} changed on disk; review with C-x C-s first",
+                        changed.join(", ")
+                    ));
+                    self.confirm_mode = ConfirmMode::Off;
+                    return;
+                }
+                // まず保存（終了時は変更のあるバッファすべて）
+                let saved = if quit {
+                    self.all_buffers_mut()
+                        .filter(|b| b.document.is_modified())
+                        .try_for_each(|b| {
+                            b.document.save().map_err(|e| format!("{}: {}", b.name, e))?;
+                            b.recover_pending = false;
+                            Ok(())
+                        })
+                } else {
+                    self.buf.document.save().map_err(|e| e.to_string())
+                };
+                if saved.is_ok() {
+                    self.buf.recover_pending = false;
+                }
+                if let Err(e) = saved {
                     self.status_message = Some(format!("Save failed: {}", e));
                     self.confirm_mode = ConfirmMode::Off;
                     return;
@@ -1568,31 +3165,119 @@ impl App {
         }
     }
 
+    /// 外部変更の確認中のキー処理
+    fn handle_file_changed_key(&mut self, code: KeyCode, modifiers: KeyModifiers, saving: bool) {
+        match code {
+            // r: ディスク上の内容を読み直す
+            KeyCode::Char('r') | KeyCode::Char('R') => {
+                self.confirm_mode = ConfirmMode::Off;
+                match self.buf.document.revert() {
+                    Ok(()) => {
+                        self.buf.recover_pending = false;
+                        self.buf.selection = None;
+                        self.buf.selection_start = None;
+                        self.buf.cursor = self
+                            .buf
+                            .cursor
+                            .min(self.buf.document.len().saturating_sub(1));
+                        self.ensure_cursor_visible();
+                        self.status_message = Some("Reverted from disk".to_string());
+                    }
+                    Err(e) => {
+                        self.status_message = Some(format!("Revert failed: {}", e));
+                    }
+                }
+            }
+            // k: バッファの内容を維持する（保存中なら上書き保存）
+            KeyCode::Char('k') | KeyCode::Char('K') => {
+                self.confirm_mode = ConfirmMode::Off;
+                self.buf.document.acknowledge_disk_change();
+                if saving {
+                    self.save_document();
+                } else {
+                    self.status_message = Some("Keeping buffer contents".to_string());
+                }
+            }
+            // d: ディスク上の内容との違いを表示
+            KeyCode::Char('d') | KeyCode::Char('D') => {
+                let summary = match self.buf.document.diff_with_disk() {
+                    Ok(diff) => match diff.first {
+                        None => "identical to buffer".to_string(),
+                        Some(first) => {
+                            self.buf.cursor = first.min(self.buf.document.len().saturating_sub(1));
+                            self.ensure_cursor_visible();
+                            format!(
+                                "first diff at {:08X}, {} bytes differ, disk {} / buffer {} bytes",
+                                first,
+                                diff.differing,
+                                diff.disk_len,
+                                self.buf.document.len()
+                            )
+                        }
+                    },
+                    Err(e) => format!("diff failed: {}", e),
+                };
+                self.confirm_mode = ConfirmMode::FileChanged {
+                    saving,
+                    diff: Some(summary),
+                };
+            }
+            KeyCode::Esc => {
+                self.confirm_mode = ConfirmMode::Off;
+                self.status_message = Some("Cancelled".to_string());
+            }
+            KeyCode::Char('g') if modifiers.contains(KeyModifiers::CONTROL) => {
+                self.confirm_mode = ConfirmMode::Off;
+                self.status_message = Some("Cancelled".to_string());
+            }
+            _ => {}
+        }
+    }
+
     /// 確認後のアクションを実行
     fn execute_confirmed_action(&mut self) {
         let mode = std::mem::take(&mut self.confirm_mode);
         match mode {
             ConfirmMode::Quit => {
+                // 変更を破棄するので自動保存も不要
+                // 復元していないジャーナルは残す
+                for buf in self.all_buffers_mut() {
+                    if !buf.recover_pending {
+                        buf.document.remove_journal();
+                    }
+                }
                 self.should_quit = true;
             }
-            ConfirmMode::OpenFile(path) => {
-                self.open_file(&path);
-            }
             ConfirmMode::KillBuffer => {
+                if !self.buf.recover_pending {
+                    self.buf.document.remove_journal();
+                }
                 self.do_kill_buffer();
             }
-            ConfirmMode::Off => {}
+            ConfirmMode::FileChanged { .. } | ConfirmMode::Off => {}
         }
     }
 
-    /// バッファを閉じる（空のバッファにする）
+    /// 表示中のバッファを閉じて直前のバッファを表示する
+    /// なければ空のバッファにする
     fn do_kill_buffer(&mut self) {
-        self.document = Document::new();
-        self.cursor = 0;
-        self.offset = 0;
-        self.selection = None;
-        self.selection_start = None;
-        self.status_message = Some("Buffer killed".to_string());
+        let next = if self.buffers.is_empty() {
+            let mut document = Document::new();
+            document.set_make_backup(self.make_backup);
+            document.set_readonly(self.readonly);
+            Buffer::new(document)
+        } else {
+            self.buffers.remove(0)
+        };
+        let killed = self.show_buffer(next);
+        self.retarget_windows(killed.id);
+        if self.occur.as_ref().is_some_and(|o| o.buffer == killed.id) {
+            self.occur = None;
+        }
+        if self.template.as_ref().is_some_and(|t| t.buffer == killed.id) {
+            self.template = None;
+        }
+        self.status_message = Some(format!("Killed buffer {}", killed.name));
     }
 
     /// 別名保存
@@ -1603,20 +3288,23 @@ impl App {
             return;
         }
 
-        // チルダ展開
-        let expanded = if path.starts_with("~/") {
-            if let Some(home) = std::env::var_os("HOME") {
-                PathBuf::from(home).join(&path[2..])
-            } else {
-                PathBuf::from(path)
-            }
-        } else {
-            PathBuf::from(path)
-        };
+        let expanded = expand_home(path);
 
-        match self.document.save_as(&expanded) {
+        match self.buf.document.save_as(&expanded) {
             Ok(()) => {
-                self.status_message = Some(format!("Saved: {}", expanded.display()));
+                self.buf.recover_pending = false;
+                // バッファ名を新しいファイル名に合わせる
+                let base = Buffer::base_name(&self.buf.document).to_string();
+                self.buf.name.clear();
+                self.buf.name = self.unique_buffer_name(&base);
+                self.status_message = Some(match self.buf.document.take_history_error() {
+                    Some(e) => format!(
+                        "Saved: {}, but undo history not written: {}",
+                        expanded.display(),
+                        e
+                    ),
+                    None => format!("Saved: {}", expanded.display()),
+                });
             }
             Err(e) => {
                 self.status_message = Some(format!("Failed to save: {}", e));
@@ -1627,7 +3315,7 @@ impl App {
     /// 選択範囲の数値解釈をフォーマット
     fn format_selection_info(&self, start: usize, end: usize) -> String {
         let len = end - start + 1;
-        let bytes = match self.document.get_range(start, end + 1) {
+        let bytes = match self.buf.document.get_range(start, end + 1) {
             Some(b) => b,
             None => return format!("Selection: {} bytes", len),
         };
@@ -1698,10 +3386,185 @@ impl App {
         format!(" {}", parts.join(" | "))
     }
 
+    /// ウィンドウにバッファの HEXビューを描画
+    /// 表示範囲の前後だけを読み込む
+    fn draw_hex_view(&self, frame: &mut Frame, area: Rect, window: &Window, buf: &Buffer) {
+        let rows = area.height as usize;
+        let len = buf.document.len();
+        // 他のウィンドウでの編集で短くなっていることがある
+        let offset = window.offset.min(len - len % self.bytes_per_row);
+        let view_start = offset.saturating_sub(HexView::LOOKAROUND);
+        let view_end = (offset + rows * self.bytes_per_row + HexView::LOOKAROUND).min(len);
+        let view_data = buf
+            .document
+            .get_range(view_start.min(view_end), view_end)
+            .unwrap_or_default();
+        // 比較中のウィンドウなら差分を強調表示する
+        let highlights = self
+            .comparison
+            .as_ref()
+            .and_then(|cmp| {
+                let side = cmp.windows.iter().position(|&id| id == window.id)?;
+                Some(cmp.ranges(side, self.compared_documents(cmp)?, view_start, view_end))
+            })
+            .unwrap_or_default();
+        let modified: Vec<_> = buf
+            .document
+            .dirty_ranges()
+            .into_iter()
+            .filter(|r| r.end > view_start && r.start < view_end)
+            .collect();
+        // 検索中なら表示範囲の一致をすべて強調表示する
+        let matches = match &self.match_counter {
+            Some(counter) if buf.id == self.buf.id => {
+                counter.visible(&buf.document, view_start, view_end)
+            }
+            _ => Vec::new(),
+        };
+        let hex_view = HexView::new(&view_data)
+            .window(view_start.min(view_end), len)
+            .offset(offset)
+            .cursor(window.cursor.min(len))
+            .selection(window.selection)
+            .highlights(&highlights)
+            .modified(&modified)
+            .matches(&matches)
+            .bytes_per_row(self.bytes_per_row)
+            .encoding(buf.encoding)
+            .mode(if self.hex_mode {
+                ViewMode::Hex
+            } else {
+                ViewMode::Ascii
+            });
+        frame.render_widget(hex_view, area);
+    }
+
+    /// 分割時のウィンドウごとのモードライン（バッファ名とカーソル位置）
+    fn draw_mode_line(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        window: &Window,
+        buf: &Buffer,
+        active: bool,
+    ) {
+        let line = format!(
+            " {}{} | {:08X}/{:08X}",
+            buf.name,
+            if buf.document.is_modified() { "[+]" } else { "" },
+            window.cursor.min(buf.document.len()),
+            buf.document.len(),
+        );
+        let style = if active {
+            Style::default().bg(Color::Gray).fg(Color::Black)
+        } else {
+            Style::default().bg(Color::DarkGray).fg(Color::Gray)
+        };
+        frame.render_widget(Paragraph::new(line).style(style), area);
+    }
+
+    /// バッファ一覧 (C-x C-b) を描画
+    /// 表示中のバッファ(.)・変更あり(*)・読み取り専用(%) を
+    /// Emacs と同じ記号で示す
+    fn draw_buffer_menu(&self, frame: &mut Frame, area: Rect, selected: usize) {
+        let name_width = self
+            .all_buffers()
+            .map(|b| b.name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("Buffer".len());
+        let mut lines = vec![Line::styled(
+            format!(" CRM {:<name_width$} {:>12}  File", "Buffer", "Size"),
+            Style::default().fg(Colors::HEADER),
+        )];
+        for (i, buf) in self.all_buffers().enumerate() {
+            let line = format!(
+                " {}{}{} {:<name_width$} {:>12}  {}",
+                if i == 0 { '.' } else { ' ' },
+                if buf.document.is_readonly() { '%' } else { ' ' },
+                if buf.document.is_modified() { '*' } else { ' ' },
+                buf.name,
+                buf.document.len(),
+                buf.document.path().map(|p| p.display().to_string()).unwrap_or_default(),
+            );
+            let style = if i == selected {
+                Style::default().bg(Colors::SELECTION_BG)
+            } else {
+                Style::default()
+            };
+            lines.push(Line::styled(line, style));
+        }
+        frame.render_widget(Paragraph::new(lines), area);
+    }
+
+    /// 一致の一覧 (M-s o) のペインを描画
+    fn draw_occur(&self, frame: &mut Frame, area: Rect, occur: &Occur) {
+        let Some(buf) = self.all_buffers().find(|b| b.id == occur.buffer) else {
+            return;
+        };
+        let mut lines = vec![Line::styled(
+            occur.header(&buf.name),
+            Style::default().fg(Colors::HEADER),
+        )];
+        let rows = occur.matches.len().min(occur.scroll + occur.rows);
+        for i in occur.scroll..rows {
+            let line = occur.line(i, &buf.document, buf.encoding);
+            lines.push(match i == occur.selected {
+                true if occur.focused => line.style(Style::default().bg(Colors::SELECTION_BG)),
+                true => line.style(Style::default().bg(Color::DarkGray)),
+                false => line,
+            });
+        }
+        frame.render_widget(Paragraph::new(lines), area);
+    }
+
+    /// 構造体テンプレート (M-x apply-template) のペインを描画
+    fn draw_template(&self, frame: &mut Frame, area: Rect, template: &TemplateView) {
+        let mut lines = vec![Line::styled(template.header(), Style::default().fg(Colors::HEADER))];
+        let rows = template.len().min(template.scroll + template.rows);
+        for i in template.scroll..rows {
+            let line = template.line(i);
+            lines.push(match i == template.selected {
+                true if template.focused => line.style(Style::default().bg(Colors::SELECTION_BG)),
+                true => line.style(Style::default().bg(Color::DarkGray)),
+                false => line,
+            });
+        }
+        let block = Block::default()
+            .borders(Borders::LEFT)
+            .border_style(Style::default().fg(Color::DarkGray));
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    /// データインスペクタ (C-x i) のペインを描画
+    fn draw_inspector(&self, frame: &mut Frame, area: Rect, inspector: &Inspector) {
+        let data = self.inspector_bytes();
+        let header = format!(" Inspector @ {:08X}", self.buf.cursor);
+        let mut lines = vec![Line::styled(header, Style::default().fg(Colors::HEADER))];
+        let rows = FIELDS.len().min(inspector.scroll + inspector.rows);
+        for (i, field) in FIELDS.iter().enumerate().take(rows).skip(inspector.scroll) {
+            let line = Line::from(vec![
+                Span::styled(format!(" {:<9} ", field.label()), Style::default().fg(Colors::ADDR)),
+                Span::raw(field.decode(&data).unwrap_or_else(|| "-".to_string())),
+            ]);
+            lines.push(match i == inspector.selected {
+                true if inspector.focused => line.style(Style::default().bg(Colors::SELECTION_BG)),
+                true => line.style(Style::default().bg(Color::DarkGray)),
+                false => line,
+            });
+        }
+        let block = Block::default()
+            .borders(Borders::LEFT)
+            .border_style(Style::default().fg(Color::DarkGray));
+        // HEXビューは領域の幅で切らずに描くので、はみ出した分を消してから描く
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
     /// UIを描画
     pub fn draw(&mut self, frame: &mut Frame) {
         let size = frame.area();
-        self.set_visible_rows(size.height as usize);
 
         let layout = Layout::default()
             .direction(Direction::Vertical)
@@ -1711,66 +3574,243 @@ impl App {
             ])
             .split(size);
 
-        // HEXビュー
-        let hex_view = HexView::new(self.document.data())
-            .offset(self.offset)
-            .cursor(self.cursor)
-            .selection(self.selection)
-            .bytes_per_row(self.bytes_per_row)
-            .encoding(self.encoding)
-            .mode(if self.hex_mode {
-                ViewMode::Hex
+        // 一致の一覧 (M-s o) はウィンドウの下に表示する（見出し + 一致の行）
+        let mut main_area = layout[0];
+        let mut occur_area = None;
+        if let Some(occur) = self.occur.as_mut() {
+            let height = (occur.matches.len() as u16 + 1).min((main_area.height / 3).max(4));
+            let split = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(1), Constraint::Length(height)])
+                .split(main_area);
+            occur.set_rows(split[1].height.saturating_sub(1) as usize);
+            main_area = split[0];
+            occur_area = Some(split[1]);
+        }
+
+        // テンプレートは編集に合わせて当てはめ直し、選択をカーソルに合わせる
+        if let Some(template) = self.template.as_mut() {
+            if template.buffer == self.buf.id {
+                template.refresh(&self.buf.document);
+                if !template.focused {
+                    template.follow(self.buf.cursor);
+                }
+            } else if let Some(buf) = self.buffers.iter().find(|b| b.id == template.buffer) {
+                template.refresh(&buf.document);
+            }
+        }
+
+        // 構造体テンプレート・データインスペクタ (C-x i) は
+        // ウィンドウの右に表示する
+        // 両方なら上下に並べる
+        let mut template_area = None;
+        let mut inspector_area = None;
+        if self.template.is_some() || self.inspector.is_some() {
+            let width = if self.template.is_some() {
+                TEMPLATE_WIDTH
             } else {
-                ViewMode::Ascii
-            });
-        frame.render_widget(hex_view, layout[0]);
+                INSPECTOR_WIDTH
+            };
+            let split = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Min(1),
+                    Constraint::Length(width.min(main_area.width / 2)),
+                ])
+                .split(main_area);
+            main_area = split[0];
+            let mut side = split[1];
+            if let Some(inspector) = self.inspector.as_mut() {
+                let area = if self.template.is_some() {
+                    let height = (FIELDS.len() as u16 + 1).min(side.height / 2);
+                    let split = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints([Constraint::Min(1), Constraint::Length(height)])
+                        .split(side);
+                    side = split[0];
+                    split[1]
+                } else {
+                    side
+                };
+                inspector.set_rows(area.height.saturating_sub(1) as usize);
+                inspector_area = Some(area);
+            }
+            if let Some(template) = self.template.as_mut() {
+                template.set_rows(side.height.saturating_sub(1) as usize);
+                template_area = Some(side);
+            }
+        }
+
+        // ウィンドウ（分割時は各ウィンドウの最終行をモードラインにする）
+        let areas = self.window_tree.areas(main_area);
+        let split = areas.len() > 1;
+        for (id, area) in areas {
+            let mut view_area = area;
+            if split {
+                view_area.height = area.height.saturating_sub(1);
+            }
+            let active = id == self.active_window;
+            let window = if active {
+                self.set_visible_rows(view_area.height as usize);
+                Window::new(id, &self.buf)
+            } else {
+                match self.windows.iter().find(|w| w.id == id) {
+                    Some(window) => window.clone(),
+                    None => continue,
+                }
+            };
+            let Some(buf) = self.all_buffers().find(|b| b.id == window.buffer) else {
+                continue;
+            };
+            match self.buffer_menu {
+                Some(row) if active => self.draw_buffer_menu(frame, view_area, row),
+                _ => self.draw_hex_view(frame, view_area, &window, buf),
+            }
+            if split && area.height > 0 {
+                let mode_line = Rect {
+                    y: area.y + view_area.height,
+                    height: 1,
+                    ..area
+                };
+                self.draw_mode_line(frame, mode_line, &window, buf, active);
+            }
+        }
+
+        if let (Some(occur), Some(area)) = (&self.occur, occur_area) {
+            self.draw_occur(frame, area, occur);
+        }
+        if let (Some(template), Some(area)) = (&self.template, template_area) {
+            self.draw_template(frame, area, template);
+        }
+        if let (Some(inspector), Some(area)) = (&self.inspector, inspector_area) {
+            self.draw_inspector(frame, area, inspector);
+        }
 
         // ステータスバー（ファイル名 + 情報を統合）
-        let filename = self.document.filename().unwrap_or("[New]");
-        let modified = if self.document.is_modified() { "[+]" } else { "" };
+        let file_info = self.format_file_info();
+        let filename = self.buf.name.as_str();
+        let modified = match (self.buf.document.is_modified(), self.buf.document.is_readonly()) {
+            (true, true) => "[+] RO",
+            (true, false) => "[+]",
+            (false, true) => " RO",
+            (false, false) => "",
+        };
         let mode_str = if self.hex_mode { "HEX" } else { "ASC" };
         let edit_str = match self.edit_mode {
             EditMode::Overwrite => "OVR",
             EditMode::Insert => "INS",
         };
 
+        let regexp = if self.search_regex { " regexp" } else { "" };
         let status = if self.search_mode {
-            format!("I-search: {}_", self.search_query)
+            let count = match &self.match_counter {
+                Some(counter) => format!("  [{}]", counter.status(self.buf.cursor)),
+                None => String::new(),
+            };
+            format!(
+                "{}{}I-search: {}_{}{}",
+                if self.search_fold_case { "Case-insensitive " } else { "" },
+                if self.search_regex { "Regexp " } else { "" },
+                self.search_query,
+                if self.search_invalid { " [incomplete input]" } else { "" },
+                count
+            )
         } else if self.replace_mode == ReplaceMode::EnteringSearch {
-            format!("Query replace: {}_", self.search_query)
+            format!("Query replace{}: {}_", regexp, self.search_query)
         } else if self.replace_mode == ReplaceMode::EnteringReplace {
-            format!("Query replace {} with: {}_", self.search_query, self.replace_with)
+            format!("Query replace{} {} with: {}_", regexp, self.search_query, self.replace_with)
         } else if self.prompt_mode == PromptMode::GotoAddress {
             format!("Goto address: {}_", self.prompt_input)
         } else if self.prompt_mode == PromptMode::OpenFile {
             format!("Open file: {}_", self.prompt_input)
         } else if self.prompt_mode == PromptMode::SaveAs {
             format!("Save as: {}_", self.prompt_input)
+        } else if self.prompt_mode == PromptMode::SwitchBuffer {
+            let default = match self.buffers.first() {
+                Some(buf) => format!(" (default {})", buf.name),
+                None => String::new(),
+            };
+            format!(
+                "Switch to buffer{}: {}_ {{{}}}",
+                default,
+                self.prompt_input,
+                self.buffer_candidates(&self.prompt_input).join(" | ")
+            )
+        } else if self.buffer_menu.is_some() {
+            " Buffer list: (n)ext (p)revious (f)/Enter select (q)uit".to_string()
+        } else if self.prompt_mode == PromptMode::Occur {
+            let default = match self.last_search_query.as_str() {
+                "" => String::new(),
+                query => format!(" (default {})", query),
+            };
+            format!("List matching{}: {}_", default, self.prompt_input)
+        } else if self.occur.as_ref().is_some_and(|o| o.focused) && self.status_message.is_none() {
+            " Occur: (n)ext (p)revious Enter jump SPC show (o)ther window (q)uit".to_string()
+        } else if self.prompt_mode == PromptMode::InspectorEdit {
+            let label = self
+                .inspector
+                .as_ref()
+                .map(|i| i.field().label())
+                .unwrap_or_default();
+            format!(
+                "Write {} at {:08X}: {}_",
+                label, self.buf.cursor, self.prompt_input
+            )
+        } else if self.template.as_ref().is_some_and(|t| t.focused) && self.status_message.is_none()
+        {
+            " Template: (n)ext (p)revious TAB fold Enter select SPC show (o)ther window (q)uit"
+                .to_string()
+        } else if self.inspector.as_ref().is_some_and(|i| i.focused)
+            && self.status_message.is_none()
+        {
+            " Inspector: (n)ext (p)revious (e)/Enter edit (o)ther window (q)uit".to_string()
         } else if self.prompt_mode == PromptMode::Command {
             format!("M-x {}_", self.prompt_input)
         } else if self.prompt_mode == PromptMode::CommandArg {
             let prompt = match self.current_command.as_str() {
                 "fill" => "Fill with byte (hex):",
                 "insert" => "Insert (count [byte]):",
+                "bytes-per-row" => "Bytes per row:",
+                "apply-template" => "Template file:",
+                "goto-section" => "Go to section:",
+                "goto-symbol" => "Go to symbol:",
                 _ => "Arg:",
             };
-            format!("{} {}_", prompt, self.prompt_input)
+            if self.name_targets.is_empty() {
+                format!("{} {}_", prompt, self.prompt_input)
+            } else {
+                let candidates = self.name_candidates(&self.prompt_input);
+                let more = if candidates.len() > MAX_CANDIDATES { " | …" } else { "" };
+                format!(
+                    "{} {}_ {{{}{}}}",
+                    prompt,
+                    self.prompt_input,
+                    candidates[..candidates.len().min(MAX_CANDIDATES)].join(" | "),
+                    more
+                )
+            }
+        } else if let ConfirmMode::FileChanged { ref diff, .. } = self.confirm_mode {
+            match diff {
+                Some(summary) => format!("Changed on disk: {} | (r)evert (k)eep", summary),
+                None => "File changed on disk; (r)evert (k)eep (d)iff".to_string(),
+            }
         } else if self.confirm_mode != ConfirmMode::Off {
             "Save changes? (y)es (n)o (c)ancel".to_string()
         } else if let Some(ref msg) = self.status_message {
             format!(" {}{} | {}", filename, modified, msg)
-        } else if let Some((start, end)) = self.selection {
+        } else if let Some((start, end)) = self.buf.selection {
             format!(" {}{} | {}", filename, modified, self.format_selection_info(start, end))
         } else {
             format!(
-                " {}{} | {:08X}/{:08X} | {} {} | {}",
+                " {}{} | {:08X}/{:08X} | {} {} | {}{}",
                 filename,
                 modified,
-                self.cursor,
-                self.document.len(),
+                self.buf.cursor,
+                self.buf.document.len(),
                 mode_str,
                 edit_str,
-                self.encoding.name(),
+                self.buf.encoding.name(),
+                file_info,
             )
         };
 
@@ -1785,3 +3825,107 @@ impl Default for App {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_hex_pattern_detection() {
+        let hex = |s| App::hex_pattern(s).map(|m| (m.bytes, m.mask));
+        assert_eq!(
+            hex("E8 ?? 4? 0x8B"),
+            Some((vec![0xE8, 0, 0x40, 0x8B], vec![0xFF, 0, 0xF0, 0xFF]))
+        );
+        assert_eq!(
+            hex("dead beef"),
+            Some((vec![0xDE, 0xAD, 0xBE, 0xEF], vec![0xFF; 4]))
+        );
+        assert_eq!(hex("{0x41, 0x42}"), Some((vec![0x41, 0x42], vec![0xFF; 2])));
+        assert_eq!(hex("４？"), Some((vec![0x40], vec![0xF0])));
+        // 16進数字でない文字や、
+        // バイト単位でないワイルドカードを含むものはテキスト
+        assert_eq!(hex("what?"), None);
+        assert_eq!(hex("bad?"), None);
+        assert_eq!(hex("exe"), None);
+        assert_eq!(hex("?"), None);
+    }
+
+    #[test]
+    fn test_auto_save_after_declined_recovery() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let mut doc = Document::open(&path).unwrap();
+        doc.set(0, b'a').unwrap();
+        doc.write_journal().unwrap();
+        drop(doc);
+
+        let mut app = App::new();
+        app.open(&path).unwrap();
+        assert!(app.buf.recover_pending);
+
+        // 復元せずに編集を続けると、その編集で自動保存が再開する
+        app.hex_mode = false;
+        app.buf.cursor = 5;
+        app.execute(Action::InputAscii('x'));
+        app.buf.journal_time = Instant::now().checked_sub(AUTO_SAVE_INTERVAL).unwrap();
+        app.tick();
+        assert!(!app.buf.recover_pending);
+        let mut doc = Document::open(&path).unwrap();
+        doc.recover_journal().unwrap();
+        assert_eq!(doc.get_range(0, doc.len()).unwrap().as_ref(), b"01234x6789");
+
+        // 復元に失敗した場合も同じ
+        let mut app = App::new();
+        app.open(&path).unwrap();
+        std::fs::write(&path, b"changed").unwrap();
+        app.recover_file();
+        assert!(!app.buf.recover_pending);
+    }
+
+    #[test]
+    fn test_external_changes_across_buffers() {
+        let dir = TempDir::new();
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        std::fs::write(&a, b"aaaa").unwrap();
+        std::fs::write(&b, b"bbbb").unwrap();
+        let mut app = App::new();
+        app.open(&a).unwrap();
+        app.hex_mode = false;
+        app.execute(Action::InputAscii('x'));
+        app.open(&b).unwrap();
+
+        // 終了時の一括保存でも、外部で変更されたファイルは上書きしない
+        std::fs::write(&a, b"external").unwrap();
+        app.confirm_mode = ConfirmMode::Quit;
+        app.handle_confirm_key(KeyCode::Char('y').into());
+        assert!(!app.should_quit);
+        assert_eq!(app.confirm_mode, ConfirmMode::Off);
+        assert_eq!(std::fs::read(&a).unwrap(), b"external");
+
+        // フォーカス復帰時は表示していないバッファも確認する
+        assert_eq!(app.buf.name, "b");
+        app.dispatch_event(Event::FocusGained);
+        assert_eq!(app.buf.name, "a");
+        assert!(matches!(app.confirm_mode, ConfirmMode::FileChanged { saving: false, .. }));
+    }
+
+    #[test]
+    fn test_text_queries_with_question_marks() {
+        let app = App::new();
+        for query in ["what?", "bad?"] {
+            let Ok(Pattern::Bytes(bytes)) = app.query_to_pattern(query) else {
+                panic!("{query} should be searched as text");
+            };
+            assert_eq!(bytes, query.as_bytes());
+        }
+        let mut app = App::new();
+        app.search_query = "bad".to_string();
+        app.replace_with = "bad?".to_string();
+        let replaced = app.replace_with_to_pattern().unwrap();
+        assert_eq!(replaced.bytes, b"bad?");
+        assert!(replaced.is_exact());
+    }
+}
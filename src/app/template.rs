@@ -0,0 +1,244 @@
+use std::collections::HashSet;
+
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+
+use crate::buffer::Document;
+use crate::template::{Node, Template, TemplateError};
+use crate::ui::Colors;
+
+/// M-x apply-template の結果（フィールドのツリーを表示するペイン）
+pub struct TemplateView {
+    /// テンプレートのファイル名
+    pub name: String,
+    template: Template,
+    /// 当てはめたバッファの ID
+    pub buffer: u64,
+    /// 当てはめた位置
+    pub start: usize,
+    /// 当てはめた時のドキュメントのリビジョン
+    revision: u64,
+    /// フィールドのツリー
+    pub root: Node,
+    /// 編集後に当てはめ直せなかった時のエラー（ツリーは前回のもの）
+    pub error: Option<String>,
+    /// 折りたたんだノードの path
+    collapsed: HashSet<Vec<usize>>,
+    /// 表示している行（ノードの path）
+    lines: Vec<Vec<usize>>,
+    /// 選択中の行
+    pub selected: usize,
+    /// 表示している先頭の行
+    pub scroll: usize,
+    /// ペインに表示できる行数（ページ送りの単位）
+    pub rows: usize,
+    /// 最後に選択を合わせたカーソル位置
+    followed: Option<usize>,
+    /// ペインにフォーカスがあるか
+    pub focused: bool,
+}
+
+impl TemplateView {
+    /// doc の start にテンプレートを当てはめる（配列は折りたたんでおく）
+    pub fn new(
+        name: String,
+        template: Template,
+        buffer: u64,
+        doc: &Document,
+        start: usize,
+    ) -> Result<Self, TemplateError> {
+        let root = template.apply(doc, start)?;
+        let mut collapsed = HashSet::new();
+        collect_arrays(&root, &mut Vec::new(), &mut collapsed);
+        let mut view = Self {
+            name,
+            template,
+            buffer,
+            start,
+            revision: doc.revision(),
+            root,
+            error: None,
+            collapsed,
+            lines: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            rows: 1,
+            followed: None,
+            focused: true,
+        };
+        view.rebuild_lines();
+        Ok(view)
+    }
+
+    /// ドキュメントが編集されていたら当てはめ直す
+    pub fn refresh(&mut self, doc: &Document) {
+        if doc.revision() == self.revision {
+            return;
+        }
+        self.revision = doc.revision();
+        match self.template.apply(doc, self.start) {
+            Ok(root) => {
+                self.root = root;
+                self.error = None;
+                self.rebuild_lines();
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// 折りたたみに従って表示する行を並べ直す
+    fn rebuild_lines(&mut self) {
+        self.lines.clear();
+        let mut stack = vec![Vec::new()];
+        while let Some(path) = stack.pop() {
+            let Some(node) = self.root.get(&path) else {
+                continue;
+            };
+            if !self.collapsed.contains(&path) {
+                for i in (0..node.children.len()).rev() {
+                    let mut child = path.clone();
+                    child.push(i);
+                    stack.push(child);
+                }
+            }
+            self.lines.push(path);
+        }
+        self.selected = self.selected.min(self.lines.len().saturating_sub(1));
+    }
+
+    /// 表示している行数
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// 選択中のノード
+    pub fn current(&self) -> Option<&Node> {
+        self.root.get(self.lines.get(self.selected)?)
+    }
+
+    /// 選択を delta 行動かす（端で止まる）
+    pub fn move_by(&mut self, delta: isize) {
+        let last = self.lines.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// 選択中のノードを展開する（Some(true)）・折りたたむ（Some(false)）・
+    /// 切り替える（None）
+    pub fn set_expanded(&mut self, expanded: Option<bool>) {
+        let Some(path) = self.lines.get(self.selected).cloned() else {
+            return;
+        };
+        if self.root.get(&path).is_none_or(|node| node.children.is_empty()) {
+            return;
+        }
+        let expand = expanded.unwrap_or_else(|| self.collapsed.contains(&path));
+        if expand {
+            self.collapsed.remove(&path);
+        } else {
+            self.collapsed.insert(path);
+        }
+        self.rebuild_lines();
+    }
+
+    /// カーソル位置を含む最も深い行を選ぶ（カーソルが動いた時だけ）
+    pub fn follow(&mut self, pos: usize) {
+        if self.followed == Some(pos) {
+            return;
+        }
+        self.followed = Some(pos);
+        let found = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, path)| self.root.get(path).is_some_and(|node| node.range.contains(&pos)))
+            .max_by_key(|(_, path)| path.len());
+        if let Some((i, _)) = found {
+            self.selected = i;
+        }
+    }
+
+    /// 選択を合わせたことにする（ペインから選んだ範囲にカーソルを移した時）
+    pub fn mark_followed(&mut self, pos: usize) {
+        self.followed = Some(pos);
+    }
+
+    /// 表示できる行数を設定し、選択中の行が収まるようにスクロールする
+    pub fn set_rows(&mut self, rows: usize) {
+        self.rows = rows.max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.rows {
+            self.scroll = self.selected + 1 - self.rows;
+        }
+    }
+
+    /// 見出し行
+    pub fn header(&self) -> String {
+        match &self.error {
+            Some(error) => format!(" {} @ {:08X} | {}", self.name, self.start, error),
+            None => format!(" {} @ {:08X}", self.name, self.start),
+        }
+    }
+
+    /// ツリーの行（オフセット、インデントした名前、型、値）
+    pub fn line(&self, index: usize) -> Line<'static> {
+        let path = &self.lines[index];
+        let Some(node) = self.root.get(path) else {
+            return Line::default();
+        };
+        let marker = match (node.children.is_empty(), self.collapsed.contains(path)) {
+            (true, _) => "  ",
+            (false, true) => "▸ ",
+            (false, false) => "▾ ",
+        };
+        let mut spans = vec![
+            Span::styled(format!(" {:08X} ", node.range.start), Style::default().fg(Colors::ADDR)),
+            Span::raw(format!("{}{}{}", "  ".repeat(path.len()), marker, node.name)),
+            Span::styled(format!(" {}", node.type_name), Style::default().fg(Color::DarkGray)),
+        ];
+        if !node.value.is_empty() {
+            spans.push(Span::raw(format!(" = {}", node.value)));
+        }
+        Line::from(spans)
+    }
+}
+
+/// 要素を持つ配列の path を集める
+fn collect_arrays(node: &Node, path: &mut Vec<usize>, arrays: &mut HashSet<Vec<usize>>) {
+    if node.type_name.ends_with(']') && !node.children.is_empty() {
+        arrays.insert(path.clone());
+    }
+    for (i, child) in node.children.iter().enumerate() {
+        path.push(i);
+        collect_arrays(child, path, arrays);
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold_and_follow() {
+        let template =
+            Template::parse("struct P { u8 x; u8 y; } struct S { u16 n; P points[n]; u8 end; }")
+                .unwrap();
+        let doc = Document::from_bytes(vec![2, 0, 1, 2, 3, 4, 9]);
+        let mut view = TemplateView::new("s.tpl".to_string(), template, 0, &doc, 0).unwrap();
+        // S, n, points（折りたたみ）, end
+        assert_eq!(view.len(), 4);
+        view.follow(6);
+        assert_eq!(view.current().unwrap().name, "end");
+        view.follow(3);
+        assert_eq!(view.current().unwrap().name, "points");
+        view.set_expanded(None);
+        assert_eq!(view.len(), 10);
+        view.followed = None;
+        view.follow(3);
+        assert_eq!(view.current().unwrap().name, "y");
+        assert_eq!(view.current().unwrap().range, 3..4);
+    }
+}
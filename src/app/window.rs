@@ -0,0 +1,152 @@
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+
+use super::buffers::Buffer;
+
+/// ウィンドウ（バッファを表示する領域と、その中の表示位置）
+/// フォーカスのあるウィンドウの表示位置は表示中のバッファが持っていて、
+/// ここの値は古い
+#[derive(Debug, Clone)]
+pub struct Window {
+    /// ウィンドウ ID
+    pub id: usize,
+    /// 表示しているバッファの ID
+    pub buffer: u64,
+    /// カーソル位置
+    pub cursor: usize,
+    /// 表示オフセット
+    pub offset: usize,
+    /// 選択範囲
+    pub selection: Option<(usize, usize)>,
+    /// 選択開始位置
+    pub selection_start: Option<usize>,
+}
+
+impl Window {
+    /// バッファの現在の表示位置で新しいウィンドウを作成
+    pub fn new(id: usize, buf: &Buffer) -> Self {
+        Self {
+            id,
+            buffer: buf.id,
+            cursor: buf.cursor,
+            offset: buf.offset,
+            selection: buf.selection,
+            selection_start: buf.selection_start,
+        }
+    }
+
+    /// バッファの表示位置をウィンドウに保存
+    pub fn save(&mut self, buf: &Buffer) {
+        *self = Self::new(self.id, buf);
+    }
+
+    /// ウィンドウの表示位置をバッファに戻す
+    pub fn restore(&self, buf: &mut Buffer) {
+        buf.cursor = self.cursor;
+        buf.offset = self.offset;
+        buf.selection = self.selection;
+        buf.selection_start = self.selection_start;
+    }
+}
+
+/// ウィンドウの分割状態（二分木）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowTree {
+    /// 1つのウィンドウ（ウィンドウ ID）
+    Leaf(usize),
+    /// 2つに分割（Vertical は上下、Horizontal は左右）
+    Split(Direction, Box<WindowTree>, Box<WindowTree>),
+}
+
+impl WindowTree {
+    /// ウィンドウ id を分割して、新しいウィンドウ new_id を下（右）に置く
+    pub fn split(&mut self, id: usize, new_id: usize, direction: Direction) -> bool {
+        match self {
+            WindowTree::Leaf(leaf) if *leaf == id => {
+                *self = WindowTree::Split(
+                    direction,
+                    Box::new(WindowTree::Leaf(id)),
+                    Box::new(WindowTree::Leaf(new_id)),
+                );
+                true
+            }
+            WindowTree::Leaf(_) => false,
+            WindowTree::Split(_, first, second) => {
+                first.split(id, new_id, direction) || second.split(id, new_id, direction)
+            }
+        }
+    }
+
+    /// ウィンドウ id を取り除く（隣のウィンドウがその領域を引き継ぐ）
+    /// 最後の1つは取り除けない
+    pub fn remove(&mut self, id: usize) -> bool {
+        let WindowTree::Split(_, first, second) = self else {
+            return false;
+        };
+        let remaining = match (first.as_ref(), second.as_ref()) {
+            (WindowTree::Leaf(leaf), _) if *leaf == id => second.as_ref().clone(),
+            (_, WindowTree::Leaf(leaf)) if *leaf == id => first.as_ref().clone(),
+            _ => return first.remove(id) || second.remove(id),
+        };
+        *self = remaining;
+        true
+    }
+
+    /// ウィンドウ ID を画面上の順（上・左から）に並べる
+    pub fn ids(&self) -> Vec<usize> {
+        match self {
+            WindowTree::Leaf(id) => vec![*id],
+            WindowTree::Split(_, first, second) => {
+                let mut ids = first.ids();
+                ids.extend(second.ids());
+                ids
+            }
+        }
+    }
+
+    /// 各ウィンドウの画面上の領域
+    pub fn areas(&self, area: Rect) -> Vec<(usize, Rect)> {
+        match self {
+            WindowTree::Leaf(id) => vec![(*id, area)],
+            WindowTree::Split(direction, first, second) => {
+                let halves = Layout::default()
+                    .direction(*direction)
+                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                    .split(area);
+                let mut areas = first.areas(halves[0]);
+                areas.extend(second.areas(halves[1]));
+                areas
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_remove() {
+        let mut tree = WindowTree::Leaf(0);
+        assert!(tree.split(0, 1, Direction::Vertical));
+        assert!(tree.split(1, 2, Direction::Horizontal));
+        assert!(!tree.split(7, 3, Direction::Vertical));
+        assert_eq!(tree.ids(), vec![0, 1, 2]);
+
+        assert!(tree.remove(1));
+        assert_eq!(tree.ids(), vec![0, 2]);
+        assert!(tree.remove(0));
+        assert_eq!(tree, WindowTree::Leaf(2));
+        assert!(!tree.remove(2));
+    }
+
+    #[test]
+    fn test_areas() {
+        let mut tree = WindowTree::Leaf(0);
+        tree.split(0, 1, Direction::Vertical);
+        tree.split(1, 2, Direction::Horizontal);
+        let areas = tree.areas(Rect::new(0, 0, 80, 20));
+        assert_eq!(areas[0], (0, Rect::new(0, 0, 80, 10)));
+        assert_eq!(areas[1], (1, Rect::new(0, 10, 40, 10)));
+        assert_eq!(areas[2], (2, Rect::new(40, 10, 40, 10)));
+    }
+}
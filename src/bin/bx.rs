@@ -6,6 +6,8 @@ use std::io::{self, Read, Write};
 
 use anyhow::{bail, Result};
 use clap::{Parser, Subcommand};
+use ehx::buffer::{Masked, Numeric, Pattern};
+use ehx::format::magic;
 
 /// Binary hex tool for pipes
 #[derive(Parser, Debug)]
@@ -18,9 +20,12 @@ struct Args {
 
 #[derive(Subcommand, Debug)]
 enum Command {
-    /// Find hex pattern in input, output matching offsets
+    /// Find hex pattern or typed value in input, output matching offsets
     Find {
-        /// Hex pattern to search (e.g., "DEADBEEF" or "DE AD BE EF")
+        /// Hex pattern to search (e.g., "DEADBEEF", "DE AD BE EF" or "E8 ?? ?? 48 8B")
+        /// `??` matches any byte and `?` any nibble (e.g., "4?")
+        /// Typed values: "u32le:1337", "i16be:-2", "f32:3.14159~0.001", "u64:0xdeadbeef"
+        /// (both byte orders are searched when le/be is omitted)
         pattern: String,
 
         /// Input file (default: stdin)
@@ -49,10 +54,10 @@ enum Command {
 
     /// Replace hex pattern in input
     Replace {
-        /// Pattern to find (hex)
+        /// Pattern to find (hex, `?` matches any nibble)
         from: String,
 
-        /// Pattern to replace with (hex)
+        /// Pattern to replace with (hex, `?` keeps the matched nibble)
         to: String,
 
         /// Input file (default: stdin)
@@ -74,13 +79,20 @@ enum Command {
         input: Option<String>,
     },
 
-    /// Show file info (size, entropy, etc.)
+    /// Show file info (type, size, entropy, etc.)
     Info {
         /// Input file (default: stdin)
         #[arg(short, long)]
         input: Option<String>,
     },
 
+    /// Scan for embedded files by their signatures (like binwalk), output offsets and types
+    Scan {
+        /// Input file (default: stdin)
+        #[arg(short, long)]
+        input: Option<String>,
+    },
+
     /// Convert between hex and binary
     Conv {
         /// Direction: "hex2bin" or "bin2hex"
@@ -105,6 +117,7 @@ fn main() -> Result<()> {
         Command::Replace { from, to, input, all } => cmd_replace(&from, &to, input.as_deref(), all),
         Command::Patch { patches, input } => cmd_patch(&patches, input.as_deref()),
         Command::Info { input } => cmd_info(input.as_deref()),
+        Command::Scan { input } => cmd_scan(input.as_deref()),
         Command::Conv { direction, input, width } => cmd_conv(&direction, input.as_deref(), width),
     }
 }
@@ -121,24 +134,28 @@ fn read_input(path: Option<&str>) -> Result<Vec<u8>> {
     }
 }
 
-/// Parse hex string to bytes
+/// Parse hex string to bytes (no wildcards)
 fn parse_hex(s: &str) -> Result<Vec<u8>> {
-    let cleaned: String = s
-        .chars()
-        .filter(|c| c.is_ascii_hexdigit())
-        .collect();
-
-    if cleaned.len() % 2 != 0 {
-        bail!("Hex string must have even length");
+    let pattern = parse_pattern(s)?;
+    if !pattern.is_exact() {
+        bail!("Wildcards are not allowed here: {}", s.trim());
     }
+    Ok(pattern.bytes)
+}
 
-    (0..cleaned.len())
-        .step_by(2)
-        .map(|i| {
-            u8::from_str_radix(&cleaned[i..i + 2], 16)
-                .map_err(|e| anyhow::anyhow!("Invalid hex: {}", e))
-        })
-        .collect()
+/// Parse hex pattern with `??` / `?` wildcards
+fn parse_pattern(s: &str) -> Result<Masked> {
+    Masked::parse(s).map_err(|e| anyhow::anyhow!("{}: {}", e, s.trim()))
+}
+
+/// Parse find pattern: typed value ("u32le:1337") or hex pattern
+fn parse_find_pattern(s: &str) -> Result<Pattern> {
+    match Numeric::parse(s) {
+        Some(numeric) => Ok(Pattern::Numeric(
+            numeric.map_err(|e| anyhow::anyhow!("{}: {}", e, s.trim()))?,
+        )),
+        None => Ok(parse_pattern(s)?.into()),
+    }
 }
 
 /// Parse range string "start:end"
@@ -173,28 +190,17 @@ fn parse_offset(s: &str) -> Result<usize> {
 }
 
 /// Find pattern in data
-fn find_pattern(data: &[u8], pattern: &[u8]) -> Vec<usize> {
-    let mut results = Vec::new();
-    if pattern.is_empty() || pattern.len() > data.len() {
-        return results;
-    }
-
-    for i in 0..=data.len() - pattern.len() {
-        if &data[i..i + pattern.len()] == pattern {
-            results.push(i);
-        }
-    }
-    results
+fn find_pattern(data: &[u8], pattern: &Masked) -> Vec<usize> {
+    pattern.positions(data).collect()
 }
 
 // === Commands ===
 
 fn cmd_find(pattern: &str, input: Option<&str>, format: &str) -> Result<()> {
     let data = read_input(input)?;
-    let pattern_bytes = parse_hex(pattern)?;
-    let matches = find_pattern(&data, &pattern_bytes);
+    let pattern = parse_find_pattern(pattern)?;
 
-    for offset in matches {
+    for offset in pattern.matches_in(&data).map(|found| found.start) {
         match format {
             "dec" => println!("{}", offset),
             "both" => println!("0x{:08X} ({})", offset, offset),
@@ -238,10 +244,10 @@ fn cmd_slice(range: &str, input: Option<&str>, hex_output: bool) -> Result<()> {
 
 fn cmd_replace(from: &str, to: &str, input: Option<&str>, all: bool) -> Result<()> {
     let mut data = read_input(input)?;
-    let from_bytes = parse_hex(from)?;
-    let to_bytes = parse_hex(to)?;
+    let from_pattern = parse_pattern(from)?;
+    let to_pattern = parse_pattern(to)?;
 
-    let matches = find_pattern(&data, &from_bytes);
+    let matches = find_pattern(&data, &from_pattern);
 
     if matches.is_empty() {
         // No matches, output unchanged
@@ -256,9 +262,10 @@ fn cmd_replace(from: &str, to: &str, input: Option<&str>, all: bool) -> Result<(
         vec![matches[0]]
     };
 
-    for offset in indices.iter().rev() {
-        let end = offset + from_bytes.len();
-        data.splice(*offset..end, to_bytes.iter().cloned());
+    for &offset in &indices {
+        let end = offset + from_pattern.len();
+        let to_bytes = to_pattern.apply(&data[offset..end])?;
+        data.splice(offset..end, to_bytes);
     }
 
     io::stdout().write_all(&data)?;
@@ -292,6 +299,10 @@ fn cmd_patch(patches: &[String], input: Option<&str>) -> Result<()> {
 fn cmd_info(input: Option<&str>) -> Result<()> {
     let data = read_input(input)?;
 
+    match magic::identify(&data) {
+        Some(found) => println!("Type: {}", found),
+        None => println!("Type: unknown"),
+    }
     println!("Size: {} bytes (0x{:X})", data.len(), data.len());
 
     if !data.is_empty() {
@@ -322,6 +333,16 @@ fn cmd_info(input: Option<&str>) -> Result<()> {
     Ok(())
 }
 
+fn cmd_scan(input: Option<&str>) -> Result<()> {
+    let data = read_input(input)?;
+
+    for found in magic::scan(&data) {
+        println!("0x{:08X}  {}", found.offset, found);
+    }
+
+    Ok(())
+}
+
 fn cmd_conv(direction: &str, input: Option<&str>, width: usize) -> Result<()> {
     match direction {
         "bin2hex" | "b2h" => {
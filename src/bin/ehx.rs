@@ -13,6 +13,7 @@ use crossterm::{
 use ratatui::{backend::CrosstermBackend, Terminal};
 
 use ehx::app::App;
+use ehx::buffer::Alignment;
 
 /// Terminal hex editor inspired by Stirling
 #[derive(Parser, Debug)]
@@ -30,6 +31,28 @@ struct Args {
     /// Read-only mode
     #[arg(short, long)]
     readonly: bool,
+
+    /// Keep the previous contents as FILE~ on the first save
+    #[arg(long)]
+    backup: bool,
+
+    /// Write only changed bytes back into the file (keeps the inode, no length changes).
+    /// Always used for block and character devices
+    #[arg(long)]
+    in_place: bool,
+
+    /// Keep undo history across sessions (restored when the file is unchanged since the last save)
+    #[arg(long)]
+    persistent_undo: bool,
+
+    /// Compare two files side by side (M-n / M-p jump between differences)
+    #[arg(long, num_args = 2, value_names = ["A", "B"], conflicts_with = "file")]
+    diff: Option<Vec<String>>,
+
+    /// With --diff, realign after inserted or deleted bytes instead of comparing fixed offsets
+    /// (up to 1 MiB at a time; after a larger one the rest of the files is shown as one difference)
+    #[arg(long, requires = "diff")]
+    align: bool,
 }
 
 fn main() -> Result<()> {
@@ -83,19 +106,29 @@ fn main() -> Result<()> {
 
 fn run_app(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, args: Args, stdin_data: Option<Vec<u8>>) -> Result<()> {
     let mut app = App::new();
-
-    // データを読み込む（優先順位: ファイル > 標準入力）
-    if let Some(ref path) = args.file {
+    app.set_make_backup(args.backup);
+    app.set_in_place(args.in_place);
+    app.set_persistent_undo(args.persistent_undo);
+    app.set_readonly(args.readonly);
+    app.set_bytes_per_row(args.bytes_per_row);
+
+    // データを読み込む（優先順位: 比較 > ファイル > 標準入力）
+    if let Some(ref files) = args.diff {
+        let alignment = if args.align { Alignment::Insertions } else { Alignment::Fixed };
+        app.open_comparison(&files[0], &files[1], alignment)?;
+    } else if let Some(ref path) = args.file {
         app.open(path)?;
     } else if let Some(data) = stdin_data {
         app.load_bytes(data);
     }
 
-    // ウィンドウタイトルを設定
-    update_title(terminal.backend_mut(), &app)?;
+    // ウィンドウタイトル（変化した時だけ更新する）
+    let mut title = String::new();
 
     // メインループ
     loop {
+        update_title(terminal.backend_mut(), &app, &mut title)?;
+
         // Synchronized Update: 描画のちらつきを防止
         queue!(terminal.backend_mut(), BeginSynchronizedUpdate)?;
         terminal.draw(|f| app.draw(f))?;
@@ -112,13 +145,20 @@ fn run_app(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, args: Args, st
     Ok(())
 }
 
-/// ウィンドウタイトルを更新
-fn update_title(backend: &mut CrosstermBackend<io::Stdout>, app: &App) -> Result<()> {
+/// ウィンドウタイトルを更新（保存時点から変更されていれば [+] を付ける）
+fn update_title(
+    backend: &mut CrosstermBackend<io::Stdout>,
+    app: &App,
+    current: &mut String,
+) -> Result<()> {
     let title = format!(
         "hx - {}{}",
         app.filename().unwrap_or("[New File]"),
         if app.is_modified() { " [+]" } else { "" }
     );
-    execute!(backend, SetTitle(&title))?;
+    if title != *current {
+        execute!(backend, SetTitle(&title))?;
+        *current = title;
+    }
     Ok(())
 }
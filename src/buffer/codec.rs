@@ -0,0 +1,120 @@
+//! ジャーナル・履歴ファイル用の簡単なバイナリ形式
+//!
+//! 先頭にマジックを置き、整数はリトルエンディアン固定長、
+//! バイト列は長さを前置きして並べる。
+
+use super::BufferError;
+
+/// バイナリ形式の書き出し
+pub(super) struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    /// マジックを書き込んで開始
+    pub fn new(magic: &[u8]) -> Self {
+        Self { buf: magic.to_vec() }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// 長さ付きのバイト列
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// バイナリ形式の読み込み（壊れたデータは BufferError::Corrupt にする）
+pub(super) struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// マジックを確認して開始
+    pub fn new(data: &'a [u8], magic: &[u8]) -> Result<Self, BufferError> {
+        if !data.starts_with(magic) {
+            return Err(BufferError::Corrupt("unknown format"));
+        }
+        Ok(Self { data, pos: magic.len() })
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BufferError> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(BufferError::Corrupt("truncated"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, BufferError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, BufferError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, BufferError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// usize に収まる値として読む
+    pub fn usize(&mut self) -> Result<usize, BufferError> {
+        usize::try_from(self.u64()?).map_err(|_| BufferError::Corrupt("value out of range"))
+    }
+
+    /// 長さ付きのバイト列
+    pub fn bytes(&mut self) -> Result<&'a [u8], BufferError> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+
+    /// 末尾まで読み終えたか
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+/// FNV-1a (64bit) ハッシュ（ファイル内容・パスの照合用）
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(self) -> u64 {
+        self.0
+    }
+}
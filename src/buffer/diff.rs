@@ -0,0 +1,510 @@
+//! 2つのドキュメントの比較（差分のある範囲を求める）
+//!
+//! 大きなファイルでも入力が止まらないように、
+//! コピーせずに一定の大きさずつ読んで比較し、
+//! イベント処理の合間に少しずつ先へ進める。
+//! 編集された時は編集範囲の手前から求め直し、
+//! 編集範囲より後ろで編集前の結果と対応が一致したら、
+//! 残りは編集前の結果をずらして使う。
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Instant;
+
+use super::document::{Document, EditSpan};
+
+/// ずれを検出した後、同期が取れたとみなすのに必要な一致バイト数
+const SYNC_LEN: usize = 8;
+
+/// 同期位置をすべての位置で探す最大のずれ
+/// これを超えると WIDE_SHIFT まで間引いて探す
+const MAX_SHIFT: usize = 4096;
+
+/// 同期位置を間引いて探す最大のずれ
+/// これを超えると残りをすべて差分とする
+const WIDE_SHIFT: usize = 1 << 20;
+
+/// 間引いて探す時に索引にする b のバイト数と間隔
+/// ANCHOR_LEN + ANCHOR_STRIDE バイト以上一致が続けば同期位置が見つかる
+const ANCHOR_LEN: usize = 32;
+const ANCHOR_STRIDE: usize = 32;
+
+/// 一度に読んで比較するバイト数（この単位で時間切れを確認する）
+const COMPARE_STEP: usize = 64 << 10;
+
+/// 比較方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Alignment {
+    /// 同じオフセット同士を比較
+    #[default]
+    Fixed,
+    /// 挿入・削除でずれた位置を合わせて比較
+    Insertions,
+}
+
+/// 差分のある範囲（a と b の対応する範囲、片方が空なら挿入・削除）
+/// 範囲の間は両方とも同じ内容で、同じ長さだけ続く
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub a: Range<usize>,
+    pub b: Range<usize>,
+}
+
+/// 編集前の比較結果（編集後の比較が追いついたら引き継ぐ）
+struct Previous {
+    /// 求め直し始めた位置より後ろの差分（編集前の位置）
+    hunks: Vec<Hunk>,
+    /// 求め直し始めた位置（編集前後で同じ）
+    start: (usize, usize),
+    /// 編集前に次に比較する予定だった位置
+    next: (usize, usize),
+    done: bool,
+    /// A・B の編集範囲
+    spans: [Option<EditSpan>; 2],
+}
+
+/// A・B の差分を先頭から少しずつ求める比較
+pub struct Comparer {
+    alignment: Alignment,
+    /// 求めた差分（先頭から順）
+    hunks: Vec<Hunk>,
+    /// 次に比較する位置（ここでは A・B の対応が取れている）
+    next: (usize, usize),
+    /// 末尾まで比較し終えたか
+    done: bool,
+    /// 編集前の比較結果
+    previous: Option<Previous>,
+}
+
+impl Comparer {
+    pub fn new(alignment: Alignment) -> Self {
+        Self {
+            alignment,
+            hunks: Vec::new(),
+            next: (0, 0),
+            done: false,
+            previous: None,
+        }
+    }
+
+    /// これまでに求めた差分
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    /// 末尾まで比較し終えたか
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// 比較し終えた位置（side は 0 = A, 1 = B）
+    pub fn progress(&self, side: usize) -> usize {
+        if side == 0 { self.next.0 } else { self.next.1 }
+    }
+
+    /// A・B が編集されたので、編集範囲の影響を受けない所まで戻って求め直す
+    /// spans は前回の比較からの A・B の編集範囲（変わっていなければ None）
+    pub fn invalidate(&mut self, spans: [Option<EditSpan>; 2]) {
+        if spans == [None, None] {
+            return;
+        }
+        let limit = |side: usize| spans[side].map_or(usize::MAX, |span| span.start);
+        let (limit_a, limit_b) = (limit(0), limit(1));
+        // 差分の位置は、同期位置を探して先読みした範囲までの内容で決まる
+        let keep = self
+            .hunks
+            .iter()
+            .take_while(|h| {
+                let shift = h.a.len().max(h.b.len());
+                let ahead = shift
+                    + if shift > MAX_SHIFT {
+                        ANCHOR_LEN + ANCHOR_STRIDE
+                    } else {
+                        SYNC_LEN
+                    };
+                h.a.start + ahead <= limit_a && h.b.start + ahead <= limit_b
+            })
+            .count();
+        // 前回の編集にまだ追いついていなければ、
+        // 編集範囲をまとめて編集前の結果を使い続ける
+        if let Some(previous) = &mut self.previous {
+            for (old, new) in previous.spans.iter_mut().zip(spans) {
+                *old = match (*old, new) {
+                    (Some(old), Some(new)) => Some(old.then(new)),
+                    (old, new) => old.or(new),
+                };
+            }
+        }
+        if !self.done
+            && keep == self.hunks.len()
+            && self.next.0 <= limit_a
+            && self.next.1 <= limit_b
+        {
+            // まだ編集範囲まで比較していない
+            return;
+        }
+        let start = self.hunks[..keep].last().map_or((0, 0), |h| (h.a.end, h.b.end));
+        if self.previous.is_none() {
+            self.previous = Some(Previous {
+                hunks: self.hunks.split_off(keep),
+                start,
+                next: self.next,
+                done: self.done,
+                spans,
+            });
+        }
+        self.hunks.truncate(keep);
+        self.next = start;
+        self.done = false;
+    }
+
+    /// deadline まで比較を進める
+    pub fn step(&mut self, a: &Document, b: &Document, deadline: Instant) {
+        while !self.done {
+            let (i, j) = self.next;
+            if i == a.len() || j == b.len() {
+                self.push(i..a.len(), j..b.len());
+                self.next = (a.len(), b.len());
+                self.done = true;
+                self.previous = None;
+                return;
+            }
+            let n = COMPARE_STEP.min(a.len() - i).min(b.len() - j);
+            let (Some(wa), Some(wb)) = (a.get_range(i, i + n), b.get_range(j, j + n)) else {
+                self.done = true;
+                return;
+            };
+            let same = wa.iter().zip(wb.iter()).position(|(x, y)| x != y).unwrap_or(n);
+            if same > 0 && self.take_previous(i, j, same) {
+                continue;
+            }
+            self.next = (i + same, j + same);
+            if same < n {
+                match self.alignment {
+                    Alignment::Fixed => {
+                        // 一致しない間を1つの差分にする
+                        // 読んだ範囲の端で切れたら次でつなげる
+                        let run = wa[same..]
+                            .iter()
+                            .zip(&wb[same..])
+                            .position(|(x, y)| x == y)
+                            .unwrap_or(n - same);
+                        let (x, y) = self.next;
+                        self.push(x..x + run, y..y + run);
+                        self.next = (x + run, y + run);
+                    }
+                    Alignment::Insertions => self.resync(a, b),
+                }
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+    }
+
+    /// 一致しなくなった位置から同期し直して、その間を差分にする
+    fn resync(&mut self, a: &Document, b: &Document) {
+        let (i, j) = self.next;
+        let window = |len: usize| {
+            let wa = a.get_range(i, (i + len).min(a.len())).unwrap_or_default();
+            let wb = b.get_range(j, (j + len).min(b.len())).unwrap_or_default();
+            (wa, wb)
+        };
+        let (wa, wb) = window(MAX_SHIFT + SYNC_LEN);
+        let found = resync(&wa, &wb, a.len() - i, b.len() - j).or_else(|| {
+            let (wa, wb) = window(WIDE_SHIFT + ANCHOR_LEN);
+            resync_wide(&wa, &wb)
+        });
+        match found {
+            Some((da, db)) => {
+                self.push(i..i + da, j..j + db);
+                self.next = (i + da, j + db);
+            }
+            None => {
+                self.push(i..a.len(), j..b.len());
+                self.next = (a.len(), b.len());
+                self.done = true;
+                self.previous = None;
+            }
+        }
+    }
+
+    /// 差分を追加する（直前の差分とつながっていればまとめる）
+    fn push(&mut self, a: Range<usize>, b: Range<usize>) {
+        if a.is_empty() && b.is_empty() {
+            return;
+        }
+        match self.hunks.last_mut() {
+            Some(last) if last.a.end == a.start && last.b.end == b.start => {
+                last.a.end = a.end;
+                last.b.end = b.end;
+            }
+            _ => self.hunks.push(Hunk { a, b }),
+        }
+    }
+
+    /// (i, j) から same バイト一致が続く間に、
+    /// 編集範囲より後ろで編集前の比較と同じ対応になったら、
+    /// 編集前の結果の残りをずらして引き継ぐ
+    fn take_previous(&mut self, i: usize, j: usize, same: usize) -> bool {
+        let Some(previous) = &self.previous else {
+            return false;
+        };
+        let end = |side: usize| previous.spans[side].map_or(0, |span| span.end);
+        let delta = |side: usize| previous.spans[side].map_or(0, |span| span.delta);
+        let (da, db) = (delta(0), delta(1));
+        // 編集範囲の後ろだけが対象
+        let from = end(0).saturating_sub(i).max(end(1).saturating_sub(j));
+        if from > same {
+            return false;
+        }
+        let old = |pos: usize, delta: isize| pos.checked_add_signed(-delta);
+        let (Some(x), Some(y)) = (old(i + from, da), old(j + from, db)) else {
+            return false;
+        };
+        let x_end = x + (same - from);
+        // 編集前に一致していた区間（差分の間）のうち、同じ対応で重なるもの
+        let runs = std::iter::once(previous.start)
+            .chain(previous.hunks.iter().map(|h| (h.a.end, h.b.end)))
+            .zip(
+                previous
+                    .hunks
+                    .iter()
+                    .map(|h| (h.a.start, h.b.start))
+                    .chain(std::iter::once(previous.next)),
+            )
+            .enumerate();
+        let found = runs
+            .skip_while(|(_, (_, run_end))| run_end.0 < x)
+            .take_while(|(_, (run_start, _))| run_start.0 <= x_end)
+            .find(|(_, (run_start, run_end))| {
+                run_start.0 as isize - run_start.1 as isize == x as isize - y as isize
+                    && run_start.0 <= x_end
+                    && run_end.0 >= x
+            });
+        let Some((index, _)) = found else {
+            return false;
+        };
+        let Some(previous) = self.previous.take() else {
+            return false;
+        };
+        let shift = |pos: usize, delta: isize| pos.saturating_add_signed(delta);
+        for h in &previous.hunks[index..] {
+            self.push(
+                shift(h.a.start, da)..shift(h.a.end, da),
+                shift(h.b.start, db)..shift(h.b.end, db),
+            );
+        }
+        self.next = (shift(previous.next.0, da), shift(previous.next.1, db));
+        self.done = previous.done;
+        true
+    }
+}
+
+/// 一致しなくなった位置から後ろで、
+/// 再び一致し始める位置までのずれ (da, db) を探す
+/// a・b はその位置から読んだ内容（最大 MAX_SHIFT + SYNC_LEN バイト）、
+/// a_rest・b_rest はその位置から末尾までの長さ。
+/// 置き換え（da == db）を優先し、次に大きい方のずれが小さいものを選ぶ
+fn resync(a: &[u8], b: &[u8], a_rest: usize, b_rest: usize) -> Option<(usize, usize)> {
+    let key = |(da, db): (usize, usize)| (da.max(db), da != db, da.min(db), da < db);
+    let mut best: Option<(usize, usize)> = None;
+    let consider = |best: &mut Option<(usize, usize)>, candidate: (usize, usize)| {
+        if best.is_none_or(|best| key(candidate) < key(best)) {
+            *best = Some(candidate);
+        }
+    };
+
+    // 末尾まで SYNC_LEN バイトない場合は、両方の残りが一致すればよい
+    for n in 0..SYNC_LEN.min(a_rest + 1).min(b_rest + 1) {
+        let (da, db) = (a_rest - n, b_rest - n);
+        if da <= MAX_SHIFT && db <= MAX_SHIFT && (da, db) != (0, 0) && a[da..] == b[db..] {
+            consider(&mut best, (da, db));
+        }
+    }
+
+    // b の各位置から始まる SYNC_LEN バイトを索引にして、
+    // a の各位置と一致するものを探す
+    let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (db, gram) in b.windows(SYNC_LEN).take(MAX_SHIFT + 1).enumerate() {
+        index.entry(gram).or_default().push(db);
+    }
+    for (da, gram) in a.windows(SYNC_LEN).take(MAX_SHIFT + 1).enumerate() {
+        if best.is_some_and(|best| key(best).0 < da) {
+            break;
+        }
+        let Some(positions) = index.get(gram) else {
+            continue;
+        };
+        // da に対して候補になるのは、
+        // 同じずれ・最小のずれ・da を超える最小のずれだけ
+        if positions.binary_search(&da).is_ok() {
+            consider(&mut best, (da, da));
+        }
+        if positions[0] < da {
+            consider(&mut best, (da, positions[0]));
+        }
+        if let Some(&db) = positions.get(positions.partition_point(|&p| p <= da)) {
+            consider(&mut best, (da, db));
+        }
+    }
+    best
+}
+
+/// resync で見つからない大きなずれを、b の ANCHOR_STRIDE バイトおきの
+/// ANCHOR_LEN バイトを索引にして探す
+/// 見つけた位置からは、
+/// 一致が続く限り手前に戻して一致の始まりを同期位置にする
+fn resync_wide(a: &[u8], b: &[u8]) -> Option<(usize, usize)> {
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    for (db, gram) in b.windows(ANCHOR_LEN).enumerate().step_by(ANCHOR_STRIDE) {
+        index.entry(gram).or_insert(db);
+    }
+    let mut best: Option<(usize, usize)> = None;
+    for (da, gram) in a.windows(ANCHOR_LEN).enumerate() {
+        if best.is_some_and(|(x, y)| x.max(y) < da) {
+            break;
+        }
+        let Some(&db) = index.get(gram) else {
+            continue;
+        };
+        let back = a[..da]
+            .iter()
+            .rev()
+            .zip(b[..db].iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count();
+        let candidate = (da - back, db - back);
+        if best.is_none_or(|(x, y)| candidate.0.max(candidate.1) < x.max(y)) {
+            best = Some(candidate);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunk(a: Range<usize>, b: Range<usize>) -> Hunk {
+        Hunk { a, b }
+    }
+
+    fn compare(a: &[u8], b: &[u8], alignment: Alignment) -> Vec<Hunk> {
+        let (a, b) = (Document::from_bytes(a.to_vec()), Document::from_bytes(b.to_vec()));
+        let mut comparer = Comparer::new(alignment);
+        while !comparer.is_done() {
+            comparer.step(&a, &b, Instant::now());
+        }
+        comparer.hunks
+    }
+
+    #[test]
+    fn test_compare_fixed() {
+        let a = b"0123456789";
+        let b = b"01x3yy6789AB";
+        assert_eq!(
+            compare(a, b, Alignment::Fixed),
+            vec![hunk(2..3, 2..3), hunk(4..6, 4..6), hunk(10..10, 10..12)]
+        );
+        assert_eq!(compare(b"abc", b"abx", Alignment::Fixed), vec![hunk(2..3, 2..3)]);
+        assert_eq!(compare(b"abc", b"abxd", Alignment::Fixed), vec![hunk(2..3, 2..4)]);
+        assert!(compare(b"same", b"same", Alignment::Fixed).is_empty());
+    }
+
+    #[test]
+    fn test_compare_aligned() {
+        let a = b"HEADER--0123456789abcdefghij";
+        // 3バイト挿入と1バイト置き換え
+        let b = b"HEADER--XYZ0123456789abcdXfghij";
+        assert_eq!(
+            compare(a, b, Alignment::Insertions),
+            vec![hunk(8..8, 8..11), hunk(22..23, 25..26)]
+        );
+        // 削除と末尾の追加
+        let c = b"HEADER--456789abcdefghij!!";
+        assert_eq!(
+            compare(a, c, Alignment::Insertions),
+            vec![hunk(8..12, 8..8), hunk(28..28, 24..26)]
+        );
+        // 固定オフセットでは挿入以降がすべて差分になる
+        assert_eq!(compare(a, b, Alignment::Fixed).len(), 1);
+    }
+
+    #[test]
+    fn test_compare_across_steps() {
+        // 読む単位の境界をまたぐ差分と、単位より大きいずれ
+        let a: Vec<u8> = (0..COMPARE_STEP * 3).map(|i| (i * 7 % 251) as u8).collect();
+        let mut b = a.clone();
+        b[COMPARE_STEP - 2..COMPARE_STEP + 2].fill(0xFF);
+        assert_eq!(
+            compare(&a, &b, Alignment::Fixed),
+            vec![hunk(COMPARE_STEP - 2..COMPARE_STEP + 2, COMPARE_STEP - 2..COMPARE_STEP + 2)]
+        );
+        b.splice(COMPARE_STEP * 2..COMPARE_STEP * 2, [0xEE; 1000]);
+        assert_eq!(
+            compare(&a, &b, Alignment::Insertions),
+            vec![
+                hunk(COMPARE_STEP - 2..COMPARE_STEP + 2, COMPARE_STEP - 2..COMPARE_STEP + 2),
+                hunk(COMPARE_STEP * 2..COMPARE_STEP * 2, COMPARE_STEP * 2..COMPARE_STEP * 2 + 1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compare_large_shift() {
+        // MAX_SHIFT より大きい挿入・削除も、WIDE_SHIFT までなら位置を合わせる
+        let mut seed = 1u32;
+        let a: Vec<u8> = (0..COMPARE_STEP)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let mut b = a.clone();
+        let inserted = MAX_SHIFT * 3;
+        b.splice(1000..1000, vec![0xEE; inserted]);
+        b.drain(40_000..40_000 + MAX_SHIFT * 2);
+        assert_eq!(
+            compare(&a, &b, Alignment::Insertions),
+            vec![
+                hunk(1000..1000, 1000..1000 + inserted),
+                hunk(40_000 - inserted..40_000 - inserted + MAX_SHIFT * 2, 40_000..40_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recompare_after_edit() {
+        let data: Vec<u8> = (0..COMPARE_STEP * 4).map(|i| (i * 13 % 251) as u8).collect();
+        let mut a = Document::from_bytes(data.clone());
+        let mut b = Document::from_bytes(data);
+        b.set_range(100, b"xyz").unwrap();
+        b.insert_bytes(COMPARE_STEP * 3, b"inserted").unwrap();
+        for alignment in [Alignment::Fixed, Alignment::Insertions] {
+            let mut comparer = Comparer::new(alignment);
+            while !comparer.is_done() {
+                comparer.step(&a, &b, Instant::now());
+            }
+            let revision = a.revision();
+            a.insert_bytes(200, b"!!").unwrap();
+            comparer.invalidate([a.changes_since(revision), None]);
+            let mut steps = 0;
+            while !comparer.is_done() {
+                comparer.step(&a, &b, Instant::now());
+                steps += 1;
+            }
+            // 挿入の直後で編集前の結果と対応が一致して、残りは引き継がれる
+            if alignment == Alignment::Insertions {
+                assert_eq!(steps, 2);
+            }
+            let expected = compare(
+                &a.get_range(0, a.len()).unwrap(),
+                &b.get_range(0, b.len()).unwrap(),
+                alignment,
+            );
+            assert_eq!(comparer.hunks, expected);
+            a.undo();
+        }
+    }
+}
@@ -1,220 +1,1318 @@
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use memmap2::{Mmap, MmapOptions};
+use regex::bytes::Regex;
 
 use super::BufferError;
+use super::codec::Fnv1a;
+use super::history::{self, History};
+use super::journal::{self, Edit, Journal};
+use super::numeric::Numeric;
+use super::search::Masked;
+
+/// これ以上の大きさのファイルはメモリマップで開く
+/// 小さいファイルは読み込む
+const MAP_THRESHOLD: u64 = 4 << 20;
+
+/// Undo 履歴を保存するファイルの大きさの上限
+/// 内容のハッシュを開く・保存するたびに求めるため
+const MAX_HISTORY_LEN: usize = 64 << 20;
+
+/// 覚えておく編集の数
+/// これより古いリビジョンからの変更は全体の変更とみなす
+const EDIT_LOG_LEN: usize = 1024;
+
+/// 検索時に一度に読み込むウィンドウサイズ
+const SEARCH_CHUNK: usize = 1 << 20;
+
+/// 正規表現の検索で、
+/// チャンクの境界をまたぐ一致を拾うために重ねて読むバイト数
+/// 一致がウィンドウの終わりからこれより近くまで届いていれば、
+/// ウィンドウを広げて探し直す
+pub(super) const REGEX_OVERLAP: usize = 64 << 10;
+
+/// 正規表現の検索で、
+/// `\b` や `(?m)^` が直前の文字を見られるようにチャンクの手前から読むバイト数
+const REGEX_CONTEXT: usize = 16;
 
 /// Undo/Redo用の操作記録
 #[derive(Debug, Clone)]
-enum UndoOp {
+pub(super) enum UndoOp {
     /// バイトの上書き (位置, 旧値, 新値)
     Set(usize, u8, u8),
-    /// バイトの挿入 (位置, 値)
-    Insert(usize, u8),
-    /// バイトの削除 (位置, 値)
-    Delete(usize, u8),
+    /// バイト列の上書き (位置, 旧値, 新値)
+    SetRange(usize, Vec<u8>, Vec<u8>),
+    /// バイト列の挿入 (位置, 値)
+    InsertRange(usize, Vec<u8>),
+    /// バイト列の削除 (位置, 値)
+    DeleteRange(usize, Vec<u8>),
+    /// 1回のUndoでまとめて取り消す操作列（適用順）
+    Group(Vec<UndoOp>),
+}
+
+impl UndoOp {
+    /// 操作の先頭位置
+    fn pos(&self) -> usize {
+        match self {
+            Self::Set(pos, ..)
+            | Self::SetRange(pos, ..)
+            | Self::InsertRange(pos, _)
+            | Self::DeleteRange(pos, _) => *pos,
+            Self::Group(ops) => ops.first().map_or(0, Self::pos),
+        }
+    }
+
+    /// 長さ len のドキュメントに対して行った操作なら、その前の長さ
+    /// 範囲外なら None
+    fn len_before(&self, len: usize) -> Option<usize> {
+        match self {
+            Self::Set(pos, ..) => (*pos < len).then_some(len),
+            Self::SetRange(pos, old, new) => {
+                (old.len() == new.len() && pos.checked_add(new.len())? <= len).then_some(len)
+            }
+            Self::InsertRange(pos, bytes) => {
+                let before = len.checked_sub(bytes.len())?;
+                (*pos <= before).then_some(before)
+            }
+            Self::DeleteRange(pos, bytes) => (*pos <= len).then_some(len.checked_add(bytes.len())?),
+            Self::Group(ops) => ops.iter().rev().try_fold(len, |len, op| op.len_before(len)),
+        }
+    }
+
+    /// 長さ len のドキュメントにこの操作を行った後の長さ（範囲外なら None）
+    fn len_after(&self, len: usize) -> Option<usize> {
+        match self {
+            Self::Set(pos, ..) => (*pos < len).then_some(len),
+            Self::SetRange(pos, old, new) => {
+                (old.len() == new.len() && pos.checked_add(new.len())? <= len).then_some(len)
+            }
+            Self::InsertRange(pos, bytes) => (*pos <= len).then_some(len.checked_add(bytes.len())?),
+            Self::DeleteRange(pos, bytes) => {
+                (pos.checked_add(bytes.len())? <= len).then(|| len - bytes.len())
+            }
+            Self::Group(ops) => ops.iter().try_fold(len, |len, op| op.len_after(len)),
+        }
+    }
+}
+
+/// ピースの参照先バッファ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    /// 読み込み時のデータ（変更しない）
+    Original,
+    /// 編集で追加されたデータ（追記のみ）
+    Add,
+}
+
+/// 保存方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SaveStrategy {
+    /// 一時ファイルに書き込んでからリネームで置き換える
+    #[default]
+    Atomic,
+    /// 変更されたバイト範囲だけを元のファイルに直接書き込む
+    /// ブロックデバイスや、他のプロセスが開いていて
+    /// inode を保つ必要があるファイル用。
+    /// 長さが変わる編集はできない
+    InPlace,
+}
+
+/// ディスク上のファイルの状態（外部での変更の検出用）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DiskStamp {
+    mtime: Option<SystemTime>,
+    size: u64,
+    inode: u64,
+}
+
+impl DiskStamp {
+    /// 現在のファイルの状態を取得
+    fn read(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&meta);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Some(Self {
+            mtime: meta.modified().ok(),
+            size: meta.len(),
+            inode,
+        })
+    }
+}
+
+/// バッファとディスク上のファイルの比較結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskDiff {
+    /// 最初に異なる位置（同一なら None）
+    pub first: Option<usize>,
+    /// 共通の長さの範囲で異なるバイト数
+    pub differing: usize,
+    /// ディスク上のファイルの長さ
+    pub disk_len: usize,
+}
+
+/// あるリビジョン以降の編集をまとめた範囲
+/// start より前は変わっておらず、
+/// end（編集後の位置）以降は編集前の位置から delta だけずれている
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EditSpan {
+    pub start: usize,
+    pub end: usize,
+    pub delta: isize,
+}
+
+impl EditSpan {
+    /// pos から removed バイトを inserted バイトに置き換える編集の範囲
+    pub fn new(pos: usize, removed: usize, inserted: usize) -> Self {
+        Self {
+            start: pos,
+            end: pos + inserted,
+            delta: inserted as isize - removed as isize,
+        }
+    }
+
+    /// この後に next の編集をした時の、まとめた範囲
+    pub fn then(self, next: Self) -> Self {
+        let next_old_end = (next.end as isize - next.delta) as usize;
+        Self {
+            start: self.start.min(next.start),
+            end: self.end.max(next_old_end) - next_old_end + next.end,
+            delta: self.delta + next.delta,
+        }
+    }
+}
+
+/// 読み込み時のデータ
+enum Original {
+    /// メモリ上のバイト列（標準入力・新規バッファ・小さいファイル等）
+    Bytes(Vec<u8>),
+    /// 読み取り専用でマップしたファイル（参照したページだけが読み込まれる）
+    Mapped(MappedFile),
+}
+
+impl Original {
+    /// メモリ上のバイト列を書き換え用に取得（マップを参照している間は None）
+    fn bytes_mut(&mut self) -> Option<&mut Vec<u8>> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            Self::Mapped(mapped) => mapped.copy.get_mut(),
+        }
+    }
+
+    /// 自分でファイルに書き込んだ後、
+    /// その状態を外部での変更とみなさないよう記録し直す
+    fn restamp(&mut self) {
+        if let Self::Mapped(mapped) = self {
+            mapped.state = FileState::read(&mapped.file);
+        }
+    }
+}
+
+impl Deref for Original {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Bytes(bytes) => bytes,
+            Self::Mapped(mapped) => mapped.data(),
+        }
+    }
+}
+
+/// 開いているファイルの状態（同じ inode への外部の書き込みの検出用）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileState {
+    mtime: Option<SystemTime>,
+    size: u64,
+}
+
+impl FileState {
+    /// ファイルの状態を取得（取得できなければ None）
+    fn read(file: &File) -> Option<Self> {
+        let meta = file.metadata().ok()?;
+        Some(Self {
+            mtime: meta.modified().ok(),
+            size: meta.len(),
+        })
+    }
+}
+
+/// マップしたファイル
+///
+/// 共有マップは外部プロセスによる同じファイルへの書き込みがそのまま見え、
+/// 切り詰められると末尾を超えた参照で SIGBUS を受ける。
+/// そのためイベント処理の合間にファイルの状態を確かめ（check）、
+/// 変わっていればマップを使うのをやめて、
+/// その時点の内容を read で読み込んだコピーに切り替える。
+/// 確かめてから次に確かめるまでの間に切り詰められた場合は防げない
+struct MappedFile {
+    map: Mmap,
+    file: File,
+    /// マップした時点（または自分で書き込んだ後）のファイルの状態
+    state: Option<FileState>,
+    /// 変更を検出した後に使う内容のコピー
+    copy: OnceCell<Vec<u8>>,
+}
+
+impl MappedFile {
+    /// 現在参照してよい内容
+    fn data(&self) -> &[u8] {
+        self.copy.get().map_or(&self.map, |copy| copy)
+    }
+
+    /// ファイルの状態が変わっていれば、マップをやめてコピーに切り替える
+    fn check(&self) {
+        if self.copy.get().is_none() && FileState::read(&self.file) != self.state {
+            self.copy.get_or_init(|| self.read_copy());
+        }
+    }
+
+    /// ファイルを read で読み込む
+    /// 切り詰められて失われた部分は、ピースの範囲を保つため 0 で埋める
+    fn read_copy(&self) -> Vec<u8> {
+        let len = self.map.len();
+        let mut data = Vec::with_capacity(len);
+        let mut file = &self.file;
+        if file.seek(SeekFrom::Start(0)).is_ok() {
+            let _ = file.take(len as u64).read_to_end(&mut data);
+        }
+        data.resize(len, 0);
+        data
+    }
+}
+
+/// ピーステーブルの1要素（参照先バッファの連続領域）
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    source: Source,
+    start: usize,
+    len: usize,
 }
 
 /// バイナリドキュメントを表す構造体
+///
+/// データはピーステーブルで保持する。読み込み時のデータ（original）と
+/// 編集で追加されたデータ（add）を並べ替えずに参照するため、
+/// 挿入・削除のコストはファイルサイズではなくピース数に比例する。
+/// 大きいファイルはメモリマップで開くので、
+/// 巨大なファイルでも開く時点では読み込まない。
 #[allow(dead_code)]
 pub struct Document {
     /// ファイルパス
     path: Option<PathBuf>,
-    /// バッファデータ
-    data: Vec<u8>,
-    /// 変更フラグ
-    modified: bool,
+    /// 読み込み時のデータ
+    original: Original,
+    /// 追加データ
+    add: Vec<u8>,
+    /// ピース列（論理順）
+    pieces: Vec<Piece>,
+    /// 各ピースの論理開始位置（pieces と同じ長さ）
+    starts: Vec<usize>,
+    /// 論理データ長
+    len: usize,
+    /// 保存時点の Undo 履歴の深さ（保存した状態が履歴から失われたら None）
+    save_point: Option<usize>,
     /// 読み取り専用フラグ
     readonly: bool,
+    /// 保存方法
+    save_strategy: SaveStrategy,
+    /// 読み込み・保存時のファイルの状態
+    disk_stamp: Option<DiskStamp>,
+    /// 初回保存時にバックアップファイル（file~）を作るか
+    make_backup: bool,
+    /// このセッションでバックアップを作成済みか
+    backup_done: bool,
+    /// 保存時に Undo 履歴を書き出し、次に開いた時に読み戻すか
+    persistent_undo: bool,
+    /// 最後の保存で Undo 履歴を書き出せなかった理由
+    history_error: Option<BufferError>,
     /// Undo履歴
     undo_stack: Vec<UndoOp>,
     /// Redo履歴
     redo_stack: Vec<UndoOp>,
+    /// 記録中のグループ（begin_group 〜 commit_group の間の操作）
+    group: Vec<UndoOp>,
+    /// begin_group のネスト数
+    group_depth: usize,
+    /// 内容が変わるたびに増える番号（自動保存の要否の判定用）
+    revision: u64,
+    /// 最近の編集（リビジョン、範囲）
+    edit_log: VecDeque<(u64, EditSpan)>,
 }
 
 #[allow(dead_code)]
 impl Document {
     /// 空のドキュメントを作成
     pub fn new() -> Self {
-        Self {
-            path: None,
-            data: Vec::new(),
-            modified: false,
-            readonly: false,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
-        }
+        Self::from_bytes(Vec::new())
     }
 
     /// バイト列から作成
     pub fn from_bytes(data: Vec<u8>) -> Self {
-        Self {
+        Self::with_original(Original::Bytes(data))
+    }
+
+    /// 読み込み時のデータから作成
+    fn with_original(original: Original) -> Self {
+        let mut doc = Self {
             path: None,
-            data,
-            modified: false,
+            original,
+            add: Vec::new(),
+            pieces: Vec::new(),
+            starts: Vec::new(),
+            len: 0,
+            save_point: Some(0),
             readonly: false,
+            save_strategy: SaveStrategy::Atomic,
+            disk_stamp: None,
+            make_backup: false,
+            backup_done: false,
+            persistent_undo: false,
+            history_error: None,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
-        }
+            group: Vec::new(),
+            group_depth: 0,
+            revision: 0,
+            edit_log: VecDeque::new(),
+        };
+        doc.reset_pieces();
+        doc
     }
 
     /// ファイルから読み込み
+    /// 大きい通常ファイルは読み取り専用でメモリマップし、
+    /// 編集内容は追加バッファに積む
+    /// ブロックデバイス・キャラクタデバイスは直接書き込みモードで開く
     pub fn open(path: impl Into<PathBuf>) -> Result<Self, BufferError> {
         let path = path.into();
-        let mut file = File::open(&path)?;
-        let mut data = Vec::new();
-        file.read_to_end(&mut data)?;
+        let original = Self::load(&path)?;
+        let mut doc = Self::with_original(original);
+        if is_device(&path) {
+            doc.save_strategy = SaveStrategy::InPlace;
+        }
+        doc.disk_stamp = DiskStamp::read(&path);
+        doc.path = Some(path);
+        Ok(doc)
+    }
 
-        Ok(Self {
-            path: Some(path),
-            data,
-            modified: false,
-            readonly: false,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+    /// マップしたファイルが外部で書き換えられていないか確かめ、
+    /// 変わっていればその時点の内容のコピーに切り替える
+    /// 参照のたびではなく、イベント処理の合間に呼ぶ
+    pub fn check_mapping(&self) {
+        if let Original::Mapped(mapped) = &self.original {
+            mapped.check();
+        }
+    }
+
+    /// 読み込み・保存した後にファイルが外部で変更されたかどうか
+    /// mtime・サイズ・inode のいずれかが変わっていれば変更とみなす
+    pub fn changed_on_disk(&self) -> bool {
+        match (&self.path, self.disk_stamp) {
+            (Some(path), Some(stamp)) => {
+                DiskStamp::read(path).is_some_and(|current| current != stamp)
+            }
+            _ => false,
+        }
+    }
+
+    /// 外部での変更を受け入れたものとして、現在のファイルの状態を記録し直す
+    pub fn acknowledge_disk_change(&mut self) {
+        if let Some(path) = &self.path {
+            self.disk_stamp = DiskStamp::read(path);
+        }
+    }
+
+    /// ディスク上のファイルを読み直す（編集内容と Undo 履歴は破棄する）
+    pub fn revert(&mut self) -> Result<(), BufferError> {
+        let Some(path) = self.path.clone() else {
+            return Err(BufferError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "No file path set",
+            )));
+        };
+        self.original = Self::load(&path)?;
+        self.reset_pieces();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.group.clear();
+        self.save_point = Some(0);
+        self.disk_stamp = DiskStamp::read(&path);
+        Ok(())
+    }
+
+    /// バッファの内容とディスク上のファイルを比較する
+    /// バッファ側は読み込み時の内容に対する編集結果で、
+    /// 外部の書き込みは含まない
+    /// マップした大きいファイルは、変更を検出した時点で写したコピーを使う
+    pub fn diff_with_disk(&self) -> Result<DiskDiff, BufferError> {
+        let Some(path) = &self.path else {
+            return Err(BufferError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "No file path set",
+            )));
+        };
+        // マップではなく read で読む
+        // 切り詰められたファイルでも安全に比較できる
+        let mut file = File::open(path)?;
+        let mut buf = vec![0u8; SEARCH_CHUNK];
+        let mut diff = DiskDiff { first: None, differing: 0, disk_len: 0 };
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            let pos = diff.disk_len;
+            let end = (pos + n).min(self.len);
+            if pos < end {
+                let ours = self.get_range(pos, end).unwrap_or_default();
+                for (i, (a, b)) in ours.iter().zip(&buf[..n]).enumerate() {
+                    if a != b {
+                        diff.first.get_or_insert(pos + i);
+                        diff.differing += 1;
+                    }
+                }
+            }
+            diff.disk_len += n;
+        }
+        if diff.first.is_none() && diff.disk_len != self.len {
+            diff.first = Some(diff.disk_len.min(self.len));
+        }
+        Ok(diff)
+    }
+
+    // === 永続 Undo 履歴 ===
+
+    /// 永続 Undo 履歴を使うかを設定する
+    /// 有効にした時、ファイルの内容が前回の保存時と同じなら履歴を読み戻す
+    /// 戻り値: 読み戻した Undo の段数
+    pub fn set_persistent_undo(&mut self, enabled: bool) -> Result<usize, BufferError> {
+        self.persistent_undo = enabled;
+        let Some(path) = self.path.clone() else {
+            return Ok(0);
+        };
+        if !enabled
+            || !self.undo_stack.is_empty()
+            || !self.redo_stack.is_empty()
+            || self.len > MAX_HISTORY_LEN
+        {
+            return Ok(0);
+        }
+        let Some(history) = history::read(&path)? else {
+            return Ok(0);
+        };
+        if self.is_modified() || history.content_hash != self.content_hash() {
+            return Ok(0);
+        }
+        // 書き換えられた履歴で、
+        // Undo・Redo がドキュメントの範囲外に触れないか確かめる
+        let undo = history.undo.iter().rev().try_fold(self.len, |len, op| op.len_before(len));
+        let redo = history.redo.iter().rev().try_fold(self.len, |len, op| op.len_after(len));
+        if undo.is_none() || redo.is_none() {
+            return Err(BufferError::Corrupt("undo history out of range"));
+        }
+        self.undo_stack = history.undo;
+        self.redo_stack = history.redo;
+        self.save_point = Some(self.undo_stack.len());
+        Ok(self.undo_stack.len())
+    }
+
+    /// 現在の内容のハッシュ
+    fn content_hash(&self) -> u64 {
+        let mut hash = Fnv1a::new();
+        for chunk in self.chunks(0, self.len) {
+            hash.update(chunk);
+        }
+        hash.finish()
+    }
+
+    /// 保存直後の Undo 履歴を書き出す（大きすぎるファイルの履歴は保存しない）
+    fn write_history(&mut self, path: &Path) {
+        if !self.persistent_undo || self.len > MAX_HISTORY_LEN {
+            return;
+        }
+        let history = History {
+            content_hash: self.content_hash(),
+            undo: self.undo_stack.clone(),
+            redo: self.redo_stack.clone(),
+        };
+        // 履歴は補助的なものなので、書けなくても保存自体は成功とする
+        self.history_error = history::write(path, &history).err();
+    }
+
+    /// 最後の保存で Undo 履歴を書き出せなかった理由（一度だけ返す）
+    pub fn take_history_error(&mut self) -> Option<BufferError> {
+        self.history_error.take()
+    }
+
+    // === 自動保存ジャーナル ===
+
+    /// 内容が変わるたびに増える番号
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// revision 以降の編集をまとめた範囲（変わっていなければ None）
+    /// 編集の記録が残っていない古いリビジョンなら全体が変わったものとみなす
+    pub fn changes_since(&self, revision: u64) -> Option<EditSpan> {
+        if revision == self.revision {
+            return None;
+        }
+        let logged = self.edit_log.front().is_some_and(|&(first, _)| first <= revision + 1);
+        if revision > self.revision || !logged {
+            return Some(EditSpan { start: 0, end: self.len, delta: 0 });
+        }
+        self.edit_log
+            .iter()
+            .filter(|&&(rev, _)| rev > revision)
+            .map(|&(_, span)| span)
+            .reduce(EditSpan::then)
+    }
+
+    /// リビジョンを進めて、編集した範囲を記録する
+    fn bump_revision(&mut self, pos: usize, removed: usize, inserted: usize) {
+        self.revision += 1;
+        if self.edit_log.len() == EDIT_LOG_LEN {
+            self.edit_log.pop_front();
+        }
+        self.edit_log.push_back((self.revision, EditSpan::new(pos, removed, inserted)));
+    }
+
+    /// ディスク上の内容からの差分をジャーナルに書き出す
+    /// 戻り値: 書き込んだジャーナルのパス
+    /// ファイルに対応しないバッファは None
+    pub fn write_journal(&self) -> Result<Option<PathBuf>, BufferError> {
+        let (Some(path), Some(stamp)) = (&self.path, self.disk_stamp) else {
+            return Ok(None);
+        };
+        let journal = Journal {
+            size: stamp.size,
+            mtime: stamp.mtime,
+            edits: self.journal_edits(),
+        };
+        journal::write(path, &journal).map(Some)
+    }
+
+    /// このファイルの既存のジャーナルのパス
+    pub fn find_journal(&self) -> Option<PathBuf> {
+        journal::find(self.path.as_ref()?)
+    }
+
+    /// ジャーナルを削除する
+    pub fn remove_journal(&self) {
+        if let Some(path) = &self.path {
+            journal::remove(path);
+        }
+    }
+
+    /// ジャーナルの編集を1回の Undo で取り消せる操作として適用する
+    /// バッファがディスク上の内容のままの時だけ適用できる
+    /// 戻り値: 最初に変更された位置（変更がなければ None）
+    pub fn recover_journal(&mut self) -> Result<Option<usize>, BufferError> {
+        let Some(journal_path) = self.find_journal() else {
+            return Err(BufferError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "No auto-save journal",
+            )));
+        };
+        let journal = journal::read(&journal_path)?;
+        let stamp = self.disk_stamp.ok_or(BufferError::JournalMismatch)?;
+        if journal.size != stamp.size
+            || journal.mtime != stamp.mtime
+            || journal.size != self.original.len() as u64
+        {
+            return Err(BufferError::JournalMismatch);
+        }
+        if !self.journal_edits().is_empty() {
+            return Err(BufferError::Modified);
+        }
+
+        self.edit_group(|doc| {
+            // 前の編集で位置がずれた分を足しながら適用する
+            let mut delta = 0isize;
+            for edit in &journal.edits {
+                let pos = edit
+                    .start
+                    .checked_add_signed(delta)
+                    .ok_or(BufferError::Corrupt("bad offset"))?;
+                let removed = edit.end - edit.start;
+                if removed == edit.bytes.len() {
+                    doc.set_range(pos, &edit.bytes)?;
+                } else {
+                    if removed > 0 {
+                        doc.delete_range(pos, pos + removed)?;
+                    }
+                    doc.insert_bytes(pos, &edit.bytes)?;
+                }
+                delta += edit.bytes.len() as isize - removed as isize;
+            }
+            Ok(journal.edits.first().map(|edit| edit.start))
         })
     }
 
+    /// ピース列を「ディスク上の範囲の置き換え」の列に変換する
+    fn journal_edits(&self) -> Vec<Edit> {
+        let mut edits = Vec::new();
+        // 次に現れるはずの元データの位置と、そこまでに挟まった追加データ
+        let mut orig_pos = 0;
+        let mut pending: Vec<u8> = Vec::new();
+        for piece in &self.pieces {
+            match piece.source {
+                Source::Original => {
+                    if piece.start != orig_pos || !pending.is_empty() {
+                        edits.push(Edit {
+                            start: orig_pos,
+                            end: piece.start,
+                            bytes: std::mem::take(&mut pending),
+                        });
+                    }
+                    orig_pos = piece.start + piece.len;
+                }
+                Source::Add => {
+                    pending.extend_from_slice(&self.add[piece.start..piece.start + piece.len]);
+                }
+            }
+        }
+        if orig_pos != self.original.len() || !pending.is_empty() {
+            edits.push(Edit {
+                start: orig_pos,
+                end: self.original.len(),
+                bytes: pending,
+            });
+        }
+        edits
+    }
+
+    /// ファイルの内容をマップする
+    /// 小さいファイルやマップできない場合は読み込む
+    fn load(path: &Path) -> Result<Original, BufferError> {
+        let mut file = File::open(path)?;
+        let meta = file.metadata()?;
+        // デバイスはメタデータにサイズがないので末尾までシークして求める
+        let size = if meta.is_file() {
+            meta.len()
+        } else if is_block_device(&meta) {
+            let size = file.seek(SeekFrom::End(0))?;
+            file.seek(SeekFrom::Start(0))?;
+            size
+        } else if is_char_device(&meta) {
+            // /dev/zero や端末は終わりがないので、
+            // シークでサイズが分かるもの（MTD など）だけ開く
+            let size = file.seek(SeekFrom::End(0)).unwrap_or(0);
+            if size == 0 {
+                return Err(BufferError::UnsizedDevice);
+            }
+            file.seek(SeekFrom::Start(0))?;
+            let mut data = Vec::new();
+            file.take(size).read_to_end(&mut data)?;
+            return Ok(Original::Bytes(data));
+        } else {
+            0
+        };
+        // 小さいファイルは読み込んでしまい、
+        // 外部での変更の影響を受けないようにする
+        // （空ファイルはマップできないプラットフォームもある）
+        if size >= MAP_THRESHOLD {
+            // SAFETY: 読み取り専用のマップ。
+            // 外部での変更は check_mapping で検出してコピーに切り替えるが、
+            // 検出するまでの間に切り詰められると SIGBUS を受ける
+            // （共有マップでは避けられない）
+            if let Ok(map) = unsafe { MmapOptions::new().len(size as usize).map(&file) } {
+                let state = FileState::read(&file);
+                return Ok(Original::Mapped(MappedFile {
+                    map,
+                    file,
+                    state,
+                    copy: OnceCell::new(),
+                }));
+            }
+        }
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Original::Bytes(data))
+    }
+
     /// ファイルに保存
+    /// 同じディレクトリの一時ファイルに書き込んでから置き換えるので、
+    /// 書き込み途中で失敗しても元のファイルは壊れない
     pub fn save(&mut self) -> Result<(), BufferError> {
-        if let Some(ref path) = self.path {
-            let mut file = File::create(path)?;
-            file.write_all(&self.data)?;
-            self.modified = false;
-            Ok(())
-        } else {
-            Err(BufferError::Io(std::io::Error::new(
+        let Some(path) = self.path.clone() else {
+            return Err(BufferError::Io(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
                 "No file path set",
-            )))
+            )));
+        };
+        if self.readonly {
+            return Err(BufferError::ReadOnly);
+        }
+        if self.save_strategy == SaveStrategy::InPlace {
+            self.write_in_place(&path)?;
+            self.mark_saved();
+            self.disk_stamp = DiskStamp::read(&path);
+            journal::remove(&path);
+            self.write_history(&path);
+            return Ok(());
+        }
+
+        // シンボリックリンクはリンク先を置き換える
+        let target = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+
+        if self.make_backup && !self.backup_done && target.exists() {
+            Self::write_backup(&target)?;
+            self.backup_done = true;
+        }
+        self.write_atomic(&target)?;
+
+        // 保存したファイルを新しい元データとしてマップし直す
+        // 保存は済んでいるので、開き直せなければ書き込んだ内容をそのまま使う
+        self.original = match Self::load(&target) {
+            Ok(original) => original,
+            Err(_) => Original::Bytes(self.get_range(0, self.len).unwrap_or_default().into_owned()),
+        };
+        self.reset_pieces();
+        self.mark_saved();
+        self.disk_stamp = DiskStamp::read(&target);
+        journal::remove(&path);
+        self.write_history(&path);
+        Ok(())
+    }
+
+    /// 変更されたバイト範囲だけを pwrite で書き込む
+    /// 切り詰め・置き換えはしない
+    fn write_in_place(&mut self, path: &Path) -> Result<(), BufferError> {
+        if self.len != self.original.len() {
+            return Err(BufferError::LengthChange);
+        }
+        let file = OpenOptions::new().write(true).open(path)?;
+        for (piece, &pos) in self.pieces.iter().zip(&self.starts) {
+            // 元データの同じ位置を指すピースは変更されていない
+            if piece.source == Source::Original && piece.start == pos {
+                continue;
+            }
+            let bytes = &self.source(piece.source)[piece.start..piece.start + piece.len];
+            write_at(&file, bytes, pos as u64)?;
+        }
+        file.sync_all()?;
+
+        // マップしたデータは書き込みが反映されるので、
+        // メモリ上のデータだけ更新する
+        self.original.restamp();
+        if let Some(data) = self.original.bytes_mut() {
+            for (piece, &pos) in self.pieces.iter().zip(&self.starts) {
+                if piece.source == Source::Add {
+                    data[pos..pos + piece.len]
+                        .copy_from_slice(&self.add[piece.start..piece.start + piece.len]);
+                }
+            }
+        }
+        self.reset_pieces();
+        Ok(())
+    }
+
+    /// 保存方法を取得
+    pub fn save_strategy(&self) -> SaveStrategy {
+        self.save_strategy
+    }
+
+    /// 保存方法を設定
+    /// 直接書き込みモードは、
+    /// 長さが読み込み時から変わっていない場合のみ選べる
+    pub fn set_save_strategy(&mut self, strategy: SaveStrategy) -> Result<(), BufferError> {
+        if strategy == SaveStrategy::InPlace && self.len != self.original.len() {
+            return Err(BufferError::LengthChange);
         }
+        self.save_strategy = strategy;
+        Ok(())
+    }
+
+    /// 一時ファイルに書き出して fsync し、
+    /// 元の権限を引き継いでからリネームする
+    fn write_atomic(&self, target: &Path) -> Result<(), BufferError> {
+        let dir = match target.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = target.file_name().and_then(|s| s.to_str()).unwrap_or("ehx");
+        let tmp_path = dir.join(format!(".{}.ehx-save-{}", name, std::process::id()));
+        let original_meta = fs::metadata(target).ok();
+
+        let result = (|| -> Result<(), BufferError> {
+            let file = OpenOptions::new().write(true).create_new(true).open(&tmp_path)?;
+            let mut writer = BufWriter::new(file);
+            for chunk in self.chunks(0, self.len) {
+                writer.write_all(chunk)?;
+            }
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+
+            if let Some(meta) = &original_meta {
+                fs::set_permissions(&tmp_path, meta.permissions())?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::MetadataExt;
+                    // 所有者の変更は権限がなければ失敗するが、保存自体は続ける
+                    let _ = std::os::unix::fs::chown(&tmp_path, Some(meta.uid()), Some(meta.gid()));
+                }
+            }
+
+            fs::rename(&tmp_path, target)?;
+            // リネームを永続化するためディレクトリも同期する
+            #[cfg(unix)]
+            if let Ok(dir) = File::open(dir) {
+                let _ = dir.sync_all();
+            }
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    /// Emacs 風のバックアップファイル（file~）を作成する
+    /// 可能ならハードリンクにして、巨大なファイルでもコピーしない
+    fn write_backup(target: &Path) -> Result<(), BufferError> {
+        let mut backup = target.as_os_str().to_owned();
+        backup.push("~");
+        let backup = PathBuf::from(backup);
+        let _ = fs::remove_file(&backup);
+        if fs::hard_link(target, &backup).is_err() {
+            fs::copy(target, &backup)?;
+        }
+        Ok(())
+    }
+
+    /// 保存時にバックアップファイルを作るかを設定
+    pub fn set_make_backup(&mut self, make_backup: bool) {
+        self.make_backup = make_backup;
     }
 
     /// 別名で保存
+    /// 新しいファイルには全体を書き出す必要があるので、
+    /// デバイス以外は置き換え保存にする
     pub fn save_as(&mut self, path: impl Into<PathBuf>) -> Result<(), BufferError> {
-        self.path = Some(path.into());
+        let path = path.into();
+        if !is_device(&path) {
+            self.save_strategy = SaveStrategy::Atomic;
+        }
+        self.path = Some(path);
         self.save()
     }
 
     /// データの長さを取得
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.len
     }
 
     /// データが空かどうか
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.len == 0
     }
 
     /// 指定位置のバイトを取得
     pub fn get(&self, pos: usize) -> Option<u8> {
-        self.data.get(pos).copied()
+        if pos >= self.len {
+            return None;
+        }
+        let (idx, off) = self.locate(pos);
+        let piece = self.pieces[idx];
+        Some(self.source(piece.source)[piece.start + off])
     }
 
     /// 指定範囲のバイト列を取得
-    pub fn get_range(&self, start: usize, end: usize) -> Option<&[u8]> {
-        if start <= end && end <= self.data.len() {
-            Some(&self.data[start..end])
-        } else {
-            None
+    /// 範囲が1つのピースに収まる場合はコピーせずに参照を返す
+    pub fn get_range(&self, start: usize, end: usize) -> Option<Cow<'_, [u8]>> {
+        if start > end || end > self.len {
+            return None;
         }
+        let mut chunks = self.chunks(start, end);
+        let Some(first) = chunks.next() else {
+            return Some(Cow::Borrowed(&[]));
+        };
+        if first.len() == end - start {
+            return Some(Cow::Borrowed(first));
+        }
+        let mut buf = Vec::with_capacity(end - start);
+        buf.extend_from_slice(first);
+        for chunk in chunks {
+            buf.extend_from_slice(chunk);
+        }
+        Some(Cow::Owned(buf))
+    }
+
+    /// 指定範囲をピース単位の連続スライスとして順に返す
+    pub fn chunks(&self, start: usize, end: usize) -> impl Iterator<Item = &[u8]> + '_ {
+        let end = end.min(self.len);
+        let first = if start < end { self.locate(start).0 } else { self.pieces.len() };
+        self.pieces[first..]
+            .iter()
+            .zip(&self.starts[first..])
+            .take_while(move |&(_, &piece_start)| piece_start < end)
+            .map(move |(piece, &piece_start)| {
+                let from = start.max(piece_start) - piece_start;
+                let to = end.min(piece_start + piece.len) - piece_start;
+                &self.source(piece.source)[piece.start + from..piece.start + to]
+            })
     }
 
     /// 指定位置のバイトを設定
     pub fn set(&mut self, pos: usize, value: u8) -> Result<(), BufferError> {
-        if pos < self.data.len() {
-            let old_value = self.data[pos];
-            if old_value != value {
-                self.data[pos] = value;
-                self.modified = true;
-                self.undo_stack.push(UndoOp::Set(pos, old_value, value));
-                self.redo_stack.clear();
-            }
-            Ok(())
-        } else {
-            Err(BufferError::OutOfBounds(pos))
+        if self.readonly {
+            return Err(BufferError::ReadOnly);
+        }
+        let Some(old_value) = self.get(pos) else {
+            return Err(BufferError::OutOfBounds(pos));
+        };
+        if old_value != value {
+            self.raw_set_range(pos, &[value]);
+            self.record(UndoOp::Set(pos, old_value, value));
+        }
+        Ok(())
+    }
+
+    /// 指定位置からバイト列を上書き
+    pub fn set_range(&mut self, pos: usize, bytes: &[u8]) -> Result<(), BufferError> {
+        if self.readonly {
+            return Err(BufferError::ReadOnly);
+        }
+        let end = pos + bytes.len();
+        let Some(old) = self.get_range(pos, end).map(Cow::into_owned) else {
+            return Err(BufferError::OutOfBounds(end));
+        };
+        if old != bytes {
+            self.raw_set_range(pos, bytes);
+            self.record(UndoOp::SetRange(pos, old, bytes.to_vec()));
         }
+        Ok(())
     }
 
     /// 指定位置にバイトを挿入
     pub fn insert(&mut self, pos: usize, value: u8) -> Result<(), BufferError> {
-        if pos <= self.data.len() {
-            self.data.insert(pos, value);
-            self.modified = true;
-            self.undo_stack.push(UndoOp::Insert(pos, value));
-            self.redo_stack.clear();
-            Ok(())
-        } else {
-            Err(BufferError::OutOfBounds(pos))
+        self.insert_bytes(pos, &[value])
+    }
+
+    /// 指定位置にバイト列を挿入
+    pub fn insert_bytes(&mut self, pos: usize, bytes: &[u8]) -> Result<(), BufferError> {
+        if self.readonly {
+            return Err(BufferError::ReadOnly);
+        }
+        if pos > self.len {
+            return Err(BufferError::OutOfBounds(pos));
+        }
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        if self.save_strategy == SaveStrategy::InPlace {
+            return Err(BufferError::LengthChange);
         }
+        self.raw_insert(pos, bytes);
+        self.record(UndoOp::InsertRange(pos, bytes.to_vec()));
+        Ok(())
     }
 
     /// 指定位置のバイトを削除
     pub fn delete(&mut self, pos: usize) -> Result<u8, BufferError> {
-        if pos < self.data.len() {
-            let value = self.data.remove(pos);
-            self.modified = true;
-            self.undo_stack.push(UndoOp::Delete(pos, value));
-            self.redo_stack.clear();
-            Ok(value)
-        } else {
-            Err(BufferError::OutOfBounds(pos))
+        if pos >= self.len {
+            return Err(BufferError::OutOfBounds(pos));
         }
+        let removed = self.delete_range(pos, pos + 1)?;
+        Ok(removed[0])
     }
 
-    /// Undo: 直前の操作を取り消す
-    /// 戻り値: (成功したか, 影響を受けた位置)
-    pub fn undo(&mut self) -> Option<usize> {
-        let op = self.undo_stack.pop()?;
-        let pos = match op {
-            UndoOp::Set(pos, old_value, new_value) => {
-                self.data[pos] = old_value;
-                self.redo_stack.push(UndoOp::Set(pos, old_value, new_value));
-                pos
+    /// 指定範囲 [start, end) のバイト列を削除
+    pub fn delete_range(&mut self, start: usize, end: usize) -> Result<Vec<u8>, BufferError> {
+        if self.readonly {
+            return Err(BufferError::ReadOnly);
+        }
+        if start > end || end > self.len {
+            return Err(BufferError::OutOfBounds(end));
+        }
+        if start == end {
+            return Ok(Vec::new());
+        }
+        if self.save_strategy == SaveStrategy::InPlace {
+            return Err(BufferError::LengthChange);
+        }
+        let removed = self.raw_delete(start, end);
+        self.record(UndoOp::DeleteRange(start, removed.clone()));
+        Ok(removed)
+    }
+
+    /// 操作グループを開始する（commit_group までの操作を1回のUndoで取り消す）
+    /// ネスト可能で、最も外側の commit_group で確定する
+    pub fn begin_group(&mut self) {
+        self.group_depth += 1;
+    }
+
+    /// 操作グループを確定する
+    pub fn commit_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            self.flush_group();
+        }
+    }
+
+    /// クロージャ内の操作を1つのグループとして実行する
+    pub fn edit_group<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        self.begin_group();
+        let result = f(self);
+        self.commit_group();
+        result
+    }
+
+    /// パターンを前方検索（start 以降で最初に一致する位置）
+    pub fn find(&self, pattern: &[u8], start: usize) -> Option<usize> {
+        self.find_until(pattern, start, self.len)
+    }
+
+    /// パターンを前方検索（start 以降、limit より前から始まる最初の一致位置）
+    pub fn find_until(&self, pattern: &[u8], start: usize, limit: usize) -> Option<usize> {
+        self.find_by(pattern.len(), start, limit, |w| w == pattern)
+    }
+
+    /// パターンを後方検索（end より前で終わる最後の一致位置）
+    pub fn rfind(&self, pattern: &[u8], end: usize) -> Option<usize> {
+        self.rfind_by(pattern.len(), end, |w| w == pattern)
+    }
+
+    /// ワイルドカード付きのパターンを前方検索（limit より前から始まるもの）
+    pub fn find_masked(&self, pattern: &Masked, start: usize, limit: usize) -> Option<usize> {
+        self.find_by(pattern.len(), start, limit, |w| pattern.matches(w))
+    }
+
+    /// ワイルドカード付きのパターンを後方検索
+    pub fn rfind_masked(&self, pattern: &Masked, end: usize) -> Option<usize> {
+        self.rfind_by(pattern.len(), end, |w| pattern.matches(w))
+    }
+
+    /// 数値パターンで start 以降、limit より前から始まる最初の一致位置を検索
+    pub fn find_numeric(&self, pattern: &Numeric, start: usize, limit: usize) -> Option<usize> {
+        self.find_by(pattern.len(), start, limit, |w| pattern.matches(w))
+    }
+
+    /// 数値パターンで end までに終わる最後の一致位置を検索
+    pub fn rfind_numeric(&self, pattern: &Numeric, end: usize) -> Option<usize> {
+        self.rfind_by(pattern.len(), end, |w| pattern.matches(w))
+    }
+
+    /// n バイトの窓が matches を満たす最初の位置（start 以上 limit 未満）
+    fn find_by(
+        &self,
+        n: usize,
+        start: usize,
+        limit: usize,
+        matches: impl Fn(&[u8]) -> bool,
+    ) -> Option<usize> {
+        if n == 0 {
+            return None;
+        }
+        let mut pos = start;
+        while pos + n <= self.len && pos < limit {
+            // 境界をまたぐ一致を拾うため n-1 バイト重ねて読む
+            let end = (pos + SEARCH_CHUNK).min(limit).saturating_add(n - 1).min(self.len);
+            let window = self.get_range(pos, end)?;
+            if let Some(i) = window.windows(n).position(&matches) {
+                return Some(pos + i);
+            }
+            pos += SEARCH_CHUNK;
+        }
+        None
+    }
+
+    /// n バイトの窓が matches を満たす最後の位置（end より前で終わるもの）
+    fn rfind_by(&self, n: usize, end: usize, matches: impl Fn(&[u8]) -> bool) -> Option<usize> {
+        let mut end = end.min(self.len);
+        if n == 0 {
+            return None;
+        }
+        while end >= n {
+            let start = end.saturating_sub(SEARCH_CHUNK + n - 1);
+            let window = self.get_range(start, end)?;
+            if let Some(i) = window.windows(n).rposition(&matches) {
+                return Some(start + i);
             }
-            UndoOp::Insert(pos, value) => {
-                self.data.remove(pos);
-                self.redo_stack.push(UndoOp::Insert(pos, value));
-                pos.saturating_sub(1).min(self.data.len().saturating_sub(1))
+            if start == 0 {
+                break;
             }
-            UndoOp::Delete(pos, value) => {
-                self.data.insert(pos, value);
-                self.redo_stack.push(UndoOp::Delete(pos, value));
-                pos
+            end = start + n - 1;
+        }
+        None
+    }
+
+    /// 正規表現を前方検索
+    /// start 以降、limit より前から始まる最初の空でない一致範囲
+    pub fn find_regex(&self, re: &Regex, start: usize, limit: usize) -> Option<Range<usize>> {
+        let limit = limit.min(self.len);
+        let mut pos = start;
+        while pos < limit {
+            let end = (pos + SEARCH_CHUNK).min(limit);
+            if let Some(found) = self.regex_in_chunk(re, pos, end, false) {
+                return Some(found);
             }
+            pos = end;
+        }
+        None
+    }
+
+    /// 正規表現を後方検索（end より前から始まる最後の空でない一致範囲）
+    /// 一致は先頭から重ならないように区切ったものを数える
+    pub fn rfind_regex(&self, re: &Regex, end: usize) -> Option<Range<usize>> {
+        let mut end = end.min(self.len);
+        while end > 0 {
+            let start = end.saturating_sub(SEARCH_CHUNK);
+            if let Some(found) = self.regex_in_chunk(re, start, end, true) {
+                return Some(found);
+            }
+            end = start;
+        }
+        None
+    }
+
+    /// [start, end) から始まる最初（last なら最後）の空でない一致範囲
+    /// 手前の文脈も読み、一致の後ろは REGEX_OVERLAP だけ余分に読む。
+    /// 一致がウィンドウの終わり近くまで届いていれば、
+    /// 端で切れたり端を文字列の終わりとみなしたりしているかもしれないので、
+    /// 広げて探し直す。
+    /// 最後の一致は、手前の REGEX_OVERLAP から区切り始めて、
+    /// start をまたぐ一致の途中から数えないようにする
+    fn regex_in_chunk(
+        &self,
+        re: &Regex,
+        start: usize,
+        end: usize,
+        last: bool,
+    ) -> Option<Range<usize>> {
+        let scan = if last {
+            start.saturating_sub(REGEX_OVERLAP)
+        } else {
+            start
         };
-        self.modified = !self.undo_stack.is_empty();
+        let from = scan.saturating_sub(REGEX_CONTEXT);
+        let mut window_end = (end + REGEX_OVERLAP).min(self.len);
+        loop {
+            let window = self.get_range(from, window_end)?;
+            let mut found = None;
+            let mut at = scan - from;
+            while let Some(m) = re.find_at(&window, at) {
+                if from + m.start() >= end {
+                    break;
+                }
+                if !m.is_empty() && from + m.start() >= start {
+                    found = Some(from + m.start()..from + m.end());
+                    if !last {
+                        break;
+                    }
+                }
+                // 空の一致の後ろは1バイト進める（find_iter と同じ）
+                at = if m.is_empty() { m.end() + 1 } else { m.end() };
+                if at > window.len() {
+                    break;
+                }
+            }
+            match found {
+                Some(m) if window_end < self.len && m.end + REGEX_OVERLAP > window_end => {
+                    window_end = (window_end + (window_end - from)).min(self.len);
+                }
+                found => return found,
+            }
+        }
+    }
+
+    /// Undo: 直前の操作を取り消す
+    /// 戻り値: 影響を受けた位置
+    pub fn undo(&mut self) -> Option<usize> {
+        self.flush_group();
+        if self.readonly {
+            return None;
+        }
+        let op = self.undo_stack.pop()?;
+        self.apply_undo(&op);
+        let pos = op.pos();
+        self.redo_stack.push(op);
         Some(pos)
     }
 
     /// Redo: 取り消した操作をやり直す
-    /// 戻り値: (成功したか, 影響を受けた位置)
+    /// 戻り値: 影響を受けた位置
     pub fn redo(&mut self) -> Option<usize> {
+        self.flush_group();
+        if self.readonly {
+            return None;
+        }
         let op = self.redo_stack.pop()?;
-        let pos = match op {
-            UndoOp::Set(pos, old_value, new_value) => {
-                self.data[pos] = new_value;
-                self.undo_stack.push(UndoOp::Set(pos, old_value, new_value));
-                pos
-            }
-            UndoOp::Insert(pos, value) => {
-                self.data.insert(pos, value);
-                self.undo_stack.push(UndoOp::Insert(pos, value));
-                pos
-            }
-            UndoOp::Delete(pos, value) => {
-                self.data.remove(pos);
-                self.undo_stack.push(UndoOp::Delete(pos, value));
-                pos.min(self.data.len().saturating_sub(1))
-            }
-        };
-        self.modified = true;
+        self.apply_redo(&op);
+        let pos = op.pos();
+        self.undo_stack.push(op);
         Some(pos)
     }
 
-    /// 変更されているかどうか
+    /// 変更されているかどうか（最後に保存した状態と比べる）
     pub fn is_modified(&self) -> bool {
-        self.modified
+        !self.group.is_empty() || self.save_point != Some(self.undo_stack.len())
+    }
+
+    /// 保存時点（読み込み時点）から書き換え・挿入されたバイトの範囲
+    /// 先頭から順
+    /// 削除したバイトは範囲として現れない。保存時点の状態に戻っていれば空
+    /// 元データの同じ長さの隙間を埋める書き換えは、
+    /// 元の値に戻したバイトを除く
+    pub fn dirty_ranges(&self) -> Vec<Range<usize>> {
+        if !self.is_modified() {
+            return Vec::new();
+        }
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let mut push = |range: Range<usize>| match ranges.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => ranges.push(range),
+        };
+        // 追加データのピースが続く範囲ごとに、
+        // 前後の元データのピースの間の隙間と比べる
+        let mut orig_end = 0;
+        let mut i = 0;
+        while i < self.pieces.len() {
+            let piece = self.pieces[i];
+            if piece.source == Source::Original {
+                orig_end = piece.start + piece.len;
+                i += 1;
+                continue;
+            }
+            let first = i;
+            while i < self.pieces.len() && self.pieces[i].source == Source::Add {
+                i += 1;
+            }
+            let start = self.starts[first];
+            let end = self.starts.get(i).copied().unwrap_or(self.len);
+            let orig_next = self.pieces.get(i).map_or(self.original.len(), |p| p.start);
+            if orig_next.checked_sub(orig_end) != Some(end - start) {
+                push(start..end);
+                continue;
+            }
+            let saved = &self.original[orig_end..orig_next];
+            let current = self.get_range(start, end).unwrap_or_default();
+            let mut pos = 0;
+            while pos < current.len() {
+                let same = current[pos] == saved[pos];
+                let run = current[pos..]
+                    .iter()
+                    .zip(&saved[pos..])
+                    .take_while(|(a, b)| (a == b) == same)
+                    .count();
+                if !same {
+                    push(start + pos..start + pos + run);
+                }
+                pos += run;
+            }
+        }
+        ranges
     }
 
     /// 読み取り専用かどうか
@@ -237,9 +1335,271 @@ impl Document {
         self.path.as_ref().and_then(|p| p.file_name()).and_then(|s| s.to_str())
     }
 
-    /// 生データへの参照を取得
-    pub fn data(&self) -> &[u8] {
-        &self.data
+    // === Undo履歴 ===
+
+    /// 現在の状態を保存時点として記録する
+    fn mark_saved(&mut self) {
+        self.flush_group();
+        self.save_point = Some(self.undo_stack.len());
+    }
+
+    /// 操作を記録する（グループ記録中はグループに積む）
+    fn record(&mut self, op: UndoOp) {
+        // 保存時点が Redo 側にある場合、Redo 履歴と一緒に失われる
+        if self.save_point.is_some_and(|sp| sp > self.undo_stack.len()) {
+            self.save_point = None;
+        }
+        self.redo_stack.clear();
+        if self.group_depth > 0 {
+            self.group.push(op);
+        } else {
+            self.undo_stack.push(op);
+        }
+    }
+
+    /// 記録中のグループを Undo 履歴に積む
+    fn flush_group(&mut self) {
+        let mut ops = std::mem::take(&mut self.group);
+        match ops.len() {
+            0 => {}
+            1 => self.undo_stack.extend(ops.pop()),
+            _ => self.undo_stack.push(UndoOp::Group(ops)),
+        }
+    }
+
+    /// 操作を取り消す
+    fn apply_undo(&mut self, op: &UndoOp) {
+        match op {
+            UndoOp::Set(pos, old_value, _) => self.raw_set_range(*pos, &[*old_value]),
+            UndoOp::SetRange(pos, old, _) => self.raw_set_range(*pos, old),
+            UndoOp::InsertRange(pos, bytes) => {
+                self.raw_delete(*pos, *pos + bytes.len());
+            }
+            UndoOp::DeleteRange(pos, bytes) => self.raw_insert(*pos, bytes),
+            UndoOp::Group(ops) => {
+                for op in ops.iter().rev() {
+                    self.apply_undo(op);
+                }
+            }
+        }
+    }
+
+    /// 操作をやり直す
+    fn apply_redo(&mut self, op: &UndoOp) {
+        match op {
+            UndoOp::Set(pos, _, new_value) => self.raw_set_range(*pos, &[*new_value]),
+            UndoOp::SetRange(pos, _, new) => self.raw_set_range(*pos, new),
+            UndoOp::InsertRange(pos, bytes) => self.raw_insert(*pos, bytes),
+            UndoOp::DeleteRange(pos, bytes) => {
+                self.raw_delete(*pos, *pos + bytes.len());
+            }
+            UndoOp::Group(ops) => {
+                for op in ops {
+                    self.apply_redo(op);
+                }
+            }
+        }
+    }
+
+    // === ピーステーブル操作（Undo履歴は扱わない） ===
+
+    /// 参照先バッファを取得
+    fn source(&self, source: Source) -> &[u8] {
+        match source {
+            Source::Original => &self.original,
+            Source::Add => &self.add,
+        }
+    }
+
+    /// ピース列を original 全体を指す1ピースに戻す
+    fn reset_pieces(&mut self) {
+        self.bump_revision(0, self.len, self.original.len());
+        self.add.clear();
+        self.pieces.clear();
+        if !self.original.is_empty() {
+            self.pieces.push(Piece {
+                source: Source::Original,
+                start: 0,
+                len: self.original.len(),
+            });
+        }
+        self.reindex(0);
+    }
+
+    /// 論理位置を含むピースの (インデックス, ピース内オフセット) を返す
+    /// pos < len であること
+    fn locate(&self, pos: usize) -> (usize, usize) {
+        let idx = self.starts.partition_point(|&s| s <= pos) - 1;
+        (idx, pos - self.starts[idx])
+    }
+
+    /// from 番目以降のピースの開始位置を再計算
+    fn reindex(&mut self, from: usize) {
+        self.starts.truncate(from);
+        let mut pos = match from {
+            0 => 0,
+            _ => self.starts[from - 1] + self.pieces[from - 1].len,
+        };
+        for piece in &self.pieces[from..] {
+            self.starts.push(pos);
+            pos += piece.len;
+        }
+        self.len = pos;
+    }
+
+    /// pos にピース境界を作り、pos から始まるピースのインデックスを返す
+    fn split_at(&mut self, pos: usize) -> usize {
+        if pos >= self.len {
+            return self.pieces.len();
+        }
+        let (idx, off) = self.locate(pos);
+        if off == 0 {
+            return idx;
+        }
+        let piece = self.pieces[idx];
+        self.pieces[idx].len = off;
+        self.pieces.insert(
+            idx + 1,
+            Piece {
+                source: piece.source,
+                start: piece.start + off,
+                len: piece.len - off,
+            },
+        );
+        self.starts.insert(idx + 1, pos);
+        idx + 1
+    }
+
+    /// バイト列を挿入
+    fn raw_insert(&mut self, pos: usize, bytes: &[u8]) {
+        self.bump_revision(pos, 0, bytes.len());
+        let add_start = self.add.len();
+        self.add.extend_from_slice(bytes);
+        let idx = self.split_at(pos);
+
+        // 直前のピースが追加バッファの末尾を指していれば延長する（連続入力）
+        if idx > 0 {
+            let prev = &mut self.pieces[idx - 1];
+            if prev.source == Source::Add && prev.start + prev.len == add_start {
+                prev.len += bytes.len();
+                self.reindex(idx - 1);
+                return;
+            }
+        }
+        self.pieces.insert(
+            idx,
+            Piece {
+                source: Source::Add,
+                start: add_start,
+                len: bytes.len(),
+            },
+        );
+        self.reindex(idx);
+    }
+
+    /// 範囲 [start, end) を削除し、削除したバイト列を返す
+    fn raw_delete(&mut self, start: usize, end: usize) -> Vec<u8> {
+        self.bump_revision(start, end - start, 0);
+        let removed = self.get_range(start, end).map(Cow::into_owned).unwrap_or_default();
+        let first = self.split_at(start);
+        let last = self.split_at(end);
+        self.pieces.drain(first..last);
+        self.reindex(first);
+        removed
+    }
+
+    /// 範囲を上書き
+    fn raw_set_range(&mut self, pos: usize, bytes: &[u8]) {
+        let end = pos + bytes.len();
+        if bytes.is_empty() {
+            return;
+        }
+        self.bump_revision(pos, bytes.len(), bytes.len());
+        let (first, _) = self.locate(pos);
+        let all_add = self.pieces[first..]
+            .iter()
+            .zip(&self.starts[first..])
+            .take_while(|&(_, &piece_start)| piece_start < end)
+            .all(|(piece, _)| piece.source == Source::Add);
+
+        if all_add {
+            // 追加バッファの各領域は1つのピースからしか参照されないので、
+            // 直接書き換えられる
+            let mut written = 0;
+            for idx in first.. {
+                if written == bytes.len() {
+                    break;
+                }
+                let piece = self.pieces[idx];
+                let from = (pos + written) - self.starts[idx];
+                let n = (piece.len - from).min(bytes.len() - written);
+                let at = piece.start + from;
+                self.add[at..at + n].copy_from_slice(&bytes[written..written + n]);
+                written += n;
+            }
+        } else {
+            self.raw_delete(pos, end);
+            self.raw_insert(pos, bytes);
+        }
+    }
+}
+
+/// ブロックデバイスかどうか
+fn is_block_device(meta: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        meta.file_type().is_block_device()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = meta;
+        false
+    }
+}
+
+/// キャラクタデバイスかどうか
+fn is_char_device(meta: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        meta.file_type().is_char_device()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = meta;
+        false
+    }
+}
+
+/// ブロックデバイスまたはキャラクタデバイスかどうか
+fn is_device(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        fs::metadata(path).is_ok_and(|meta| {
+            meta.file_type().is_block_device() || meta.file_type().is_char_device()
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+/// 指定位置に書き込む（ファイル位置を共有しない pwrite）
+fn write_at(file: &File, buf: &[u8], pos: u64) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.write_all_at(buf, pos)
+    }
+    #[cfg(not(unix))]
+    {
+        let mut file = file;
+        file.seek(SeekFrom::Start(pos))?;
+        file.write_all(buf)
     }
 }
 
@@ -248,3 +1608,410 @@ impl Default for Document {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn contents(doc: &Document) -> Vec<u8> {
+        doc.get_range(0, doc.len()).unwrap().into_owned()
+    }
+
+    #[test]
+    fn test_insert_delete_set() {
+        let mut doc = Document::from_bytes(b"Hello".to_vec());
+        doc.insert_bytes(5, b" World").unwrap();
+        doc.insert(0, b'>').unwrap();
+        assert_eq!(contents(&doc), b">Hello World");
+
+        doc.delete_range(1, 7).unwrap();
+        assert_eq!(contents(&doc), b">World");
+
+        doc.set(1, b'w').unwrap();
+        doc.set(2, b'O').unwrap();
+        assert_eq!(contents(&doc), b">wOrld");
+        assert_eq!(doc.get(5), Some(b'd'));
+        assert_eq!(doc.get(6), None);
+    }
+
+    #[test]
+    fn test_readonly_refuses_edits() {
+        let mut doc = Document::from_bytes(b"abc".to_vec());
+        doc.set(0, b'x').unwrap();
+        doc.set_readonly(true);
+        assert!(matches!(doc.set(1, b'y'), Err(BufferError::ReadOnly)));
+        assert!(matches!(doc.insert_bytes(0, b"y"), Err(BufferError::ReadOnly)));
+        assert!(matches!(doc.delete_range(0, 1), Err(BufferError::ReadOnly)));
+        assert_eq!(doc.undo(), None);
+        assert_eq!(contents(&doc), b"xbc");
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut doc = Document::from_bytes(b"abcdef".to_vec());
+        doc.delete_range(1, 4).unwrap();
+        doc.insert_bytes(1, b"XY").unwrap();
+        doc.set(0, b'Z').unwrap();
+        assert_eq!(contents(&doc), b"ZXYef");
+
+        while doc.undo().is_some() {}
+        assert_eq!(contents(&doc), b"abcdef");
+
+        while doc.redo().is_some() {}
+        assert_eq!(contents(&doc), b"ZXYef");
+    }
+
+    #[test]
+    fn test_grouped_undo() {
+        let mut doc = Document::from_bytes(b"0123456789".to_vec());
+        doc.edit_group(|doc| {
+            doc.set_range(2, b"AB").unwrap();
+            doc.delete_range(5, 8).unwrap();
+            doc.insert_bytes(0, b"xy").unwrap();
+        });
+        doc.set(0, b'z').unwrap();
+        assert_eq!(contents(&doc), b"zy01AB489");
+
+        assert_eq!(doc.undo(), Some(0));
+        assert_eq!(contents(&doc), b"xy01AB489");
+        doc.undo();
+        assert_eq!(contents(&doc), b"0123456789");
+        assert_eq!(doc.undo(), None);
+
+        doc.redo();
+        assert_eq!(contents(&doc), b"xy01AB489");
+    }
+
+    #[test]
+    fn test_modified_tracks_save_point() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        std::fs::write(&path, b"abc").unwrap();
+        let mut doc = Document::open(&path).unwrap();
+
+        doc.set(0, b'x').unwrap();
+        doc.save().unwrap();
+        assert!(!doc.is_modified());
+
+        // 保存後に Undo すると、ディスクの内容とは異なる
+        doc.undo();
+        assert!(doc.is_modified());
+        doc.redo();
+        assert!(!doc.is_modified());
+
+        // 保存時点が Redo 側にある状態で編集すると、戻る手段はなくなる
+        doc.undo();
+        doc.set(1, b'y').unwrap();
+        doc.undo();
+        assert!(doc.is_modified());
+    }
+
+    #[test]
+    fn test_dirty_ranges() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let mut doc = Document::open(&path).unwrap();
+        assert!(doc.dirty_ranges().is_empty());
+
+        doc.set(1, b'x').unwrap();
+        doc.set(2, b'y').unwrap();
+        doc.insert_bytes(6, b"AB").unwrap();
+        doc.delete(0).unwrap();
+        assert_eq!(doc.dirty_ranges(), vec![0..2, 5..7]);
+
+        // 保存すると保存時点からの変更はなくなる
+        doc.save().unwrap();
+        assert!(doc.dirty_ranges().is_empty());
+        doc.set(9, b'z').unwrap();
+        assert_eq!(doc.dirty_ranges(), vec![9..10]);
+        doc.undo();
+        assert!(doc.dirty_ranges().is_empty());
+
+        // 元の値に戻したバイトは、他の変更が残っていても変更として数えない
+        doc.set(2, b'A').unwrap();
+        doc.set(3, b'B').unwrap();
+        doc.set(7, b'C').unwrap();
+        doc.undo();
+        assert!(doc.is_modified());
+        assert_eq!(doc.dirty_ranges(), vec![2..4]);
+        doc.set(3, b'B').unwrap();
+        doc.set(3, b'4').unwrap();
+        assert_eq!(doc.dirty_ranges(), vec![2..3]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_preserves_mode_and_makes_backup() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        std::fs::write(&path, b"abc").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o751)).unwrap();
+
+        let mut doc = Document::open(&path).unwrap();
+        doc.set_make_backup(true);
+        doc.insert_bytes(3, b"def").unwrap();
+        doc.save().unwrap();
+
+        let backup = dir.join("file~");
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcdef");
+        assert_eq!(std::fs::read(&backup).unwrap(), b"abc");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+    }
+
+    #[test]
+    fn test_in_place_save_keeps_inode() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let mut doc = Document::open(&path).unwrap();
+        doc.set_save_strategy(SaveStrategy::InPlace).unwrap();
+
+        assert!(doc.insert(0, b'x').is_err());
+        assert!(doc.delete(0).is_err());
+        doc.set_range(3, b"AB").unwrap();
+        doc.set(9, b'Z').unwrap();
+
+        // 保存中も同じファイルを開いている他のプロセスから見える
+        let other = std::fs::File::open(&path).unwrap();
+        doc.save().unwrap();
+        assert!(!doc.is_modified());
+        let mut contents = Vec::new();
+        (&other).read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"012AB5678Z");
+
+        doc.set(0, b'_').unwrap();
+        doc.save().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"_12AB5678Z");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_refuses_unsized_char_device() {
+        // 終わりのないデバイスは読み込もうとせずにエラーにする
+        assert!(matches!(Document::open("/dev/zero"), Err(BufferError::UnsizedDevice)));
+    }
+
+    #[test]
+    fn test_detects_change_on_disk() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let mut doc = Document::open(&path).unwrap();
+        assert!(!doc.changed_on_disk());
+
+        // 同じ inode への書き換えでも、
+        // 比較するのは読み込み時の内容に対する編集結果
+        doc.set(0, b'x').unwrap();
+        std::fs::write(&path, b"abcdefghijkl").unwrap();
+        assert!(doc.changed_on_disk());
+        assert_eq!(doc.get_range(0, doc.len()).unwrap().as_ref(), b"x123456789");
+        let diff = doc.diff_with_disk().unwrap();
+        assert_eq!(diff.first, Some(0));
+        assert_eq!(diff.differing, 10);
+        assert_eq!(diff.disk_len, 12);
+
+        // 維持して保存するとバッファの内容だけが書き込まれる
+        doc.acknowledge_disk_change();
+        assert!(!doc.changed_on_disk());
+        doc.save().unwrap();
+        assert!(!doc.changed_on_disk());
+        assert_eq!(std::fs::read(&path).unwrap(), b"x123456789");
+
+        // 読み直すとディスク上の内容だけになり、編集と履歴は破棄される
+        doc.set(1, b'y').unwrap();
+        std::fs::write(&path, b"ABCDEFGH").unwrap();
+        assert!(doc.changed_on_disk());
+        doc.revert().unwrap();
+        assert!(!doc.changed_on_disk());
+        assert!(!doc.is_modified());
+        assert_eq!(doc.get_range(0, doc.len()).unwrap().as_ref(), b"ABCDEFGH");
+        assert_eq!(doc.undo(), None);
+        assert_eq!(std::fs::read(&path).unwrap(), b"ABCDEFGH");
+    }
+
+    #[test]
+    fn test_survives_truncation_on_disk() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        let size = MAP_THRESHOLD as usize + 4096;
+        let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+        let doc = Document::open(&path).unwrap();
+        assert!(matches!(doc.original, Original::Mapped(_)));
+        assert_eq!(doc.get(size - 1), Some(data[size - 1]));
+
+        // 切り詰めた後もマップの範囲外には触れず、失われた部分は 0 になる
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(16).unwrap();
+        doc.check_mapping();
+        assert_eq!(doc.get(size - 1), Some(0));
+        assert_eq!(doc.get_range(0, 16).unwrap().as_ref(), &data[..16]);
+        assert_eq!(doc.len(), size);
+
+        // コピーに切り替えた後の書き込みはバッファに見えない
+        write_at(&file, b"XXXX", 0).unwrap();
+        assert_eq!(doc.get_range(0, 4).unwrap().as_ref(), &data[..4]);
+    }
+
+    #[test]
+    fn test_small_file_ignores_writes_on_disk() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let doc = Document::open(&path).unwrap();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        write_at(&file, b"XX", 0).unwrap();
+        file.set_len(4).unwrap();
+        assert_eq!(doc.get_range(0, doc.len()).unwrap().as_ref(), b"0123456789");
+    }
+
+    #[test]
+    fn test_recover_journal() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let mut doc = Document::open(&path).unwrap();
+        doc.set(1, b'a').unwrap();
+        doc.insert_bytes(5, b"XYZ").unwrap();
+        doc.delete_range(10, 13).unwrap();
+        doc.insert_bytes(doc.len(), b"!").unwrap();
+        let expected = contents(&doc);
+        let journal_path = doc.write_journal().unwrap().unwrap();
+        assert_eq!(journal_path.file_name().unwrap().to_str().unwrap().chars().next(), Some('#'));
+        drop(doc);
+
+        let mut doc = Document::open(&path).unwrap();
+        assert_eq!(doc.find_journal(), Some(journal_path.clone()));
+        assert_eq!(doc.recover_journal().unwrap(), Some(1));
+        assert_eq!(contents(&doc), expected);
+        assert!(doc.is_modified());
+        doc.undo();
+        assert_eq!(contents(&doc), b"0123456789");
+        doc.redo();
+
+        // 保存するとジャーナルは消える
+        doc.save().unwrap();
+        assert!(doc.find_journal().is_none());
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_persistent_undo() {
+        let dir = TempDir::new();
+        let path = dir.join("file");
+        journal::TEST_STATE_DIR.set(Some(dir.join("state")));
+        std::fs::write(&path, b"0123").unwrap();
+
+        let mut doc = Document::open(&path).unwrap();
+        assert_eq!(doc.set_persistent_undo(true).unwrap(), 0);
+        doc.set(0, b'a').unwrap();
+        doc.insert_bytes(4, b"xy").unwrap();
+        doc.save().unwrap();
+        drop(doc);
+
+        let mut doc = Document::open(&path).unwrap();
+        assert_eq!(doc.set_persistent_undo(true).unwrap(), 2);
+        assert!(!doc.is_modified());
+        doc.undo();
+        doc.undo();
+        assert_eq!(contents(&doc), b"0123");
+        drop(doc);
+
+        // 内容が変わっていれば履歴は使わない
+        std::fs::write(&path, b"a123xz").unwrap();
+        let mut doc = Document::open(&path).unwrap();
+        assert_eq!(doc.set_persistent_undo(true).unwrap(), 0);
+        assert_eq!(doc.undo(), None);
+
+        // 内容が一致していても、範囲外に触れる履歴は使わない
+        let history = History {
+            content_hash: doc.content_hash(),
+            undo: vec![UndoOp::Set(0, b'0', b'a'), UndoOp::DeleteRange(10, vec![0])],
+            redo: Vec::new(),
+        };
+        history::write(&path, &history).unwrap();
+        let mut doc = Document::open(&path).unwrap();
+        assert!(matches!(doc.set_persistent_undo(true), Err(BufferError::Corrupt(_))));
+        assert_eq!(doc.undo(), None);
+
+        // 書き出せなかった理由は保存の後に取り出せる
+        journal::TEST_STATE_DIR.set(Some(path.join("state")));
+        doc.set(0, b'b').unwrap();
+        doc.save().unwrap();
+        assert!(doc.take_history_error().is_some());
+        assert!(doc.take_history_error().is_none());
+
+        journal::TEST_STATE_DIR.set(None);
+    }
+
+    #[test]
+    fn test_find_across_pieces() {
+        let mut doc = Document::from_bytes(b"xxABxx".to_vec());
+        doc.insert_bytes(3, b"C").unwrap();
+        assert_eq!(doc.find(b"ACB", 0), Some(2));
+        assert_eq!(doc.rfind(b"x", doc.len()), Some(6));
+        assert_eq!(doc.rfind(b"ACB", 4), None);
+        assert_eq!(doc.find(b"ACB", 3), None);
+        assert_eq!(doc.find_until(b"ACB", 0, 2), None);
+        assert_eq!(doc.find_until(b"ACB", 0, 3), Some(2));
+
+        let masked = Masked::parse("41 ?3 4?").unwrap();
+        assert_eq!(doc.find_masked(&masked, 0, doc.len()), Some(2));
+        assert_eq!(doc.rfind_masked(&masked, doc.len()), Some(2));
+        assert_eq!(doc.find_masked(&masked, 3, doc.len()), None);
+    }
+
+    #[test]
+    fn test_find_regex() {
+        let mut data = vec![0x41u8; SEARCH_CHUNK + 16];
+        data[10..13].copy_from_slice(b"\x01\x02\x7f");
+        // チャンクの境界をまたぐ一致
+        data[SEARCH_CHUNK - 2..SEARCH_CHUNK + 2].copy_from_slice(b"\x00\x1f\x03\x7f");
+        let mut doc = Document::from_bytes(data);
+        doc.insert_bytes(11, b"\x05").unwrap();
+        let re = Regex::new(r"(?-u)[\x00-\x1f]+\x7f").unwrap();
+        assert_eq!(doc.find_regex(&re, 0, doc.len()), Some(10..14));
+        assert_eq!(doc.find_regex(&re, 14, doc.len()), Some(SEARCH_CHUNK - 1..SEARCH_CHUNK + 3));
+        assert_eq!(doc.find_regex(&re, 14, SEARCH_CHUNK - 1), None);
+        assert_eq!(doc.find_regex(&re, SEARCH_CHUNK + 3, doc.len()), None);
+        assert_eq!(doc.rfind_regex(&re, doc.len()), Some(SEARCH_CHUNK - 1..SEARCH_CHUNK + 3));
+        assert_eq!(doc.rfind_regex(&re, SEARCH_CHUNK - 1), Some(10..14));
+        assert_eq!(doc.rfind_regex(&re, 10), None);
+        // 空の一致は飛ばす
+        let empty = Regex::new(r"(?-u)\x05*").unwrap();
+        assert_eq!(doc.find_regex(&empty, 0, doc.len()), Some(11..12));
+    }
+
+    #[test]
+    fn test_find_regex_across_windows() {
+        // REGEX_OVERLAP より長く、チャンクの境界をまたぐ一致
+        let mut data = vec![0x41u8; 3 * SEARCH_CHUNK];
+        let long = SEARCH_CHUNK - 100..SEARCH_CHUNK + 2 * REGEX_OVERLAP;
+        data[long.clone()].fill(0x42);
+        data[2 * SEARCH_CHUNK + 4..2 * SEARCH_CHUNK + 6].copy_from_slice(b" B");
+        let doc = Document::from_bytes(data);
+        let re = Regex::new(r"(?-u)B+").unwrap();
+        assert_eq!(doc.find_regex(&re, 0, doc.len()), Some(long.clone()));
+        assert_eq!(doc.rfind_regex(&re, 2 * SEARCH_CHUNK), Some(long.clone()));
+        assert_eq!(
+            doc.rfind_regex(&re, doc.len()),
+            Some(2 * SEARCH_CHUNK + 5..2 * SEARCH_CHUNK + 6)
+        );
+
+        // 途中から探しても、^ はドキュメントの先頭、$ は末尾だけに一致する
+        let anchored = Regex::new(r"(?-u)^A|A$").unwrap();
+        assert_eq!(doc.find_regex(&anchored, 1, doc.len()), Some(doc.len() - 1..doc.len()));
+        assert_eq!(doc.find_regex(&anchored, SEARCH_CHUNK, doc.len() - 1), None);
+        // \b は手前のバイトも見る
+        let word = Regex::new(r"(?-u)\bB").unwrap();
+        assert_eq!(
+            doc.find_regex(&word, long.start + 1, doc.len()),
+            Some(2 * SEARCH_CHUNK + 5..2 * SEARCH_CHUNK + 6)
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! 永続 Undo 履歴
+//!
+//! 保存時に Undo/Redo 履歴を `$XDG_STATE_HOME/ehx/undo/` に書き出し、
+//! 次に開いた時にファイルの内容が保存時と同じなら読み戻す。
+//! ファイル名はパスのハッシュで、中身にはパスと内容のハッシュを持つ。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::BufferError;
+use super::codec::{Decoder, Encoder, Fnv1a};
+use super::document::UndoOp;
+use super::journal;
+
+const MAGIC: &[u8] = b"EHXU\x01";
+
+/// 保存された履歴
+pub(super) struct History {
+    /// 保存時のファイル内容のハッシュ
+    pub content_hash: u64,
+    pub undo: Vec<UndoOp>,
+    pub redo: Vec<UndoOp>,
+}
+
+/// 履歴ファイルのパス
+fn history_path(path: &Path) -> Option<PathBuf> {
+    let absolute = fs::canonicalize(path).ok()?;
+    let mut hash = Fnv1a::new();
+    hash.update(absolute.to_str()?.as_bytes());
+    Some(journal::state_dir()?.join("undo").join(format!("{:016x}", hash.finish())))
+}
+
+/// 履歴を書き出す
+pub(super) fn write(path: &Path, history: &History) -> Result<(), BufferError> {
+    let target = history_path(path).ok_or(BufferError::Corrupt("no state directory"))?;
+    let mut enc = Encoder::new(MAGIC);
+    enc.bytes(fs::canonicalize(path)?.as_os_str().as_encoded_bytes());
+    enc.u64(history.content_hash);
+    encode_ops(&mut enc, &history.undo);
+    encode_ops(&mut enc, &history.redo);
+
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp = target.clone().into_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, enc.finish())?;
+    fs::rename(&tmp, &target)?;
+    Ok(())
+}
+
+/// 履歴を読み込む（履歴がない・別のファイルのものなら None）
+pub(super) fn read(path: &Path) -> Result<Option<History>, BufferError> {
+    let Some(target) = history_path(path) else {
+        return Ok(None);
+    };
+    let data = match fs::read(&target) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut dec = Decoder::new(&data, MAGIC)?;
+    // パスのハッシュが衝突した別のファイルの履歴は使わない
+    if dec.bytes()? != fs::canonicalize(path)?.as_os_str().as_encoded_bytes() {
+        return Ok(None);
+    }
+    let content_hash = dec.u64()?;
+    let undo = decode_ops(&mut dec)?;
+    let redo = decode_ops(&mut dec)?;
+    if !dec.is_empty() {
+        return Err(BufferError::Corrupt("trailing data"));
+    }
+    Ok(Some(History { content_hash, undo, redo }))
+}
+
+fn encode_ops(enc: &mut Encoder, ops: &[UndoOp]) {
+    enc.u64(ops.len() as u64);
+    for op in ops {
+        encode_op(enc, op);
+    }
+}
+
+fn encode_op(enc: &mut Encoder, op: &UndoOp) {
+    match op {
+        UndoOp::Set(pos, old, new) => {
+            enc.u8(0);
+            enc.u64(*pos as u64);
+            enc.u8(*old);
+            enc.u8(*new);
+        }
+        UndoOp::SetRange(pos, old, new) => {
+            enc.u8(1);
+            enc.u64(*pos as u64);
+            enc.bytes(old);
+            enc.bytes(new);
+        }
+        UndoOp::InsertRange(pos, bytes) => {
+            enc.u8(2);
+            enc.u64(*pos as u64);
+            enc.bytes(bytes);
+        }
+        UndoOp::DeleteRange(pos, bytes) => {
+            enc.u8(3);
+            enc.u64(*pos as u64);
+            enc.bytes(bytes);
+        }
+        UndoOp::Group(ops) => {
+            enc.u8(4);
+            encode_ops(enc, ops);
+        }
+    }
+}
+
+fn decode_ops(dec: &mut Decoder) -> Result<Vec<UndoOp>, BufferError> {
+    let count = dec.usize()?;
+    let mut ops = Vec::new();
+    for _ in 0..count {
+        ops.push(decode_op(dec)?);
+    }
+    Ok(ops)
+}
+
+fn decode_op(dec: &mut Decoder) -> Result<UndoOp, BufferError> {
+    Ok(match dec.u8()? {
+        0 => UndoOp::Set(dec.usize()?, dec.u8()?, dec.u8()?),
+        1 => UndoOp::SetRange(dec.usize()?, dec.bytes()?.to_vec(), dec.bytes()?.to_vec()),
+        2 => UndoOp::InsertRange(dec.usize()?, dec.bytes()?.to_vec()),
+        3 => UndoOp::DeleteRange(dec.usize()?, dec.bytes()?.to_vec()),
+        4 => UndoOp::Group(decode_ops(dec)?),
+        _ => return Err(BufferError::Corrupt("unknown undo operation")),
+    })
+}
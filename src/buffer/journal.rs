@@ -0,0 +1,172 @@
+//! 自動保存ジャーナル
+//!
+//! ファイル全体ではなく「ディスク上の内容からの差分」だけを書き出す。
+//! 置き場所は Emacs と同じくファイルの隣の `#name#`。
+//! ディレクトリに書き込めなければ `$XDG_STATE_HOME/ehx/auto-save/` に置く。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::BufferError;
+use super::codec::{Decoder, Encoder};
+
+const MAGIC: &[u8] = b"EHXJ\x01";
+
+/// ディスク上の範囲 [start, end) を bytes に置き換える編集
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// ジャーナルの内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Journal {
+    /// 記録時のディスク上のファイルサイズ
+    pub size: u64,
+    /// 記録時のディスク上のファイルの mtime
+    pub mtime: Option<SystemTime>,
+    /// 位置の昇順に並んだ重ならない編集
+    pub edits: Vec<Edit>,
+}
+
+impl Journal {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut enc = Encoder::new(MAGIC);
+        enc.u64(self.size);
+        match self.mtime.and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok()) {
+            Some(d) => {
+                enc.u8(1);
+                enc.u64(d.as_secs());
+                enc.u32(d.subsec_nanos());
+            }
+            None => enc.u8(0),
+        }
+        enc.u64(self.edits.len() as u64);
+        for edit in &self.edits {
+            enc.u64(edit.start as u64);
+            enc.u64(edit.end as u64);
+            enc.bytes(&edit.bytes);
+        }
+        enc.finish()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, BufferError> {
+        let mut dec = Decoder::new(data, MAGIC)?;
+        let size = dec.u64()?;
+        let mtime = match dec.u8()? {
+            0 => None,
+            _ => {
+                let secs = dec.u64()?;
+                let nanos = dec.u32()?;
+                SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+            }
+        };
+        let count = dec.usize()?;
+        let mut edits = Vec::new();
+        let mut prev_end = 0;
+        for _ in 0..count {
+            let start = dec.usize()?;
+            let end = dec.usize()?;
+            if start < prev_end || end < start || end as u64 > size {
+                return Err(BufferError::Corrupt("overlapping edits"));
+            }
+            prev_end = end;
+            let bytes = dec.bytes()?.to_vec();
+            edits.push(Edit { start, end, bytes });
+        }
+        if !dec.is_empty() {
+            return Err(BufferError::Corrupt("trailing data"));
+        }
+        Ok(Self { size, mtime, edits })
+    }
+}
+
+/// ファイルの隣に置くジャーナルのパス（`dir/#name#`）
+fn local_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    Some(path.with_file_name(format!("#{}#", name)))
+}
+
+/// 状態ディレクトリに置くジャーナルのパス（パス区切りを `!` に置き換える）
+fn state_path(path: &Path) -> Option<PathBuf> {
+    let absolute = fs::canonicalize(path).ok()?;
+    let name = absolute.to_str()?.replace(std::path::MAIN_SEPARATOR, "!");
+    Some(state_dir()?.join("auto-save").join(format!("#{}#", name)))
+}
+
+#[cfg(test)]
+thread_local! {
+    /// テストで使う状態ディレクトリ
+    /// 環境変数を書き換えずに、そのスレッドだけ差し替える
+    pub(super) static TEST_STATE_DIR: std::cell::RefCell<Option<PathBuf>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// ehx の状態ディレクトリ（$XDG_STATE_HOME/ehx、なければ ~/.local/state/ehx）
+pub(super) fn state_dir() -> Option<PathBuf> {
+    #[cfg(test)]
+    if let Some(dir) = TEST_STATE_DIR.with_borrow(Clone::clone) {
+        return Some(dir);
+    }
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(base.join("ehx"))
+}
+
+/// 既存のジャーナルを探す
+pub(super) fn find(path: &Path) -> Option<PathBuf> {
+    [local_path(path), state_path(path)]
+        .into_iter()
+        .flatten()
+        .find(|p| p.is_file())
+}
+
+/// ジャーナルを書き込む（一時ファイルに書いてから置き換える）
+/// 戻り値: 書き込んだジャーナルのパス
+pub(super) fn write(path: &Path, journal: &Journal) -> Result<PathBuf, BufferError> {
+    let data = journal.encode();
+    let mut last_err = None;
+    for target in [local_path(path), state_path(path)].into_iter().flatten() {
+        if let Some(dir) = target.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let mut tmp = target.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        match fs::write(&tmp, &data).and_then(|()| fs::rename(&tmp, &target)) {
+            Ok(()) => {
+                // 片方に書けたら、もう片方に残っている古いジャーナルは消す
+                for other in [local_path(path), state_path(path)].into_iter().flatten() {
+                    if other != target {
+                        let _ = fs::remove_file(other);
+                    }
+                }
+                return Ok(target);
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err
+        .unwrap_or_else(|| std::io::Error::other("No location for auto-save journal"))
+        .into())
+}
+
+/// ジャーナルを読み込む
+pub(super) fn read(journal_path: &Path) -> Result<Journal, BufferError> {
+    Journal::decode(&fs::read(journal_path)?)
+}
+
+/// ジャーナルを削除する（存在しなければ何もしない）
+pub(super) fn remove(path: &Path) {
+    for target in [local_path(path), state_path(path)].into_iter().flatten() {
+        let _ = fs::remove_file(target);
+    }
+}
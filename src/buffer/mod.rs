@@ -1,6 +1,15 @@
+mod codec;
+mod diff;
 mod document;
+mod history;
+mod journal;
+mod numeric;
+mod search;
 
-pub use document::Document;
+pub use diff::{Alignment, Comparer, Hunk};
+pub use document::{DiskDiff, Document, EditSpan, SaveStrategy};
+pub use numeric::{NumKind, Numeric};
+pub use search::{compile_regex, expand_replacement, Masked, Pattern};
 
 use thiserror::Error;
 
@@ -10,4 +19,35 @@ pub enum BufferError {
     Io(#[from] std::io::Error),
     #[error("Position out of bounds: {0}")]
     OutOfBounds(usize),
+    #[error("Length cannot change in in-place write mode")]
+    LengthChange,
+    #[error("Corrupt file: {0}")]
+    Corrupt(&'static str),
+    #[error("File on disk no longer matches the auto-save journal")]
+    JournalMismatch,
+    #[error("Buffer has unsaved changes")]
+    Modified,
+    #[error("Buffer is read-only")]
+    ReadOnly,
+    #[error("Character device has no fixed size")]
+    UnsizedDevice,
+}
+
+/// 検索パターン（HEX・数値・テキスト）の解釈エラー
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PatternError {
+    #[error("Invalid hex character: {0:?}")]
+    InvalidChar(char),
+    #[error("Hex string must have even length")]
+    OddLength,
+    #[error("Invalid value: {0:?}")]
+    InvalidValue(String),
+    #[error("Value {0} is out of range for {1}")]
+    OutOfRange(String, &'static str),
+    #[error("Tolerance (~) is only allowed for f32 / f64")]
+    ToleranceNotAllowed,
+    #[error("Cannot encode {0:?} in {1}")]
+    Unencodable(char, &'static str),
+    #[error("Wildcards in the replacement must be within the {0} matched bytes")]
+    WildcardOutOfRange(usize),
 }
@@ -0,0 +1,285 @@
+//! 数値の検索パターン（`u32le:1337`, `i16be:-2`, `f32:3.14159~0.001`）
+
+use super::PatternError;
+
+/// 数値の型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumKind {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+impl NumKind {
+    /// 型名から（`u32` など、小文字）
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "u8" => NumKind::U8,
+            "i8" => NumKind::I8,
+            "u16" => NumKind::U16,
+            "i16" => NumKind::I16,
+            "u32" => NumKind::U32,
+            "i32" => NumKind::I32,
+            "u64" => NumKind::U64,
+            "i64" => NumKind::I64,
+            "f32" => NumKind::F32,
+            "f64" => NumKind::F64,
+            _ => return None,
+        })
+    }
+
+    /// 型名
+    pub fn name(self) -> &'static str {
+        match self {
+            NumKind::U8 => "u8",
+            NumKind::I8 => "i8",
+            NumKind::U16 => "u16",
+            NumKind::I16 => "i16",
+            NumKind::U32 => "u32",
+            NumKind::I32 => "i32",
+            NumKind::U64 => "u64",
+            NumKind::I64 => "i64",
+            NumKind::F32 => "f32",
+            NumKind::F64 => "f64",
+        }
+    }
+
+    /// バイト数
+    pub fn width(self) -> usize {
+        match self {
+            NumKind::U8 | NumKind::I8 => 1,
+            NumKind::U16 | NumKind::I16 => 2,
+            NumKind::U32 | NumKind::I32 | NumKind::F32 => 4,
+            NumKind::U64 | NumKind::I64 | NumKind::F64 => 8,
+        }
+    }
+
+    /// 整数型の値の範囲
+    fn range(self) -> (i128, i128) {
+        match self {
+            NumKind::U8 => (0, u8::MAX.into()),
+            NumKind::I8 => (i8::MIN.into(), i8::MAX.into()),
+            NumKind::U16 => (0, u16::MAX.into()),
+            NumKind::I16 => (i16::MIN.into(), i16::MAX.into()),
+            NumKind::U32 => (0, u32::MAX.into()),
+            NumKind::I32 => (i32::MIN.into(), i32::MAX.into()),
+            NumKind::U64 => (0, u64::MAX.into()),
+            NumKind::I64 => (i64::MIN.into(), i64::MAX.into()),
+            NumKind::F32 | NumKind::F64 => (0, 0),
+        }
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, NumKind::F32 | NumKind::F64)
+    }
+
+    /// data（型の幅）を指定したバイト順で読み、表示用の文字列にする
+    pub fn format(self, data: &[u8], big_endian: bool) -> String {
+        match (self, self.decode(data, big_endian)) {
+            // f32 は f64 に広げずに表示する
+            // 3.14 が 3.140000104904175 にならないように
+            (NumKind::F32, Value::Float(value)) => (value as f32).to_string(),
+            (_, Value::Float(value)) => value.to_string(),
+            (_, Value::Int(value)) => value.to_string(),
+        }
+    }
+
+    /// data（型の幅）を指定したバイト順で読んだ整数値（浮動小数点数なら None）
+    pub fn to_int(self, data: &[u8], big_endian: bool) -> Option<i128> {
+        match self.decode(data, big_endian) {
+            Value::Int(value) => Some(value),
+            Value::Float(_) => None,
+        }
+    }
+
+    /// data（型の幅）を指定したバイト順で読む
+    fn decode(self, data: &[u8], big_endian: bool) -> Value {
+        let raw = if big_endian {
+            data.iter().fold(0u64, |acc, &b| (acc << 8) | u64::from(b))
+        } else {
+            data.iter().rev().fold(0u64, |acc, &b| (acc << 8) | u64::from(b))
+        };
+        match self {
+            NumKind::U8 | NumKind::U16 | NumKind::U32 | NumKind::U64 => Value::Int(raw.into()),
+            NumKind::I8 => Value::Int((raw as i8).into()),
+            NumKind::I16 => Value::Int((raw as i16).into()),
+            NumKind::I32 => Value::Int((raw as i32).into()),
+            NumKind::I64 => Value::Int((raw as i64).into()),
+            NumKind::F32 => Value::Float(f32::from_bits(raw as u32).into()),
+            NumKind::F64 => Value::Float(f64::from_bits(raw)),
+        }
+    }
+}
+
+/// 読み出した値
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Int(i128),
+    Float(f64),
+}
+
+/// 指定した型で格納された数値に一致するパターン
+#[derive(Debug, Clone, PartialEq)]
+pub struct Numeric {
+    /// 型
+    pub kind: NumKind,
+    /// バイト順（None ならリトルエンディアン・ビッグエンディアンの両方）
+    pub big_endian: Option<bool>,
+    /// 探す値
+    value: Value,
+    /// 浮動小数点数の許容誤差
+    tolerance: f64,
+}
+
+impl Numeric {
+    /// `型[le|be]:値[~許容誤差]` を解釈（`型:` で始まらなければ None）
+    /// 整数は10進か `0x` 付きの16進、許容誤差は浮動小数点数だけに付けられる
+    pub fn parse(s: &str) -> Option<Result<Self, PatternError>> {
+        let (spec, value) = s.trim().split_once(':')?;
+        let spec = spec.to_ascii_lowercase();
+        let (name, big_endian) = if let Some(name) = spec.strip_suffix("le") {
+            (name, Some(false))
+        } else if let Some(name) = spec.strip_suffix("be") {
+            (name, Some(true))
+        } else {
+            (spec.as_str(), None)
+        };
+        let kind = NumKind::from_name(name)?;
+        Some(Self::parse_value(kind, big_endian, value.trim()))
+    }
+
+    fn parse_value(kind: NumKind, big_endian: Option<bool>, s: &str) -> Result<Self, PatternError> {
+        let invalid = || PatternError::InvalidValue(s.to_string());
+        let (value, tolerance) = match s.split_once('~') {
+            Some((value, tolerance)) => (value.trim(), Some(tolerance.trim())),
+            None => (s, None),
+        };
+        let (value, tolerance) = if kind.is_float() {
+            let value: f64 = value.parse().map_err(|_| invalid())?;
+            let tolerance: f64 = match tolerance {
+                Some(t) => t.parse().map_err(|_| invalid())?,
+                None => 0.0,
+            };
+            if !value.is_finite() || tolerance.is_nan() || tolerance < 0.0 {
+                return Err(invalid());
+            }
+            // 許容誤差がなければ、その型に丸めた値と完全に一致するものを探す
+            let value = match (kind, tolerance == 0.0) {
+                (NumKind::F32, true) => f64::from(value as f32),
+                _ => value,
+            };
+            (Value::Float(value), tolerance)
+        } else {
+            if tolerance.is_some() {
+                return Err(PatternError::ToleranceNotAllowed);
+            }
+            let (negative, digits) = match value.strip_prefix('-') {
+                Some(digits) => (true, digits),
+                None => (false, value),
+            };
+            // 符号は先頭の '-' だけ（"--1" や "0x-1" は不正）
+            if digits.contains(['-', '+']) {
+                return Err(invalid());
+            }
+            let magnitude = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+                Some(hex) => i128::from_str_radix(hex, 16),
+                None => digits.parse::<i128>(),
+            }
+            .map_err(|_| invalid())?;
+            let value = if negative { -magnitude } else { magnitude };
+            let (min, max) = kind.range();
+            if value < min || value > max {
+                return Err(PatternError::OutOfRange(value.to_string(), kind.name()));
+            }
+            (Value::Int(value), 0.0)
+        };
+        Ok(Self {
+            kind,
+            big_endian,
+            value,
+            tolerance,
+        })
+    }
+
+    /// バイト数
+    pub fn len(&self) -> usize {
+        self.kind.width()
+    }
+
+    /// 空かどうか（常に false）
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// data（同じ長さ）が値と一致するかどうか
+    pub fn matches(&self, data: &[u8]) -> bool {
+        if data.len() != self.len() {
+            return false;
+        }
+        let matches = |big_endian| match (self.kind.decode(data, big_endian), self.value) {
+            (Value::Int(found), Value::Int(value)) => found == value,
+            (Value::Float(found), Value::Float(value)) => (found - value).abs() <= self.tolerance,
+            _ => false,
+        };
+        // バイト順の指定がなければリトルエンディアンから試す
+        // 1バイトの型はどちらでも同じ
+        let both = self.big_endian.is_none() && self.len() > 1;
+        matches(self.big_endian.unwrap_or(false)) || (both && matches(true))
+    }
+
+    /// data の中で一致する位置（重なりを含む）
+    pub fn positions<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        data.windows(self.len())
+            .enumerate()
+            .filter(move |(_, w)| self.matches(w))
+            .map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Numeric, PatternError> {
+        Numeric::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_numeric_parse() {
+        assert!(Numeric::parse("hello").is_none());
+        assert!(Numeric::parse("x32:1").is_none());
+        assert_eq!(parse("U32LE:1337").unwrap().big_endian, Some(false));
+        assert_eq!(parse("u64:0xdeadbeef").unwrap().value, Value::Int(0xDEAD_BEEF));
+        assert_eq!(parse("i16be:-2").unwrap().value, Value::Int(-2));
+        assert_eq!(parse("u8:256"), Err(PatternError::OutOfRange("256".to_string(), "u8")));
+        assert_eq!(parse("u32:-1"), Err(PatternError::OutOfRange("-1".to_string(), "u32")));
+        assert_eq!(parse("i32:"), Err(PatternError::InvalidValue(String::new())));
+        assert_eq!(parse("i32:--1"), Err(PatternError::InvalidValue("--1".to_string())));
+        assert_eq!(parse("i32:1~2"), Err(PatternError::ToleranceNotAllowed));
+        assert!(parse("f32:3.14~-1").is_err());
+    }
+
+    #[test]
+    fn test_numeric_matches() {
+        // バイト順を指定しなければ両方に一致する
+        let pattern = parse("u32:1337").unwrap();
+        let data = [0x39, 0x05, 0x00, 0x00, 0x39, 0x05, 0x00, 0x00, 0x05, 0x39];
+        assert_eq!(pattern.positions(&data).collect::<Vec<_>>(), vec![0, 4, 6]);
+        assert_eq!(parse("u32le:1337").unwrap().positions(&data).collect::<Vec<_>>(), vec![0, 4]);
+        assert!(parse("i16be:-2").unwrap().matches(&[0xFF, 0xFE]));
+        assert!(!parse("i16be:-2").unwrap().matches(&[0xFE, 0xFF]));
+
+        let stored = 1.2345f32.to_le_bytes();
+        assert!(parse("f32:1.2345").unwrap().matches(&stored));
+        assert!(!parse("f32:1.2346").unwrap().matches(&stored));
+        assert!(parse("f32:1.2346~0.001").unwrap().matches(&stored));
+        assert!(parse("f64be:-0.5").unwrap().matches(&(-0.5f64).to_be_bytes()));
+    }
+}
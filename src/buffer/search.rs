@@ -0,0 +1,333 @@
+//! 検索パターン（バイト列・ワイルドカード付きHEX・数値・正規表現）
+
+use std::ops::Range;
+
+use regex::bytes::{Captures, Regex, RegexBuilder};
+
+use super::document::REGEX_OVERLAP;
+use super::{Document, Numeric, PatternError};
+
+/// 検索パターン
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// バイト列がそのまま一致
+    Bytes(Vec<u8>),
+    /// ワイルドカードを除いたビットが一致
+    Masked(Masked),
+    /// 指定した型で格納された数値
+    Numeric(Numeric),
+    /// バイト列に対する正規表現
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// start 以降で最初に一致する範囲
+    pub fn find(&self, doc: &Document, start: usize) -> Option<Range<usize>> {
+        self.find_until(doc, start, doc.len())
+    }
+
+    /// start 以降、limit より前から始まる最初の一致範囲
+    pub fn find_until(&self, doc: &Document, start: usize, limit: usize) -> Option<Range<usize>> {
+        match self {
+            Pattern::Bytes(bytes) => doc
+                .find_until(bytes, start, limit)
+                .map(|pos| pos..pos + bytes.len()),
+            Pattern::Masked(masked) => doc
+                .find_masked(masked, start, limit)
+                .map(|pos| pos..pos + masked.len()),
+            Pattern::Numeric(numeric) => doc
+                .find_numeric(numeric, start, limit)
+                .map(|pos| pos..pos + numeric.len()),
+            Pattern::Regex(re) => doc.find_regex(re, start, limit),
+        }
+    }
+
+    /// data の中の一致範囲を先頭から順に返す（next_start と同じ規則で進む）
+    pub fn matches_in<'a>(&'a self, data: &'a [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        match self {
+            Pattern::Bytes(bytes) => {
+                let n = bytes.len();
+                Box::new(
+                    data.windows(n.max(1))
+                        .enumerate()
+                        .filter(move |(_, w)| n > 0 && w == bytes)
+                        .map(move |(i, _)| i..i + n),
+                )
+            }
+            Pattern::Masked(masked) => {
+                Box::new(masked.positions(data).map(|i| i..i + masked.len()))
+            }
+            Pattern::Numeric(numeric) => {
+                Box::new(numeric.positions(data).map(|i| i..i + numeric.len()))
+            }
+            Pattern::Regex(re) => Box::new(
+                re.find_iter(data)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| m.range()),
+            ),
+        }
+    }
+
+    /// チャンクに分けて探す時に、
+    /// 境界をまたぐ一致を拾うため重ねて読むバイト数
+    pub fn overlap(&self) -> usize {
+        match self {
+            Pattern::Bytes(bytes) => bytes.len().saturating_sub(1),
+            Pattern::Masked(masked) => masked.len().saturating_sub(1),
+            Pattern::Numeric(numeric) => numeric.len() - 1,
+            Pattern::Regex(_) => REGEX_OVERLAP,
+        }
+    }
+
+    /// 一致の次に探し始める位置
+    /// 正規表現は一致の後ろ、それ以外は重なりも数える
+    pub fn next_start(&self, found: &Range<usize>) -> usize {
+        match self {
+            Pattern::Regex(_) => found.end,
+            Pattern::Bytes(_) | Pattern::Masked(_) | Pattern::Numeric(_) => found.start + 1,
+        }
+    }
+
+    /// end より前にある最後の一致範囲
+    /// バイト列は end までに終わるもの、正規表現は end より前から始まるもの
+    pub fn rfind(&self, doc: &Document, end: usize) -> Option<Range<usize>> {
+        match self {
+            Pattern::Bytes(bytes) => doc.rfind(bytes, end).map(|pos| pos..pos + bytes.len()),
+            Pattern::Masked(masked) => doc
+                .rfind_masked(masked, end)
+                .map(|pos| pos..pos + masked.len()),
+            Pattern::Numeric(numeric) => doc
+                .rfind_numeric(numeric, end)
+                .map(|pos| pos..pos + numeric.len()),
+            Pattern::Regex(re) => doc.rfind_regex(re, end),
+        }
+    }
+}
+
+impl From<Masked> for Pattern {
+    /// ワイルドカードがなければ単なるバイト列として扱う
+    fn from(masked: Masked) -> Self {
+        if masked.is_exact() {
+            Pattern::Bytes(masked.bytes)
+        } else {
+            Pattern::Masked(masked)
+        }
+    }
+}
+
+/// ニブル単位のワイルドカードを含むバイト列（`E8 ?? ?? 48 8B`, `4?`）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Masked {
+    /// 比較する値（ワイルドカードのビットは 0）
+    pub bytes: Vec<u8>,
+    /// 比較するビット（ワイルドカードのビットは 0）
+    pub mask: Vec<u8>,
+}
+
+impl Masked {
+    /// ワイルドカードのないバイト列
+    pub fn exact(bytes: Vec<u8>) -> Self {
+        let mask = vec![0xFF; bytes.len()];
+        Self { bytes, mask }
+    }
+
+    /// HEX文字列を解釈（`?` は1ニブルのワイルドカード）
+    /// 空白と `,` で区切ってよく、各区切りの先頭の `0x` は無視する
+    pub fn parse(s: &str) -> Result<Self, PatternError> {
+        let mut nibbles = Vec::new();
+        for token in s.split(|c: char| c.is_whitespace() || c == ',') {
+            let token = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token);
+            for c in token.chars() {
+                match c {
+                    '?' => nibbles.push(None),
+                    _ => nibbles.push(Some(
+                        c.to_digit(16).ok_or(PatternError::InvalidChar(c))? as u8
+                    )),
+                }
+            }
+        }
+        if nibbles.len() % 2 != 0 {
+            return Err(PatternError::OddLength);
+        }
+        let (bytes, mask) = nibbles
+            .chunks(2)
+            .map(|pair| {
+                let nibble = |n: Option<u8>| (n.unwrap_or(0), if n.is_some() { 0xF } else { 0 });
+                let ((high, high_mask), (low, low_mask)) = (nibble(pair[0]), nibble(pair[1]));
+                ((high << 4) | low, (high_mask << 4) | low_mask)
+            })
+            .unzip();
+        Ok(Self { bytes, mask })
+    }
+
+    /// バイト数
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// 空かどうか
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// ワイルドカードを含まないかどうか
+    pub fn is_exact(&self) -> bool {
+        self.mask.iter().all(|&m| m == 0xFF)
+    }
+
+    /// data（同じ長さ）と一致するかどうか
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() == self.len()
+            && data
+                .iter()
+                .zip(self.bytes.iter().zip(&self.mask))
+                .all(|(&d, (&b, &m))| d & m == b)
+    }
+
+    /// data の中で一致する位置（重なりを含む）
+    pub fn positions<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        // 空のパターンは長さ 1 の窓と一致しないので何も返さない
+        data.windows(self.len().max(1))
+            .enumerate()
+            .filter(move |(_, w)| self.matches(w))
+            .map(|(i, _)| i)
+    }
+
+    /// 置換後のバイト列（ワイルドカードの部分は置換前の同じ位置の値を残す）
+    /// 置換前の範囲より後ろにワイルドカードがあればエラー
+    pub fn apply(&self, matched: &[u8]) -> Result<Vec<u8>, PatternError> {
+        self.bytes
+            .iter()
+            .zip(&self.mask)
+            .enumerate()
+            .map(|(i, (&b, &m))| match matched.get(i) {
+                Some(&old) => Ok((old & !m) | b),
+                None if m == 0xFF => Ok(b),
+                None => Err(PatternError::WildcardOutOfRange(matched.len())),
+            })
+            .collect()
+    }
+}
+
+/// バイト列用の正規表現をコンパイル
+/// `\x7f` や `[\x00-\x1f]` は1バイトとして扱い、
+/// `.` は改行を含む任意の1バイトに一致する
+pub fn compile_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .unicode(false)
+        .dot_matches_new_line(true)
+        .build()
+}
+
+/// 置換文字列のキャプチャ参照を展開
+/// `\1` / `$1` / `${1}` / `${name}` はグループ、`\xHH` は1バイト、`\\` と `$$` はそれ自体
+pub fn expand_replacement(template: &str, caps: &Captures) -> Vec<u8> {
+    let bytes = template.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let group = |out: &mut Vec<u8>, name: &[u8]| {
+        let name = std::str::from_utf8(name).unwrap_or_default();
+        let m = match name.parse::<usize>() {
+            Ok(i) => caps.get(i),
+            Err(_) => caps.name(name),
+        };
+        if let Some(m) = m {
+            out.extend_from_slice(m.as_bytes());
+        }
+    };
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        let hex = rest
+            .get(2..4)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        let close = rest.iter().position(|&b| b == b'}');
+        match (rest[0], rest.get(1).copied(), close) {
+            (b'\\', Some(d), _) if d.is_ascii_digit() => {
+                group(&mut out, &rest[1..2]);
+                i += 2;
+            }
+            (b'\\', Some(b'x'), _) if hex.is_some() => {
+                out.extend(hex);
+                i += 4;
+            }
+            (b'\\', Some(b'\\'), _) | (b'$', Some(b'$'), _) => {
+                out.push(rest[0]);
+                i += 2;
+            }
+            (b'$', Some(b'{'), Some(close)) => {
+                group(&mut out, &rest[2..close]);
+                i += close + 1;
+            }
+            (b'$', Some(d), _) if d.is_ascii_digit() => {
+                let len = rest[1..].iter().take_while(|b| b.is_ascii_digit()).count();
+                group(&mut out, &rest[1..1 + len]);
+                i += 1 + len;
+            }
+            (b, _, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masked_parse() {
+        let masked = Masked::parse("E8 ?? 4? 0x8B").unwrap();
+        assert_eq!(masked.bytes, vec![0xE8, 0x00, 0x40, 0x8B]);
+        assert_eq!(masked.mask, vec![0xFF, 0x00, 0xF0, 0xFF]);
+        assert!(!masked.is_exact());
+        assert!(masked.matches(&[0xE8, 0x12, 0x4F, 0x8B]));
+        assert!(!masked.matches(&[0xE8, 0x12, 0x5F, 0x8B]));
+        assert_eq!(Masked::parse("de,AD").unwrap(), Masked::exact(vec![0xDE, 0xAD]));
+        assert_eq!(Masked::parse("DEAG"), Err(PatternError::InvalidChar('G')));
+        assert_eq!(Masked::parse("ABC"), Err(PatternError::OddLength));
+    }
+
+    #[test]
+    fn test_masked_positions_and_apply() {
+        let data = [0x90, 0xE8, 0x01, 0x02, 0x48, 0xE8, 0x03, 0x04, 0x48];
+        let masked = Masked::parse("E8 ?? ?? 48").unwrap();
+        assert_eq!(masked.positions(&data).collect::<Vec<_>>(), vec![1, 5]);
+        // 置換側のワイルドカードは元の値を残す
+        let to = Masked::parse("E9 ?? ?3 90").unwrap();
+        assert_eq!(to.apply(&data[1..5]), Ok(vec![0xE9, 0x01, 0x03, 0x90]));
+        // 一致した範囲より後ろは固定値だけ書ける
+        let longer = Masked::parse("E9 ?? ?? 90 90").unwrap();
+        assert_eq!(longer.apply(&data[1..5]), Ok(vec![0xE9, 0x01, 0x02, 0x90, 0x90]));
+        let past_end = Masked::parse("E9 ?? ?? 90 ??").unwrap();
+        assert_eq!(past_end.apply(&data[1..5]), Err(PatternError::WildcardOutOfRange(4)));
+    }
+
+    #[test]
+    fn test_compile_regex_bytes() {
+        let re = compile_regex(r"[\x00-\x1f]+\x7f").unwrap();
+        let m = re.find(b"AB\x01\x02\x7fCD").unwrap();
+        assert_eq!(m.range(), 2..5);
+        // 非ASCIIのバイトと改行にも一致する
+        let re = compile_regex(r"\xff.\xfe").unwrap();
+        assert!(re.is_match(b"\x00\xff\n\xfe"));
+        assert!(compile_regex(r"[\x00-").is_err());
+    }
+
+    #[test]
+    fn test_expand_replacement() {
+        let re = compile_regex(r"(?P<tag>[A-Z]+)=(\d+)").unwrap();
+        let caps = re.captures(b"KEY=42").unwrap();
+        assert_eq!(expand_replacement(r"\2:\1", &caps), b"42:KEY");
+        assert_eq!(expand_replacement("$2-${tag}-${1}", &caps), b"42-KEY-KEY");
+        assert_eq!(expand_replacement(r"\x00\xFF$$\\", &caps), b"\x00\xff$\\");
+        // 存在しないグループは空、閉じていない ${ はそのまま
+        assert_eq!(expand_replacement("$9${x", &caps), b"${x");
+        assert_eq!(expand_replacement("あ\\1", &caps), "あKEY".as_bytes());
+    }
+}
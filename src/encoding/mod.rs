@@ -33,6 +33,28 @@ impl CharEncoding {
         }
     }
 
+    /// 名前からエンコーディングを取得
+    /// 大文字小文字・`-` / `_` の有無は問わない
+    /// 検索クエリの `u16le:"text"` のような指定に使う
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name: String = name
+            .chars()
+            .filter(|c| *c != '-' && *c != '_')
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        Some(match name.as_str() {
+            "utf8" | "u8" => Self::Utf8,
+            "utf16le" | "u16le" => Self::Utf16Le,
+            "utf16be" | "u16be" => Self::Utf16Be,
+            "shiftjis" | "sjis" => Self::ShiftJis,
+            "eucjp" | "euc" => Self::EucJp,
+            "iso2022jp" | "jis" => Self::Iso2022Jp,
+            "ascii" => Self::Ascii,
+            "latin1" => Self::Latin1,
+            _ => return None,
+        })
+    }
+
     /// encoding_rsのEncodingを取得
     pub fn to_encoding(&self) -> &'static Encoding {
         match self {
@@ -71,6 +93,9 @@ pub fn decode_bytes(bytes: &[u8], encoding: CharEncoding) -> String {
 /// 文字列をバイト列にエンコード
 /// エンコードできない文字は置換文字になる
 pub fn encode_string(s: &str, encoding: CharEncoding) -> Vec<u8> {
+    if let Some(bytes) = encode_utf16(s, encoding) {
+        return bytes;
+    }
     let enc = encoding.to_encoding();
     let (result, _, _) = enc.encode(s);
     result.into_owned()
@@ -80,6 +105,9 @@ pub fn encode_string(s: &str, encoding: CharEncoding) -> Vec<u8> {
 /// エンコードできない場合は None を返す
 pub fn encode_char(ch: char, encoding: CharEncoding) -> Option<Vec<u8>> {
     let s: String = ch.to_string();
+    if let Some(bytes) = encode_utf16(&s, encoding) {
+        return Some(bytes);
+    }
     let enc = encoding.to_encoding();
     let (result, _, had_errors) = enc.encode(&s);
     if had_errors {
@@ -90,6 +118,17 @@ pub fn encode_char(ch: char, encoding: CharEncoding) -> Option<Vec<u8>> {
     }
 }
 
+/// UTF-16 へのエンコード
+/// encoding_rs は UTF-16 を出力できず UTF-8 になるため自前で行う
+fn encode_utf16(s: &str, encoding: CharEncoding) -> Option<Vec<u8>> {
+    let to_bytes: fn(u16) -> [u8; 2] = match encoding {
+        CharEncoding::Utf16Le => u16::to_le_bytes,
+        CharEncoding::Utf16Be => u16::to_be_bytes,
+        _ => return None,
+    };
+    Some(s.encode_utf16().flat_map(to_bytes).collect())
+}
+
 /// 1バイトを表示用文字に変換（ASCII範囲外は'.'）
 pub fn byte_to_char(byte: u8) -> char {
     if byte.is_ascii_graphic() || byte == b' ' {
@@ -102,7 +141,7 @@ pub fn byte_to_char(byte: u8) -> char {
 /// 書記素クラスタの表示幅を計算
 pub fn grapheme_width(s: &str) -> usize {
     s.graphemes(true)
-        .map(|g| UnicodeWidthStr::width(g))
+        .map(UnicodeWidthStr::width)
         .sum()
 }
 
@@ -363,3 +402,18 @@ fn is_displayable(s: &str) -> bool {
     }
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_utf16() {
+        assert_eq!(encode_string("Aあ", CharEncoding::Utf16Le), vec![0x41, 0x00, 0x42, 0x30]);
+        assert_eq!(encode_char('あ', CharEncoding::Utf16Be), Some(vec![0x30, 0x42]));
+        assert_eq!(encode_char('あ', CharEncoding::ShiftJis), Some(vec![0x82, 0xA0]));
+        assert_eq!(CharEncoding::from_name("Shift_JIS"), Some(CharEncoding::ShiftJis));
+        assert_eq!(CharEncoding::from_name("u16le"), Some(CharEncoding::Utf16Le));
+        assert_eq!(CharEncoding::from_name("u32le"), None);
+    }
+}
@@ -0,0 +1,510 @@
+//! ELF（ヘッダ、プログラムヘッダ、セクションヘッダ、シンボルテーブル）
+
+use std::ops::Range;
+
+use super::{FormatError, MAX_NAME, Reader};
+use crate::buffer::Document;
+
+/// ELF のマジックナンバー
+pub const MAGIC: &[u8] = b"\x7fELF";
+
+/// e_type: 再配置可能オブジェクト（.o）
+/// シンボルの値はセクション内のオフセット
+pub const ET_REL: u16 = 1;
+
+/// p_type: ロードされるセグメント
+pub const PT_LOAD: u32 = 1;
+
+/// sh_type: シンボルテーブル
+pub const SHT_SYMTAB: u32 = 2;
+/// sh_type: ファイル上にデータのないセクション（.bss）
+pub const SHT_NOBITS: u32 = 8;
+/// sh_type: 動的シンボルテーブル
+pub const SHT_DYNSYM: u32 = 11;
+
+/// 特殊なセクション番号の始まり
+/// SHN_ABS, SHN_COMMON など、ファイル上の位置を持たない
+const SHN_LORESERVE: u16 = 0xFF00;
+/// セクション名の文字列テーブルの番号がセクション 0 の sh_link にある
+const SHN_XINDEX: u16 = 0xFFFF;
+
+/// 読むシンボルの上限
+pub const MAX_SYMBOLS: usize = 1_000_000;
+
+/// 解析した ELF ファイル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Elf {
+    /// 64bit（ELFCLASS64）か
+    pub class64: bool,
+    /// ビッグエンディアン（ELFDATA2MSB）か
+    pub big_endian: bool,
+    /// e_type（ET_EXEC, ET_DYN など）
+    pub kind: u16,
+    /// e_machine
+    pub machine: u16,
+    /// エントリポイントの仮想アドレス
+    pub entry: u64,
+    /// プログラムヘッダ
+    pub segments: Vec<Segment>,
+    /// セクションヘッダ（番号順、0 番の空のセクションを含む）
+    pub sections: Vec<Section>,
+    /// 解析で読んだヘッダ・テーブルの範囲
+    /// 編集で解析し直す必要があるかの判定用
+    pub tables: Vec<Range<u64>>,
+}
+
+/// プログラムヘッダ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+}
+
+/// セクションヘッダ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub entry_size: u64,
+}
+
+impl Section {
+    /// ファイル上の範囲（.bss のようにデータがなければ空）
+    pub fn file_range(&self) -> std::ops::Range<u64> {
+        if self.kind == SHT_NOBITS {
+            self.offset..self.offset
+        } else {
+            self.offset..self.offset.saturating_add(self.size)
+        }
+    }
+}
+
+/// シンボル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    /// st_value
+    /// 実行ファイルでは仮想アドレス、.o ではセクション内のオフセット
+    pub value: u64,
+    pub size: u64,
+    /// st_info（下位4bit が種類、上位4bit が結合）
+    pub info: u8,
+    /// 定義されているセクションの番号
+    pub section: u16,
+}
+
+impl Elf {
+    /// doc の先頭を ELF として解析（シンボルテーブルは symbols で読む）
+    pub fn parse(doc: &Document) -> Result<Self, FormatError> {
+        let ident = Reader::new(doc, false).bytes(0, 16, "ELF header")?;
+        if &ident[..4] != MAGIC {
+            return Err(FormatError::BadMagic("ELF"));
+        }
+        let class64 = match ident[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(FormatError::Invalid("ELF class")),
+        };
+        let big_endian = match ident[5] {
+            1 => false,
+            2 => true,
+            _ => return Err(FormatError::Invalid("ELF byte order")),
+        };
+        let r = Reader::new(doc, big_endian);
+        let what = "ELF header";
+        let word = |at| if class64 { r.u64(at, what) } else { r.u32(at, what).map(u64::from) };
+        // 64bit では e_entry 以降のアドレス・オフセットが8バイトになる
+        let (phoff, shoff, rest) = if class64 { (32, 40, 52) } else { (28, 32, 40) };
+        let mut elf = Self {
+            class64,
+            big_endian,
+            kind: r.u16(16, what)?,
+            machine: r.u16(18, what)?,
+            entry: word(24)?,
+            segments: Vec::new(),
+            sections: Vec::new(),
+            tables: Vec::new(),
+        };
+        elf.tables.push(0..if class64 { 64 } else { 52 });
+        let phoff = word(phoff)?;
+        let shoff = word(shoff)?;
+        let phentsize = u64::from(r.u16(rest + 2, what)?);
+        let phnum = r.u16(rest + 4, what)?;
+        let shentsize = u64::from(r.u16(rest + 6, what)?);
+        let mut shnum = u64::from(r.u16(rest + 8, what)?);
+        let mut shstrndx = r.u16(rest + 10, what)?;
+
+        if phnum > 0 {
+            if phentsize < if class64 { 56 } else { 32 } {
+                return Err(FormatError::Invalid("program header size"));
+            }
+            if phoff.saturating_add(u64::from(phnum) * phentsize) > doc.len() as u64 {
+                return Err(FormatError::Truncated("program headers"));
+            }
+            for i in 0..u64::from(phnum) {
+                elf.segments.push(elf.read_segment(&r, phoff + i * phentsize)?);
+            }
+            elf.tables.push(phoff..phoff.saturating_add(u64::from(phnum) * phentsize));
+        }
+
+        if shoff != 0 {
+            if shentsize < if class64 { 64 } else { 40 } {
+                return Err(FormatError::Invalid("section header size"));
+            }
+            if shoff.saturating_add(shentsize) > doc.len() as u64 {
+                return Err(FormatError::Truncated("section headers"));
+            }
+            // セクションが多すぎる（0xFF00 以上）場合、
+            // 数と名前のテーブルの番号はセクション 0 にある
+            let first = elf.read_section(&r, shoff)?;
+            if shnum == 0 {
+                shnum = first.size;
+            }
+            if shstrndx == SHN_XINDEX {
+                shstrndx = first.link as u16;
+            }
+            if shnum > u64::from(u32::MAX)
+                || shoff.saturating_add(shnum * shentsize) > doc.len() as u64
+            {
+                return Err(FormatError::Truncated("section headers"));
+            }
+            let mut names = Vec::new();
+            for i in 0..shnum {
+                let (section, name) = elf.read_section_with_name(&r, shoff + i * shentsize)?;
+                elf.sections.push(section);
+                names.push(name);
+            }
+            elf.tables.push(shoff..shoff + shnum.max(1) * shentsize);
+            // 名前は文字列テーブルのセクションを読んでから引く
+            if let Some(strtab) = elf.sections.get(usize::from(shstrndx)).map(|s| s.offset) {
+                let last = names.iter().max().map_or(0, |&name| u64::from(name));
+                elf.tables.push(strtab..strtab.saturating_add(last + MAX_NAME as u64));
+                for (section, name) in elf.sections.iter_mut().zip(names) {
+                    section.name = r.c_str(strtab.saturating_add(u64::from(name)));
+                }
+            }
+        }
+        Ok(elf)
+    }
+
+    fn read_segment(&self, r: &Reader, at: u64) -> Result<Segment, FormatError> {
+        let what = "program header";
+        Ok(if self.class64 {
+            Segment {
+                kind: r.u32(at, what)?,
+                flags: r.u32(at + 4, what)?,
+                offset: r.u64(at + 8, what)?,
+                vaddr: r.u64(at + 16, what)?,
+                file_size: r.u64(at + 32, what)?,
+                mem_size: r.u64(at + 40, what)?,
+            }
+        } else {
+            Segment {
+                kind: r.u32(at, what)?,
+                offset: r.u32(at + 4, what)?.into(),
+                vaddr: r.u32(at + 8, what)?.into(),
+                file_size: r.u32(at + 16, what)?.into(),
+                mem_size: r.u32(at + 20, what)?.into(),
+                flags: r.u32(at + 24, what)?,
+            }
+        })
+    }
+
+    fn read_section(&self, r: &Reader, at: u64) -> Result<Section, FormatError> {
+        self.read_section_with_name(r, at).map(|(section, _)| section)
+    }
+
+    /// セクションヘッダと、名前の文字列テーブル内のオフセット
+    fn read_section_with_name(&self, r: &Reader, at: u64) -> Result<(Section, u32), FormatError> {
+        let what = "section header";
+        let name = r.u32(at, what)?;
+        let section = if self.class64 {
+            Section {
+                name: String::new(),
+                kind: r.u32(at + 4, what)?,
+                flags: r.u64(at + 8, what)?,
+                addr: r.u64(at + 16, what)?,
+                offset: r.u64(at + 24, what)?,
+                size: r.u64(at + 32, what)?,
+                link: r.u32(at + 40, what)?,
+                entry_size: r.u64(at + 56, what)?,
+            }
+        } else {
+            Section {
+                name: String::new(),
+                kind: r.u32(at + 4, what)?,
+                flags: r.u32(at + 8, what)?.into(),
+                addr: r.u32(at + 12, what)?.into(),
+                offset: r.u32(at + 16, what)?.into(),
+                size: r.u32(at + 20, what)?.into(),
+                link: r.u32(at + 24, what)?,
+                entry_size: r.u32(at + 36, what)?.into(),
+            }
+        };
+        Ok((section, name))
+    }
+
+    /// シンボルテーブル（.symtab と .dynsym）の名前のあるシンボル
+    pub fn symbols(&self, doc: &Document) -> Result<Vec<Symbol>, FormatError> {
+        let r = Reader::new(doc, self.big_endian);
+        let what = "symbol table";
+        let size = if self.class64 { 24 } else { 16 };
+        let mut symbols = Vec::new();
+        for table in self
+            .sections
+            .iter()
+            .filter(|s| matches!(s.kind, SHT_SYMTAB | SHT_DYNSYM))
+        {
+            let strtab = self
+                .sections
+                .get(table.link as usize)
+                .ok_or(FormatError::Invalid("symbol string table"))?;
+            let count = table.size / size;
+            if table.offset.saturating_add(table.size) > doc.len() as u64 {
+                return Err(FormatError::Truncated(what));
+            }
+            for i in 0..count {
+                let at = table.offset + i * size;
+                let name = r.u32(at, what)?;
+                if name == 0 {
+                    continue;
+                }
+                let symbol = if self.class64 {
+                    Symbol {
+                        name: String::new(),
+                        info: r.u8(at + 4, what)?,
+                        section: r.u16(at + 6, what)?,
+                        value: r.u64(at + 8, what)?,
+                        size: r.u64(at + 16, what)?,
+                    }
+                } else {
+                    Symbol {
+                        name: String::new(),
+                        value: r.u32(at + 4, what)?.into(),
+                        size: r.u32(at + 8, what)?.into(),
+                        info: r.u8(at + 12, what)?,
+                        section: r.u16(at + 14, what)?,
+                    }
+                };
+                symbols.push(Symbol {
+                    name: r.c_str(strtab.offset.saturating_add(u64::from(name))),
+                    ..symbol
+                });
+                if symbols.len() >= MAX_SYMBOLS {
+                    return Ok(symbols);
+                }
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// 名前の付いたセクション
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// ファイルオフセットを含むセクション（データのないセクションを除く）
+    pub fn section_at(&self, offset: u64) -> Option<&Section> {
+        self.sections.iter().find(|s| s.file_range().contains(&offset))
+    }
+
+    /// ファイルオフセットを仮想アドレスに
+    /// ロードされるセグメントの範囲内だけ
+    pub fn offset_to_vaddr(&self, offset: u64) -> Option<u64> {
+        self.segments
+            .iter()
+            .filter(|s| s.kind == PT_LOAD)
+            .find(|s| (s.offset..s.offset.saturating_add(s.file_size)).contains(&offset))
+            .map(|s| s.vaddr.wrapping_add(offset - s.offset))
+    }
+
+    /// 仮想アドレスをファイルオフセットに
+    /// ファイル上にデータがある範囲だけ
+    pub fn vaddr_to_offset(&self, vaddr: u64) -> Option<u64> {
+        self.segments
+            .iter()
+            .filter(|s| s.kind == PT_LOAD)
+            .find(|s| (s.vaddr..s.vaddr.saturating_add(s.file_size)).contains(&vaddr))
+            .and_then(|s| s.offset.checked_add(vaddr - s.vaddr))
+    }
+
+    /// シンボルのファイルオフセット（未定義・絶対値のシンボルは None）
+    pub fn symbol_offset(&self, symbol: &Symbol) -> Option<u64> {
+        if symbol.section == 0 || symbol.section >= SHN_LORESERVE {
+            return None;
+        }
+        if self.kind == ET_REL {
+            let section = self.sections.get(usize::from(symbol.section))?;
+            let offset = section.offset.checked_add(symbol.value)?;
+            return section.file_range().contains(&offset).then_some(offset);
+        }
+        self.vaddr_to_offset(symbol.value)
+    }
+
+    /// e_machine の名前
+    pub fn machine_name(&self) -> String {
+        machine_name(self.machine)
+    }
+}
+
+/// e_machine の名前
+pub fn machine_name(machine: u16) -> String {
+    match machine {
+        3 => "x86".to_string(),
+        8 => "MIPS".to_string(),
+        20 => "PowerPC".to_string(),
+        21 => "PowerPC64".to_string(),
+        40 => "ARM".to_string(),
+        62 => "x86-64".to_string(),
+        183 => "AArch64".to_string(),
+        243 => "RISC-V".to_string(),
+        machine => format!("machine {}", machine),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// .text と .symtab を持つ最小の ELF64 実行ファイル
+    /// 仮想アドレス 0x400000 にロード
+    fn sample() -> Vec<u8> {
+        let mut data = vec![0u8; 0x78];
+        data[..16].copy_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+        let put = |data: &mut Vec<u8>, at: usize, bytes: &[u8]| {
+            data[at..at + bytes.len()].copy_from_slice(bytes)
+        };
+        put(&mut data, 16, &2u16.to_le_bytes()); // ET_EXEC
+        put(&mut data, 18, &62u16.to_le_bytes());
+        put(&mut data, 24, &0x400078u64.to_le_bytes());
+        put(&mut data, 32, &0x40u64.to_le_bytes());
+        put(&mut data, 54, &56u16.to_le_bytes());
+        put(&mut data, 56, &1u16.to_le_bytes());
+        put(&mut data, 58, &64u16.to_le_bytes());
+        put(&mut data, 60, &5u16.to_le_bytes());
+        put(&mut data, 62, &4u16.to_le_bytes());
+
+        data.extend_from_slice(&[0x90; 16]); // .text 0x78..0x88
+        data.extend_from_slice(b"\0main\0"); // .strtab 0x88..0x8E
+        data.extend_from_slice(b"\0.text\0.symtab\0.strtab\0.shstrtab\0"); // .shstrtab 0x8E..0xAF
+        data.resize(0xB0, 0);
+        data.extend_from_slice(&[0; 24]); // .symtab 0xB0..0xE0
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&[0x12, 0]);
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&0x400080u64.to_le_bytes());
+        data.extend_from_slice(&8u64.to_le_bytes());
+
+        let shoff = data.len() as u64;
+        put(&mut data, 40, &shoff.to_le_bytes());
+        let section = |name: u32, kind: u32, addr: u64, offset: u64, size: u64, link: u32| {
+            let mut sh = Vec::new();
+            sh.extend_from_slice(&name.to_le_bytes());
+            sh.extend_from_slice(&kind.to_le_bytes());
+            sh.extend_from_slice(&[0; 8]);
+            sh.extend_from_slice(&addr.to_le_bytes());
+            sh.extend_from_slice(&offset.to_le_bytes());
+            sh.extend_from_slice(&size.to_le_bytes());
+            sh.extend_from_slice(&link.to_le_bytes());
+            sh.extend_from_slice(&[0; 12]);
+            sh.extend_from_slice(&(if kind == SHT_SYMTAB { 24u64 } else { 0 }).to_le_bytes());
+            sh
+        };
+        data.extend(section(0, 0, 0, 0, 0, 0));
+        data.extend(section(1, 1, 0x400078, 0x78, 16, 0));
+        data.extend(section(7, SHT_SYMTAB, 0, 0xB0, 48, 3));
+        data.extend(section(15, 3, 0, 0x88, 6, 0));
+        data.extend(section(23, 3, 0, 0x8E, 33, 0));
+
+        let len = data.len() as u64;
+        let mut ph = Vec::new();
+        ph.extend_from_slice(&PT_LOAD.to_le_bytes());
+        ph.extend_from_slice(&5u32.to_le_bytes());
+        ph.extend_from_slice(&0u64.to_le_bytes());
+        ph.extend_from_slice(&0x400000u64.to_le_bytes());
+        ph.extend_from_slice(&0x400000u64.to_le_bytes());
+        ph.extend_from_slice(&len.to_le_bytes());
+        ph.extend_from_slice(&len.to_le_bytes());
+        ph.extend_from_slice(&0x1000u64.to_le_bytes());
+        put(&mut data, 0x40, &ph);
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let doc = Document::from_bytes(sample());
+        let elf = Elf::parse(&doc).unwrap();
+        assert!(elf.class64 && !elf.big_endian);
+        assert_eq!(elf.machine_name(), "x86-64");
+        let names: Vec<&str> = elf.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["", ".text", ".symtab", ".strtab", ".shstrtab"]);
+        assert_eq!(elf.section_at(0x80).map(|s| s.name.as_str()), Some(".text"));
+        assert_eq!(elf.section_at(0x10), None);
+        assert_eq!(elf.offset_to_vaddr(0x80), Some(0x400080));
+        assert_eq!(elf.vaddr_to_offset(0x400078), Some(0x78));
+        assert_eq!(elf.vaddr_to_offset(0x500000), None);
+        assert_eq!(
+            elf.tables,
+            vec![
+                0..64,
+                0x40..0x78,
+                0xE0..0x220,
+                0x8E..0x8E + 23 + MAX_NAME as u64
+            ]
+        );
+
+        let symbols = elf.symbols(&doc).unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!((symbols[0].name.as_str(), symbols[0].size), ("main", 8));
+        assert_eq!(elf.symbol_offset(&symbols[0]), Some(0x80));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Elf::parse(&Document::from_bytes(b"MZ\0\0".to_vec())),
+            Err(FormatError::Truncated("ELF header"))
+        );
+        assert_eq!(
+            Elf::parse(&Document::from_bytes(vec![0; 64])),
+            Err(FormatError::BadMagic("ELF"))
+        );
+        let mut truncated = sample();
+        truncated.truncate(0x140);
+        assert_eq!(
+            Elf::parse(&Document::from_bytes(truncated)),
+            Err(FormatError::Truncated("section headers"))
+        );
+    }
+
+    #[test]
+    fn test_malformed_headers() {
+        let patched = |at: usize, bytes: &[u8]| {
+            let mut data = sample();
+            data[at..at + bytes.len()].copy_from_slice(bytes);
+            Elf::parse(&Document::from_bytes(data))
+        };
+        assert_eq!(patched(4, &[3]), Err(FormatError::Invalid("ELF class")));
+        assert_eq!(patched(5, &[0]), Err(FormatError::Invalid("ELF byte order")));
+        assert_eq!(patched(54, &[8, 0]), Err(FormatError::Invalid("program header size")));
+        // テーブルの位置がファイルの外（末尾近くの値でも桁あふれしない）
+        let far = (u64::MAX - 2).to_le_bytes();
+        assert_eq!(patched(32, &far), Err(FormatError::Truncated("program headers")));
+        assert_eq!(patched(40, &far), Err(FormatError::Truncated("section headers")));
+
+        // セグメントのファイルオフセットが大きすぎても、
+        // 仮想アドレスから求めない
+        let elf = patched(0x48, &far).unwrap();
+        assert_eq!(elf.vaddr_to_offset(0x400010), None);
+        assert_eq!(elf.offset_to_vaddr(0x10), None);
+    }
+}
@@ -0,0 +1,404 @@
+//! マジックナンバーによるファイル形式の判定と、埋め込まれたファイルの検出
+
+use std::fmt;
+
+use super::{elf, pe};
+
+/// identify に渡せば判定に足りる先頭の長さ（ISO 9660 のボリューム記述子まで）
+pub const HEAD_LEN: usize = 0x9000;
+
+/// ファイル形式のシグネチャ
+pub struct Signature {
+    /// 形式の名前
+    pub name: &'static str,
+    /// ファイルの先頭からマジックナンバーまでの距離
+    offset: usize,
+    magic: &'static [u8],
+    /// ファイルの先頭からのデータを確かめて詳細を返す
+    /// 形式が合わなければ None
+    detail: fn(&[u8]) -> Option<String>,
+}
+
+/// 判定した形式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// ファイルの先頭の位置
+    pub offset: usize,
+    /// 形式の名前
+    pub name: &'static str,
+    /// 大きさ・版などの詳細（なければ空）
+    pub detail: String,
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.detail.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}, {}", self.name, self.detail)
+        }
+    }
+}
+
+/// 既知のシグネチャ（同じマジックナンバーは先に書いたものから確かめる）
+pub const SIGNATURES: &[Signature] = &[
+    Signature { name: "ELF", offset: 0, magic: b"\x7fELF", detail: elf_detail },
+    Signature { name: "PE", offset: 0, magic: b"MZ", detail: pe_detail },
+    Signature { name: "Mach-O", offset: 0, magic: b"\xfe\xed\xfa\xce", detail: |d| {
+        macho(d, true, 32)
+    } },
+    Signature { name: "Mach-O", offset: 0, magic: b"\xfe\xed\xfa\xcf", detail: |d| {
+        macho(d, true, 64)
+    } },
+    Signature { name: "Mach-O", offset: 0, magic: b"\xce\xfa\xed\xfe", detail: |d| {
+        macho(d, false, 32)
+    } },
+    Signature { name: "Mach-O", offset: 0, magic: b"\xcf\xfa\xed\xfe", detail: |d| {
+        macho(d, false, 64)
+    } },
+    // CA FE BA BE は Mach-O のユニバーサルバイナリと Java のクラスファイルで共通
+    Signature { name: "Mach-O universal", offset: 0, magic: b"\xca\xfe\xba\xbe", detail: |d| {
+        let count = be32(d, 4)?;
+        (1..45).contains(&count).then(|| format!("{} architectures", count))
+    } },
+    Signature { name: "Java class", offset: 0, magic: b"\xca\xfe\xba\xbe", detail: |d| {
+        let (minor, major) = (be16(d, 4)?, be16(d, 6)?);
+        (major >= 45).then(|| format!("version {}.{}", major, minor))
+    } },
+    Signature { name: "PNG", offset: 0, magic: b"\x89PNG\r\n\x1a\n", detail: |d| {
+        if d.get(12..16)? != b"IHDR" {
+            return None;
+        }
+        Some(format!("{} x {}", be32(d, 16)?, be32(d, 20)?))
+    } },
+    Signature { name: "JPEG", offset: 0, magic: b"\xff\xd8\xff", detail: |d| {
+        match d.get(3)? {
+            0xE0..=0xEF | 0xDB | 0xC0..=0xC2 | 0xC4 | 0xFE => Some(match d.get(6..10) {
+                Some(b"JFIF") => "JFIF".to_string(),
+                Some(b"Exif") => "Exif".to_string(),
+                _ => String::new(),
+            }),
+            _ => None,
+        }
+    } },
+    Signature { name: "GIF", offset: 0, magic: b"GIF8", detail: |d| {
+        if !matches!(d.get(4..6)?, b"7a" | b"9a") {
+            return None;
+        }
+        Some(format!("{} x {}", le16(d, 6)?, le16(d, 8)?))
+    } },
+    Signature { name: "ZIP", offset: 0, magic: b"PK\x03\x04", detail: |d| {
+        // 展開に必要なバージョン（6.3 なら 63）
+        if le16(d, 4)? > 100 {
+            return None;
+        }
+        let len = usize::from(le16(d, 26)?);
+        let name = d.get(30..30 + len).filter(|name| !name.is_empty())?;
+        Some(format!("first entry {}", String::from_utf8_lossy(name)))
+    } },
+    Signature { name: "gzip", offset: 0, magic: b"\x1f\x8b\x08", detail: |d| {
+        let flags = *d.get(3)?;
+        if flags & 0xE0 != 0 {
+            return None;
+        }
+        // FEXTRA がなく FNAME があれば元のファイル名がヘッダの後ろにある
+        Some(match flags & 0x0C {
+            0x08 => format!("original name {}", c_str(d.get(10..)?)),
+            _ => String::new(),
+        })
+    } },
+    Signature { name: "xz", offset: 0, magic: b"\xfd7zXZ\0", detail: |d| {
+        (*d.get(6)? == 0).then(String::new)
+    } },
+    Signature { name: "zstd", offset: 0, magic: b"\x28\xb5\x2f\xfd", detail: |d| {
+        (d.get(4)? & 0x08 == 0).then(String::new)
+    } },
+    Signature { name: "bzip2", offset: 0, magic: b"BZh", detail: |d| {
+        let level = *d.get(3)?;
+        let block = d.get(4..10)?;
+        let block_magic = matches!(
+            block,
+            b"\x31\x41\x59\x26\x53\x59" | b"\x17\x72\x45\x38\x50\x90"
+        );
+        ((b'1'..=b'9').contains(&level) && block_magic)
+            .then(|| format!("block size {}00k", level as char))
+    } },
+    Signature { name: "7-Zip", offset: 0, magic: b"7z\xbc\xaf\x27\x1c", detail: |d| {
+        (*d.get(6)? == 0).then(|| format!("version 0.{}", d.get(7).copied().unwrap_or(0)))
+    } },
+    Signature { name: "RAR", offset: 0, magic: b"Rar!\x1a\x07", detail: |d| match d.get(6)? {
+        0 => Some("version 4".to_string()),
+        1 => Some("version 5".to_string()),
+        _ => None,
+    } },
+    Signature { name: "LZ4", offset: 0, magic: b"\x04\x22\x4d\x18", detail: |d| {
+        (d.get(4)? >> 6 == 1).then(String::new)
+    } },
+    Signature { name: "PDF", offset: 0, magic: b"%PDF-", detail: |d| {
+        let version = d.get(5..8)?;
+        (version[0].is_ascii_digit() && version[1] == b'.' && version[2].is_ascii_digit())
+            .then(|| format!("version {}", String::from_utf8_lossy(version)))
+    } },
+    Signature { name: "SQLite 3", offset: 0, magic: b"SQLite format 3\0", detail: |d| {
+        // 1 は 65536 を表す
+        let page = match be16(d, 16)? {
+            1 => 65536,
+            size if size.is_power_of_two() && size >= 512 => u32::from(size),
+            _ => return None,
+        };
+        Some(format!("page size {}", page))
+    } },
+    Signature { name: "UBI", offset: 0, magic: b"UBI#", detail: |d| {
+        (*d.get(4)? == 1).then(|| "erase counter header".to_string())
+    } },
+    Signature { name: "UBIFS", offset: 0, magic: b"\x31\x18\x10\x06", detail: |d| {
+        (*d.get(20)? < 13).then(String::new)
+    } },
+    Signature { name: "squashfs", offset: 0, magic: b"hsqs", detail: |d| squashfs(d, false) },
+    Signature { name: "squashfs", offset: 0, magic: b"sqsh", detail: |d| squashfs(d, true) },
+    Signature { name: "U-Boot image", offset: 0, magic: b"\x27\x05\x19\x56", detail: |d| {
+        let name = c_str(d.get(32..64)?);
+        Some(format!("\"{}\", {} bytes", name, be32(d, 12)?))
+    } },
+    Signature { name: "DTB", offset: 0, magic: b"\xd0\x0d\xfe\xed", detail: |d| {
+        let version = be32(d, 20)?;
+        ((1..=17).contains(&version) && be32(d, 24)? <= version)
+            .then(|| format!("version {}, {} bytes", version, be32(d, 4).unwrap_or(0)))
+    } },
+    Signature { name: "CramFS", offset: 0, magic: b"\x45\x3d\xcd\x28", detail: |d| {
+        let size = le32(d, 4).unwrap_or(0);
+        (d.get(16..32)? == b"Compressed ROMFS").then(|| format!("{} bytes", size))
+    } },
+    Signature { name: "cpio", offset: 0, magic: b"07070", detail: |d| match d.get(5)? {
+        b'1' | b'2' => Some("newc".to_string()),
+        b'7' => Some("odc".to_string()),
+        _ => None,
+    } },
+    Signature { name: "tar", offset: 257, magic: b"ustar", detail: |d| {
+        let name = c_str(d.get(..100)?);
+        matches!(d.get(262)?, 0 | b' ').then(|| format!("first entry {}", name))
+    } },
+    Signature { name: "ISO 9660", offset: 0x8001, magic: b"CD001", detail: |d| {
+        // 基本ボリューム記述子のボリューム名
+        let volume = String::from_utf8_lossy(d.get(0x8028..0x8048)?);
+        (*d.get(0x8000)? == 1).then(|| format!("\"{}\"", volume.trim_end()))
+    } },
+    Signature { name: "ext2/3/4", offset: 0x438, magic: b"\x53\xef", detail: |d| {
+        let log_block = le32(d, 0x418)?;
+        let ok = log_block <= 6 && le32(d, 0x44C)? <= 1 && (1..=7).contains(&le16(d, 0x43A)?);
+        ok.then(|| format!("{} KiB blocks", 1 << log_block))
+    } },
+    Signature { name: "WebAssembly", offset: 0, magic: b"\0asm", detail: |d| {
+        (le32(d, 4)? == 1).then(String::new)
+    } },
+    Signature { name: "DEX", offset: 0, magic: b"dex\n", detail: |d| {
+        let version = d.get(4..8)?;
+        (version[..3].iter().all(u8::is_ascii_digit) && version[3] == 0)
+            .then(|| format!("version {}", String::from_utf8_lossy(&version[..3])))
+    } },
+    Signature { name: "RIFF", offset: 0, magic: b"RIFF", detail: |d| {
+        let form = d.get(8..12)?;
+        form.iter().all(|b| b.is_ascii_alphanumeric() || *b == b' ')
+            .then(|| String::from_utf8_lossy(form).trim_end().to_string())
+    } },
+    Signature { name: "Ogg", offset: 0, magic: b"OggS", detail: |d| {
+        (*d.get(4)? == 0).then(String::new)
+    } },
+    Signature { name: "FLAC", offset: 0, magic: b"fLaC", detail: |d| {
+        (d.get(4)? & 0x7F == 0).then(String::new)
+    } },
+    Signature { name: "ISO media", offset: 4, magic: b"ftyp", detail: |d| {
+        let brand = d.get(8..12)?;
+        let printable = brand.iter().all(|b| b.is_ascii_graphic() || *b == b' ');
+        ((8..=1024).contains(&be32(d, 0)?) && printable)
+            .then(|| format!("brand {}", String::from_utf8_lossy(brand).trim_end()))
+    } },
+];
+
+/// data の先頭のファイル形式
+pub fn identify(data: &[u8]) -> Option<Match> {
+    SIGNATURES.iter().find_map(|sig| {
+        let magic = data.get(sig.offset..sig.offset + sig.magic.len())?;
+        if magic != sig.magic {
+            return None;
+        }
+        Some(Match {
+            offset: 0,
+            name: sig.name,
+            detail: (sig.detail)(data)?,
+        })
+    })
+}
+
+/// data の中に埋め込まれたファイルを探す
+/// マジックナンバーが一致し、ヘッダの内容も正しいもの
+pub fn scan(data: &[u8]) -> Vec<Match> {
+    // マジックナンバーの最初のバイトで候補を絞る
+    let mut by_first: Vec<Vec<&Signature>> = vec![Vec::new(); 256];
+    for sig in SIGNATURES {
+        by_first[usize::from(sig.magic[0])].push(sig);
+    }
+    let mut found = Vec::new();
+    for (pos, &byte) in data.iter().enumerate() {
+        for sig in &by_first[usize::from(byte)] {
+            if pos < sig.offset || !data[pos..].starts_with(sig.magic) {
+                continue;
+            }
+            let start = pos - sig.offset;
+            if let Some(detail) = (sig.detail)(&data[start..]) {
+                found.push(Match {
+                    offset: start,
+                    name: sig.name,
+                    detail,
+                });
+                break;
+            }
+        }
+    }
+    found.sort_by_key(|m| m.offset);
+    found
+}
+
+fn elf_detail(d: &[u8]) -> Option<String> {
+    let bits = match d.get(4)? {
+        1 => 32,
+        2 => 64,
+        _ => return None,
+    };
+    let big_endian = match d.get(5)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let read = |at| if big_endian { be16(d, at) } else { le16(d, at) };
+    let kind = match read(16)? {
+        1 => "relocatable",
+        2 => "executable",
+        3 => "shared object",
+        4 => "core file",
+        _ => "unknown type",
+    };
+    let order = if big_endian { "MSB" } else { "LSB" };
+    Some(format!("{}-bit {} {}, {}", bits, order, kind, elf::machine_name(read(18)?)))
+}
+
+fn pe_detail(d: &[u8]) -> Option<String> {
+    let nt = le32(d, 0x3C)? as usize;
+    if d.get(nt..nt.checked_add(4)?)? != b"PE\0\0" {
+        return None;
+    }
+    let bits = match le16(d, nt + 24)? {
+        0x10B => "PE32",
+        0x20B => "PE32+",
+        _ => return None,
+    };
+    let kind = if le16(d, nt + 22)? & 0x2000 != 0 { "DLL" } else { "executable" };
+    Some(format!("{} {}, {}", bits, kind, pe::machine_name(le16(d, nt + 4)?)))
+}
+
+fn macho(d: &[u8], big_endian: bool, bits: u32) -> Option<String> {
+    let read = |at| if big_endian { be32(d, at) } else { le32(d, at) };
+    let cpu = match read(4)? {
+        7 => "x86",
+        0x0100_0007 => "x86-64",
+        12 => "ARM",
+        0x0100_000C => "ARM64",
+        18 => "PowerPC",
+        0x0100_0012 => "PowerPC64",
+        _ => "unknown CPU",
+    };
+    let kind = match read(12)? {
+        1 => "object",
+        2 => "executable",
+        6 => "dylib",
+        8 => "bundle",
+        _ => "file",
+    };
+    Some(format!("{}-bit {}, {}", bits, kind, cpu))
+}
+
+fn squashfs(d: &[u8], big_endian: bool) -> Option<String> {
+    let read = |at| if big_endian { be16(d, at) } else { le16(d, at) };
+    let major = read(28)?;
+    if !(1..=4).contains(&major) {
+        return None;
+    }
+    let size = match (major, big_endian) {
+        (4, false) => format!(", {} bytes", le64(d, 40)?),
+        _ => String::new(),
+    };
+    Some(format!("version {}.{}{}", major, read(30)?, size))
+}
+
+/// NUL までの文字列
+fn c_str(d: &[u8]) -> String {
+    let len = d.iter().position(|&b| b == 0).unwrap_or(d.len());
+    String::from_utf8_lossy(&d[..len]).into_owned()
+}
+
+fn be16(d: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(d.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le16(d: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(d.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be32(d: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(d.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le32(d: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(d.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le64(d: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(d.get(at..at + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_identify() {
+        assert_eq!(identify(&png(640, 480)).unwrap().to_string(), "PNG, 640 x 480");
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.resize(20, 0);
+        elf[16] = 3;
+        elf[18] = 62;
+        assert_eq!(identify(&elf).unwrap().to_string(), "ELF, 64-bit LSB shared object, x86-64");
+        assert_eq!(identify(b"\xca\xfe\xba\xbe\0\0\0\x34").unwrap().name, "Java class");
+        assert_eq!(
+            identify(b"\xca\xfe\xba\xbe\0\0\0\x02").unwrap().to_string(),
+            "Mach-O universal, 2 architectures"
+        );
+        let mut tar = vec![0; 512];
+        tar[..5].copy_from_slice(b"a.txt");
+        tar[257..263].copy_from_slice(b"ustar\0");
+        assert_eq!(identify(&tar).unwrap().to_string(), "tar, first entry a.txt");
+        // マジックナンバーが一致してもヘッダが正しくなければ判定しない
+        assert_eq!(identify(b"MZ\0\0"), None);
+        assert_eq!(identify(b"%PDF-x"), None);
+        assert_eq!(identify(b""), None);
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut data = vec![0x1F; 100];
+        data.extend_from_slice(b"\x1f\x8b\x08\x08\0\0\0\0\0\x03a.bin\0");
+        data.extend_from_slice(b"garbage PK\x03\x04 MZ");
+        let at = data.len();
+        data.extend(png(1, 1));
+        let found: Vec<(usize, &str)> = scan(&data).iter().map(|m| (m.offset, m.name)).collect();
+        assert_eq!(found, vec![(100, "gzip"), (at, "PNG")]);
+        assert_eq!(scan(&data)[0].detail, "original name a.bin");
+    }
+}
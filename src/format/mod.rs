@@ -0,0 +1,207 @@
+//! 実行ファイルなどのファイル形式の解析
+
+pub mod elf;
+pub mod magic;
+pub mod pe;
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use thiserror::Error;
+
+use crate::buffer::Document;
+use elf::Elf;
+use pe::Pe;
+
+/// 名前として読む文字列の長さの上限
+const MAX_NAME: usize = 1024;
+
+/// ファイル形式の解析エラー
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum FormatError {
+    #[error("Not an {0} file")]
+    BadMagic(&'static str),
+    #[error("Truncated {0}")]
+    Truncated(&'static str),
+    #[error("Invalid {0}")]
+    Invalid(&'static str),
+}
+
+/// セクションを持つ実行ファイル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Executable {
+    Elf(Elf),
+    Pe(Pe),
+}
+
+/// セクションやシンボルの名前とファイル上の範囲
+pub type Target = (String, Range<u64>);
+
+impl Executable {
+    /// 先頭のマジックナンバーで形式を選んで解析（どちらでもなければ None）
+    pub fn parse(doc: &Document) -> Option<Self> {
+        let head = doc.get_range(0, elf::MAGIC.len().min(doc.len()))?;
+        if head.starts_with(elf::MAGIC) {
+            Elf::parse(doc).ok().map(Self::Elf)
+        } else if head.starts_with(pe::MAGIC) {
+            Pe::parse(doc).ok().map(Self::Pe)
+        } else {
+            None
+        }
+    }
+
+    /// 解析で読んだヘッダ・テーブルの範囲
+    /// ここが変わらなければ解析し直しても同じ結果になる
+    pub fn tables(&self) -> &[Range<u64>] {
+        match self {
+            Self::Elf(elf) => &elf.tables,
+            Self::Pe(pe) => &pe.tables,
+        }
+    }
+
+    /// ファイルオフセットを含むセクションの名前
+    pub fn section_at(&self, offset: u64) -> Option<&str> {
+        let name = match self {
+            Self::Elf(elf) => elf.section_at(offset)?.name.as_str(),
+            Self::Pe(pe) => pe.section_at(offset)?.name.as_str(),
+        };
+        (!name.is_empty()).then_some(name)
+    }
+
+    /// ファイルオフセットを仮想アドレスに
+    pub fn offset_to_vaddr(&self, offset: u64) -> Option<u64> {
+        match self {
+            Self::Elf(elf) => elf.offset_to_vaddr(offset),
+            Self::Pe(pe) => pe.offset_to_va(offset),
+        }
+    }
+
+    /// 仮想アドレスをファイルオフセットに
+    pub fn vaddr_to_offset(&self, vaddr: u64) -> Option<u64> {
+        match self {
+            Self::Elf(elf) => elf.vaddr_to_offset(vaddr),
+            Self::Pe(pe) => pe.va_to_offset(vaddr),
+        }
+    }
+
+    /// 名前のあるセクション
+    pub fn sections(&self) -> Vec<Target> {
+        let mut sections: Vec<Target> = match self {
+            Self::Elf(elf) => elf
+                .sections
+                .iter()
+                .map(|s| (s.name.clone(), s.file_range()))
+                .collect(),
+            Self::Pe(pe) => pe
+                .sections
+                .iter()
+                .map(|s| (s.name.clone(), s.file_range()))
+                .collect(),
+        };
+        sections.retain(|(name, _)| !name.is_empty());
+        sections
+    }
+
+    /// ファイル上に位置のあるシンボル
+    /// PE はエクスポートと、`DLL!関数` の名前で IAT の要素
+    pub fn symbols(&self, doc: &Document) -> Result<Vec<Target>, FormatError> {
+        Ok(match self {
+            Self::Elf(elf) => elf
+                .symbols(doc)?
+                .into_iter()
+                .filter_map(|sym| {
+                    let offset = elf.symbol_offset(&sym)?;
+                    Some((sym.name, offset..offset.saturating_add(sym.size)))
+                })
+                .collect(),
+            Self::Pe(pe) => {
+                let width = if pe.pe32_plus { 8 } else { 4 };
+                let exports = pe
+                    .exports(doc)?
+                    .into_iter()
+                    .filter(|e| e.forwarder.is_none())
+                    .filter_map(|e| {
+                        let offset = pe.rva_to_offset(e.rva)?;
+                        Some((
+                            e.name.unwrap_or_else(|| format!("#{}", e.ordinal)),
+                            offset..offset,
+                        ))
+                    });
+                let imports = pe.imports(doc)?.into_iter().filter_map(|i| {
+                    let offset = pe.rva_to_offset(i.thunk)?;
+                    Some((format!("{}!{}", i.dll, i.name), offset..offset + width))
+                });
+                exports.chain(imports).collect()
+            }
+        })
+    }
+}
+
+/// Document から指定したバイト順で整数・文字列を読む
+pub(crate) struct Reader<'a> {
+    doc: &'a Document,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(doc: &'a Document, big_endian: bool) -> Self {
+        Self { doc, big_endian }
+    }
+
+    /// at から len バイト（足りなければ what が途中で切れているエラー）
+    pub(crate) fn bytes(
+        &self,
+        at: u64,
+        len: usize,
+        what: &'static str,
+    ) -> Result<Cow<'a, [u8]>, FormatError> {
+        usize::try_from(at)
+            .ok()
+            .and_then(|at| Some((at, at.checked_add(len)?)))
+            .and_then(|(at, end)| self.doc.get_range(at, end))
+            .ok_or(FormatError::Truncated(what))
+    }
+
+    /// at から width バイトの符号なし整数
+    pub(crate) fn uint(
+        &self,
+        at: u64,
+        width: usize,
+        what: &'static str,
+    ) -> Result<u64, FormatError> {
+        let bytes = self.bytes(at, width, what)?;
+        let fold = |acc: u64, &b: &u8| (acc << 8) | u64::from(b);
+        Ok(if self.big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        })
+    }
+
+    pub(crate) fn u8(&self, at: u64, what: &'static str) -> Result<u8, FormatError> {
+        Ok(self.uint(at, 1, what)? as u8)
+    }
+
+    pub(crate) fn u16(&self, at: u64, what: &'static str) -> Result<u16, FormatError> {
+        Ok(self.uint(at, 2, what)? as u16)
+    }
+
+    pub(crate) fn u32(&self, at: u64, what: &'static str) -> Result<u32, FormatError> {
+        Ok(self.uint(at, 4, what)? as u32)
+    }
+
+    pub(crate) fn u64(&self, at: u64, what: &'static str) -> Result<u64, FormatError> {
+        self.uint(at, 8, what)
+    }
+
+    /// at から NUL までの文字列（長さは MAX_NAME まで、ファイルの終わりでも切る）
+    pub(crate) fn c_str(&self, at: u64) -> String {
+        let Ok(at) = usize::try_from(at) else {
+            return String::new();
+        };
+        let end = at.saturating_add(MAX_NAME).min(self.doc.len());
+        let data = self.doc.get_range(at, end.max(at)).unwrap_or_default();
+        let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        String::from_utf8_lossy(&data[..len]).into_owned()
+    }
+}
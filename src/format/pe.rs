@@ -0,0 +1,467 @@
+//! PE/COFF
+//! DOS ヘッダ、NT ヘッダ、セクションテーブル、インポート、エクスポート
+
+use std::ops::Range;
+
+use super::{FormatError, Reader};
+use crate::buffer::Document;
+
+/// DOS ヘッダのマジックナンバー
+pub const MAGIC: &[u8] = b"MZ";
+
+/// NT ヘッダのシグネチャ
+const SIGNATURE: &[u8] = b"PE\0\0";
+
+/// オプショナルヘッダのマジックナンバー（PE32 / PE32+）
+const PE32: u16 = 0x10B;
+const PE32_PLUS: u16 = 0x20B;
+
+/// データディレクトリの番号
+const DIRECTORY_EXPORT: usize = 0;
+const DIRECTORY_IMPORT: usize = 1;
+
+/// 読むインポート・エクスポートの上限
+pub const MAX_SYMBOLS: usize = 1_000_000;
+
+/// 解析した PE ファイル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pe {
+    /// COFF ヘッダの Machine
+    pub machine: u16,
+    /// COFF ヘッダの Characteristics
+    pub characteristics: u16,
+    /// PE32+（64bit）か
+    pub pe32_plus: bool,
+    /// エントリポイントの RVA
+    pub entry: u32,
+    /// イメージをロードする仮想アドレス
+    pub image_base: u64,
+    /// ヘッダ全体の大きさ（この範囲は RVA とファイルオフセットが等しい）
+    pub header_size: u32,
+    /// Subsystem
+    pub subsystem: u16,
+    /// データディレクトリ（RVA と大きさ）
+    pub directories: Vec<(u32, u32)>,
+    /// セクションテーブル
+    pub sections: Vec<Section>,
+    /// 解析で読んだヘッダ・テーブルの範囲
+    /// 編集で解析し直す必要があるかの判定用
+    pub tables: Vec<Range<u64>>,
+}
+
+/// セクションヘッダ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub virtual_address: u32,
+    pub virtual_size: u32,
+    /// ファイル上の位置（PointerToRawData）
+    pub raw_offset: u32,
+    /// ファイル上の大きさ（SizeOfRawData）
+    pub raw_size: u32,
+    pub characteristics: u32,
+}
+
+impl Section {
+    /// ファイル上の範囲
+    pub fn file_range(&self) -> std::ops::Range<u64> {
+        let start = u64::from(self.raw_offset);
+        start..start + u64::from(self.raw_size)
+    }
+}
+
+/// インポートする関数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// DLL 名
+    pub dll: String,
+    /// 関数名（序数で指定していれば `#5`）
+    pub name: String,
+    /// インポートアドレステーブルの要素の RVA
+    pub thunk: u32,
+}
+
+/// エクスポートする関数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    /// 関数名（序数だけでエクスポートしていれば None）
+    pub name: Option<String>,
+    pub ordinal: u32,
+    pub rva: u32,
+    /// 他の DLL への転送先（`NTDLL.RtlAllocateHeap`）
+    pub forwarder: Option<String>,
+}
+
+impl Pe {
+    /// doc の先頭を PE として解析
+    /// インポート・エクスポートは imports / exports で読む
+    pub fn parse(doc: &Document) -> Result<Self, FormatError> {
+        let r = Reader::new(doc, false);
+        if r.bytes(0, 2, "DOS header")?.as_ref() != MAGIC {
+            return Err(FormatError::BadMagic("PE"));
+        }
+        let nt = u64::from(r.u32(0x3C, "DOS header")?);
+        if r.bytes(nt, 4, "NT headers")?.as_ref() != SIGNATURE {
+            return Err(FormatError::BadMagic("PE"));
+        }
+        let what = "COFF header";
+        let coff = nt + 4;
+        let machine = r.u16(coff, what)?;
+        let section_count = u64::from(r.u16(coff + 2, what)?);
+        let optional_size = u64::from(r.u16(coff + 16, what)?);
+        let characteristics = r.u16(coff + 18, what)?;
+
+        let what = "optional header";
+        let optional = coff + 20;
+        let pe32_plus = match r.u16(optional, what)? {
+            PE32 => false,
+            PE32_PLUS => true,
+            _ => return Err(FormatError::Invalid("optional header magic")),
+        };
+        // PE32+ では ImageBase が8バイトで BaseOfData がなく、以降が4バイトずれる
+        let (image_base, directories_at) = if pe32_plus {
+            (r.u64(optional + 24, what)?, 112)
+        } else {
+            (r.u32(optional + 28, what)?.into(), 96)
+        };
+        let directory_count = r.u32(optional + directories_at - 4, what)?.min(16);
+        let directories = (0..u64::from(directory_count))
+            .map(|i| {
+                let at = optional + directories_at + i * 8;
+                Ok((r.u32(at, "data directories")?, r.u32(at + 4, "data directories")?))
+            })
+            .collect::<Result<_, FormatError>>()?;
+
+        let what = "section table";
+        let table = optional + optional_size;
+        let mut sections = Vec::new();
+        for i in 0..section_count {
+            let at = table + i * 40;
+            let name = r.bytes(at, 8, what)?;
+            let len = name.iter().position(|&b| b == 0).unwrap_or(8);
+            sections.push(Section {
+                name: String::from_utf8_lossy(&name[..len]).into_owned(),
+                virtual_size: r.u32(at + 8, what)?,
+                virtual_address: r.u32(at + 12, what)?,
+                raw_size: r.u32(at + 16, what)?,
+                raw_offset: r.u32(at + 20, what)?,
+                characteristics: r.u32(at + 36, what)?,
+            });
+        }
+
+        // NT ヘッダからセクションテーブルの終わりまで
+        // SizeOfOptionalHeader が小さくても読んだところまで
+        let end = (table + section_count * 40)
+            .max(optional + directories_at + u64::from(directory_count) * 8)
+            .max(optional + 70);
+        Ok(Self {
+            machine,
+            characteristics,
+            pe32_plus,
+            entry: r.u32(optional + 16, "optional header")?,
+            image_base,
+            header_size: r.u32(optional + 60, "optional header")?,
+            subsystem: r.u16(optional + 68, "optional header")?,
+            directories,
+            sections,
+            tables: vec![0..0x40, nt..end],
+        })
+    }
+
+    /// ファイルオフセットを含むセクション
+    pub fn section_at(&self, offset: u64) -> Option<&Section> {
+        self.sections.iter().find(|s| s.file_range().contains(&offset))
+    }
+
+    /// RVA をファイルオフセットに（ファイル上にデータがある範囲だけ）
+    pub fn rva_to_offset(&self, rva: u32) -> Option<u64> {
+        if rva < self.header_size {
+            return Some(rva.into());
+        }
+        self.sections.iter().find_map(|s| {
+            let delta = rva.checked_sub(s.virtual_address)?;
+            let size = if s.virtual_size == 0 { s.raw_size } else { s.virtual_size };
+            (delta < size && delta < s.raw_size).then(|| u64::from(s.raw_offset) + u64::from(delta))
+        })
+    }
+
+    /// ファイルオフセットを RVA に
+    pub fn offset_to_rva(&self, offset: u64) -> Option<u32> {
+        if offset < u64::from(self.header_size) {
+            return u32::try_from(offset).ok();
+        }
+        let section = self.section_at(offset)?;
+        let delta = u32::try_from(offset - u64::from(section.raw_offset)).ok()?;
+        section.virtual_address.checked_add(delta)
+    }
+
+    /// 仮想アドレスをファイルオフセットに
+    pub fn va_to_offset(&self, va: u64) -> Option<u64> {
+        let rva = u32::try_from(va.checked_sub(self.image_base)?).ok()?;
+        self.rva_to_offset(rva)
+    }
+
+    /// ファイルオフセットを仮想アドレスに
+    pub fn offset_to_va(&self, offset: u64) -> Option<u64> {
+        Some(self.image_base.wrapping_add(self.offset_to_rva(offset)?.into()))
+    }
+
+    /// データディレクトリの RVA と大きさ（なければ None）
+    fn directory(&self, index: usize) -> Option<(u32, u32)> {
+        self.directories.get(index).copied().filter(|&(rva, size)| rva != 0 && size != 0)
+    }
+
+    /// RVA の位置の文字列
+    fn string_at(&self, r: &Reader, rva: u32) -> Result<String, FormatError> {
+        let offset = self.rva_to_offset(rva).ok_or(FormatError::Invalid("string address"))?;
+        Ok(r.c_str(offset))
+    }
+
+    /// インポートする関数（DLL ごとに、インポートアドレステーブルの順）
+    pub fn imports(&self, doc: &Document) -> Result<Vec<Import>, FormatError> {
+        let Some((rva, _)) = self.directory(DIRECTORY_IMPORT) else {
+            return Ok(Vec::new());
+        };
+        let r = Reader::new(doc, false);
+        let what = "import directory";
+        let mut at = self.rva_to_offset(rva).ok_or(FormatError::Invalid(what))?;
+        let width = if self.pe32_plus { 8 } else { 4 };
+        let ordinal_flag = 1u64 << (width * 8 - 1);
+        let mut imports = Vec::new();
+        loop {
+            // ILT（OriginalFirstThunk）がなければ IAT（FirstThunk）から読む
+            let lookup = r.u32(at, what)?;
+            let name = r.u32(at + 12, what)?;
+            let iat = r.u32(at + 16, what)?;
+            if name == 0 && iat == 0 {
+                return Ok(imports);
+            }
+            let dll = self.string_at(&r, name)?;
+            let thunks = if lookup != 0 { lookup } else { iat };
+            let mut thunk_at = self
+                .rva_to_offset(thunks)
+                .ok_or(FormatError::Invalid("import lookup table"))?;
+            for i in 0u32.. {
+                let thunk = r.uint(thunk_at, width as usize, "import lookup table")?;
+                if thunk == 0 {
+                    break;
+                }
+                let name = if thunk & ordinal_flag != 0 {
+                    format!("#{}", thunk & 0xFFFF)
+                } else {
+                    // ヒント（2バイト）に続く名前
+                    self.string_at(&r, (thunk as u32).wrapping_add(2))?
+                };
+                imports.push(Import {
+                    dll: dll.clone(),
+                    name,
+                    thunk: iat.wrapping_add(i * width),
+                });
+                if imports.len() >= MAX_SYMBOLS {
+                    return Ok(imports);
+                }
+                thunk_at += u64::from(width);
+            }
+            at += 20;
+        }
+    }
+
+    /// エクスポートする関数（序数順）
+    pub fn exports(&self, doc: &Document) -> Result<Vec<Export>, FormatError> {
+        let Some((rva, size)) = self.directory(DIRECTORY_EXPORT) else {
+            return Ok(Vec::new());
+        };
+        let r = Reader::new(doc, false);
+        let what = "export directory";
+        let at = self.rva_to_offset(rva).ok_or(FormatError::Invalid(what))?;
+        let base = r.u32(at + 16, what)?;
+        let function_count = r.u32(at + 20, what)?;
+        let name_count = r.u32(at + 24, what)?;
+        let table = |rva: u32| self.rva_to_offset(rva).ok_or(FormatError::Invalid(what));
+        let functions = table(r.u32(at + 28, what)?)?;
+        let (names, ordinals) = if name_count > 0 {
+            (table(r.u32(at + 32, what)?)?, table(r.u32(at + 36, what)?)?)
+        } else {
+            (0, 0)
+        };
+        let function_count = (function_count as usize).min(MAX_SYMBOLS);
+
+        let mut exports: Vec<Export> = (0..function_count as u32)
+            .map(|i| {
+                Ok(Export {
+                    name: None,
+                    ordinal: base.wrapping_add(i),
+                    rva: r.u32(functions + u64::from(i) * 4, "export address table")?,
+                    forwarder: None,
+                })
+            })
+            .collect::<Result<_, FormatError>>()?;
+        for i in 0..u64::from(name_count).min(MAX_SYMBOLS as u64) {
+            let index = usize::from(r.u16(ordinals + i * 2, "export ordinal table")?);
+            let name = self.string_at(&r, r.u32(names + i * 4, "export name table")?)?;
+            if let Some(export) = exports.get_mut(index) {
+                export.name = Some(name);
+            }
+        }
+        // エクスポートディレクトリの中を指すアドレスは転送先の名前
+        for export in &mut exports {
+            if (rva..rva.saturating_add(size)).contains(&export.rva) {
+                export.forwarder = Some(self.string_at(&r, export.rva)?);
+            }
+        }
+        exports.retain(|export| export.rva != 0);
+        Ok(exports)
+    }
+
+    /// Machine の名前
+    pub fn machine_name(&self) -> String {
+        machine_name(self.machine)
+    }
+}
+
+/// Machine の名前
+pub fn machine_name(machine: u16) -> String {
+    match machine {
+        0x014C => "x86".to_string(),
+        0x01C4 => "ARM".to_string(),
+        0x8664 => "x86-64".to_string(),
+        0xAA64 => "ARM64".to_string(),
+        machine => format!("machine {:#06x}", machine),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// .text と、エクスポート・インポートを持つ .rdata からなる最小の PE32+ の DLL
+    fn sample() -> Vec<u8> {
+        let mut data = vec![0u8; 0x600];
+        let mut put = |at: usize, bytes: &[u8]| data[at..at + bytes.len()].copy_from_slice(bytes);
+        put(0, b"MZ");
+        put(0x3C, &0x40u32.to_le_bytes());
+        put(0x40, b"PE\0\0");
+        put(0x44, &0x8664u16.to_le_bytes());
+        put(0x46, &2u16.to_le_bytes());
+        put(0x54, &0xF0u16.to_le_bytes());
+        put(0x56, &0x2022u16.to_le_bytes());
+        // オプショナルヘッダ（0x58）
+        put(0x58, &PE32_PLUS.to_le_bytes());
+        put(0x58 + 16, &0x1010u32.to_le_bytes());
+        put(0x58 + 24, &0x1_8000_0000u64.to_le_bytes());
+        put(0x58 + 60, &0x200u32.to_le_bytes());
+        put(0x58 + 108, &16u32.to_le_bytes());
+        put(0x58 + 112, &0x2000u32.to_le_bytes());
+        put(0x58 + 116, &0x130u32.to_le_bytes());
+        put(0x58 + 120, &0x2080u32.to_le_bytes());
+        put(0x58 + 124, &40u32.to_le_bytes());
+        // セクションテーブル（0x148）
+        for (i, (name, va, raw)) in [
+            (b".text\0\0\0", 0x1000u32, 0x200u32),
+            (b".rdata\0\0", 0x2000, 0x400),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let at = 0x148 + i * 40;
+            put(at, *name);
+            put(at + 8, &0x180u32.to_le_bytes());
+            put(at + 12, &va.to_le_bytes());
+            put(at + 16, &0x200u32.to_le_bytes());
+            put(at + 20, &raw.to_le_bytes());
+        }
+        // .rdata（RVA 0x2000 = ファイル 0x400）
+        let rdata = |rva: usize| rva - 0x2000 + 0x400;
+        put(rdata(0x200C), &0x2100u32.to_le_bytes());
+        put(rdata(0x2010), &1u32.to_le_bytes());
+        put(rdata(0x2014), &2u32.to_le_bytes());
+        put(rdata(0x2018), &1u32.to_le_bytes());
+        put(rdata(0x201C), &0x2040u32.to_le_bytes());
+        put(rdata(0x2020), &0x2050u32.to_le_bytes());
+        put(rdata(0x2024), &0x2060u32.to_le_bytes());
+        put(rdata(0x2040), &0x1010u32.to_le_bytes());
+        put(rdata(0x2044), &0x2120u32.to_le_bytes());
+        put(rdata(0x2050), &0x2118u32.to_le_bytes());
+        put(rdata(0x2060), &0u16.to_le_bytes());
+        put(rdata(0x2100), b"test.dll\0");
+        put(rdata(0x2118), b"run\0");
+        put(rdata(0x2120), b"other.func\0");
+        // インポート（ILT 0x20C0、IAT 0x20E0）
+        put(rdata(0x2080), &0x20C0u32.to_le_bytes());
+        put(rdata(0x208C), &0x2140u32.to_le_bytes());
+        put(rdata(0x2090), &0x20E0u32.to_le_bytes());
+        for table in [0x20C0, 0x20E0] {
+            put(rdata(table), &0x2150u64.to_le_bytes());
+            put(rdata(table + 8), &0x8000_0000_0000_0005u64.to_le_bytes());
+        }
+        put(rdata(0x2140), b"KERNEL32.dll\0");
+        put(rdata(0x2152), b"ExitProcess\0");
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let doc = Document::from_bytes(sample());
+        let pe = Pe::parse(&doc).unwrap();
+        assert!(pe.pe32_plus);
+        assert_eq!(pe.machine_name(), "x86-64");
+        assert_eq!(pe.image_base, 0x1_8000_0000);
+        let names: Vec<&str> = pe.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec![".text", ".rdata"]);
+        assert_eq!(pe.section_at(0x210).map(|s| s.name.as_str()), Some(".text"));
+        assert_eq!(pe.rva_to_offset(0x1010), Some(0x210));
+        assert_eq!(pe.rva_to_offset(0x40), Some(0x40));
+        // 仮想サイズを超えた範囲・セクションのない範囲
+        assert_eq!(pe.rva_to_offset(0x1190), None);
+        assert_eq!(pe.rva_to_offset(0x3000), None);
+        assert_eq!(pe.va_to_offset(0x1_8000_2000), Some(0x400));
+        assert_eq!(pe.offset_to_va(0x210), Some(0x1_8000_1010));
+    }
+
+    #[test]
+    fn test_imports_and_exports() {
+        let doc = Document::from_bytes(sample());
+        let pe = Pe::parse(&doc).unwrap();
+        let imports = pe.imports(&doc).unwrap();
+        let names: Vec<(&str, &str, u32)> = imports
+            .iter()
+            .map(|i| (i.dll.as_str(), i.name.as_str(), i.thunk))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("KERNEL32.dll", "ExitProcess", 0x20E0),
+                ("KERNEL32.dll", "#5", 0x20E8)
+            ]
+        );
+
+        let exports = pe.exports(&doc).unwrap();
+        assert_eq!(exports.len(), 2);
+        assert_eq!(
+            (
+                exports[0].name.as_deref(),
+                exports[0].ordinal,
+                exports[0].rva
+            ),
+            (Some("run"), 1, 0x1010)
+        );
+        assert_eq!(
+            (exports[1].name.as_deref(), exports[1].forwarder.as_deref()),
+            (None, Some("other.func"))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Pe::parse(&Document::from_bytes(b"\x7fELF".to_vec())),
+            Err(FormatError::BadMagic("PE"))
+        );
+        let mut data = sample();
+        data.truncate(0x160);
+        assert_eq!(
+            Pe::parse(&Document::from_bytes(data)),
+            Err(FormatError::Truncated("section table"))
+        );
+    }
+}
@@ -6,4 +6,9 @@ pub mod app;
 pub mod buffer;
 pub mod clipboard;
 pub mod encoding;
+pub mod format;
+pub mod template;
 pub mod ui;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,334 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use super::{Expr, FieldDef, StructDef, Template, TemplateError, TypeRef, MAX_DEPTH, MAX_NODES};
+use crate::buffer::{Document, NumKind};
+
+/// 文字列として表示する char 配列の長さの上限（超えた分は省略）
+const MAX_TEXT: usize = 48;
+
+/// HEXで表示する u8 配列の長さの上限（超えた分は省略）
+const MAX_BYTES: usize = 16;
+
+/// テンプレートを当てはめた結果のフィールド
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    /// フィールド名（配列の要素は `items[3]`）
+    pub name: String,
+    /// 型名（`u32`, `Entry`, `char[4]`）
+    pub type_name: String,
+    /// ファイル上の範囲
+    /// 構造体・配列は @ で離れた位置に置いたフィールドを含まない
+    pub range: Range<usize>,
+    /// 表示用の値（構造体・配列は空）
+    pub value: String,
+    /// 構造体のフィールド・配列の要素
+    pub children: Vec<Node>,
+    /// 整数の値（長さ・オフセットの参照用）
+    number: Option<i128>,
+}
+
+impl Node {
+    /// path（子の添字の列）の位置にあるノード
+    pub fn get(&self, path: &[usize]) -> Option<&Node> {
+        path.iter().try_fold(self, |node, &i| node.children.get(i))
+    }
+}
+
+impl Template {
+    /// doc の start からルートの構造体として読む
+    pub fn apply(&self, doc: &Document, start: usize) -> Result<Node, TemplateError> {
+        let root = self.structs.last().ok_or(TemplateError::Empty)?;
+        let mut evaluator = Evaluator {
+            template: self,
+            doc,
+            base: start,
+            nodes: 0,
+            scopes: Vec::new(),
+        };
+        evaluator.struct_node(root, root.name.clone(), start, self.big_endian, 0)
+    }
+
+    fn find_struct(&self, name: &str) -> Option<&StructDef> {
+        self.structs.iter().find(|s| s.name == name)
+    }
+}
+
+/// テンプレートの適用中の状態
+struct Evaluator<'a> {
+    template: &'a Template,
+    doc: &'a Document,
+    /// テンプレートを当てはめた位置（@ のオフセットの起点）
+    base: usize,
+    /// 作ったノードの数
+    nodes: usize,
+    /// 読んでいる構造体ごとの、読み終えたフィールド（内側が後ろ）
+    scopes: Vec<Vec<Node>>,
+}
+
+impl<'a> Evaluator<'a> {
+    /// 構造体を pos から読む
+    fn struct_node(
+        &mut self,
+        def: &'a StructDef,
+        name: String,
+        pos: usize,
+        big_endian: bool,
+        depth: usize,
+    ) -> Result<Node, TemplateError> {
+        if depth > MAX_DEPTH {
+            return Err(TemplateError::TooDeep);
+        }
+        self.count_node()?;
+        self.scopes.push(Vec::new());
+        let mut end = pos;
+        for field in &def.fields {
+            let at = match &field.offset {
+                Some(offset) => {
+                    let offset = self.eval(offset, field)?;
+                    usize::try_from(offset)
+                        .ok()
+                        .and_then(|offset| self.base.checked_add(offset))
+                        .ok_or(TemplateError::InvalidValue(field.line, offset))?
+                }
+                None => end,
+            };
+            let node = self.field_node(field, at, big_endian, depth)?;
+            // @ で置いたフィールドの後ろには続けない
+            if field.offset.is_none() {
+                end = node.range.end;
+            }
+            self.scopes.last_mut().expect("scope pushed above").push(node);
+        }
+        let children = self.scopes.pop().unwrap_or_default();
+        Ok(Node {
+            name,
+            type_name: def.name.clone(),
+            range: pos..end,
+            value: String::new(),
+            children,
+            number: None,
+        })
+    }
+
+    /// フィールド（配列なら要素をすべて）を at から読む
+    fn field_node(
+        &mut self,
+        field: &'a FieldDef,
+        at: usize,
+        big_endian: bool,
+        depth: usize,
+    ) -> Result<Node, TemplateError> {
+        let big_endian = field.big_endian.unwrap_or(big_endian);
+        let Some(count) = &field.count else {
+            return self.value_node(field, field.name.clone(), at, big_endian, depth);
+        };
+        let count = self.eval(count, field)?;
+        let count =
+            usize::try_from(count).map_err(|_| TemplateError::InvalidValue(field.line, count))?;
+        let type_name = format!("{}[{}]", type_name(&field.ty), count);
+        // 文字列・バイト列は要素に分けずに1つのフィールドとして表示する
+        if matches!(field.ty, TypeRef::Char | TypeRef::Num(NumKind::U8)) {
+            self.count_node()?;
+            // 範囲だけ確かめて、表示する分だけ読む
+            // 巨大な配列でもコピーしない
+            if at.checked_add(count).is_none_or(|end| end > self.doc.len()) {
+                return Err(TemplateError::PastEnd(field.line, field.name.clone(), at));
+            }
+            let value = if field.ty == TypeRef::Char {
+                let shown = self.read(field, at, count.min(MAX_TEXT))?;
+                format!("\"{}\"{}", shown.escape_ascii(), if count > MAX_TEXT { "…" } else { "" })
+            } else {
+                let data = self.read(field, at, count.min(MAX_BYTES))?;
+                let shown: Vec<String> = data.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("{}{}", shown.join(" "), if count > MAX_BYTES { " …" } else { "" })
+            };
+            return Ok(Node {
+                name: field.name.clone(),
+                type_name,
+                range: at..at + count,
+                value,
+                children: Vec::new(),
+                number: None,
+            });
+        }
+        self.count_node()?;
+        let mut children = Vec::new();
+        let mut end = at;
+        for i in 0..count {
+            let node = self.value_node(
+                field,
+                format!("{}[{}]", field.name, i),
+                end,
+                big_endian,
+                depth,
+            )?;
+            end = node.range.end;
+            children.push(node);
+        }
+        Ok(Node {
+            name: field.name.clone(),
+            type_name,
+            range: at..end,
+            value: String::new(),
+            children,
+            number: None,
+        })
+    }
+
+    /// 型の値1つを at から読む
+    fn value_node(
+        &mut self,
+        field: &'a FieldDef,
+        name: String,
+        at: usize,
+        big_endian: bool,
+        depth: usize,
+    ) -> Result<Node, TemplateError> {
+        let kind = match &field.ty {
+            TypeRef::Num(kind) => *kind,
+            TypeRef::Char => NumKind::U8,
+            TypeRef::Struct(name_of_type) => {
+                let def = self
+                    .template
+                    .find_struct(name_of_type)
+                    .ok_or_else(|| TemplateError::UnknownType(field.line, name_of_type.clone()))?;
+                return self.struct_node(def, name, at, big_endian, depth + 1);
+            }
+        };
+        self.count_node()?;
+        let data = self.read(field, at, kind.width())?;
+        let value = match field.ty {
+            TypeRef::Char => format!("'{}'", data.escape_ascii()),
+            _ => kind.format(&data, big_endian),
+        };
+        Ok(Node {
+            name,
+            type_name: type_name(&field.ty),
+            range: at..at + kind.width(),
+            value,
+            children: Vec::new(),
+            number: kind.to_int(&data, big_endian),
+        })
+    }
+
+    /// at から len バイト読む
+    fn read(
+        &self,
+        field: &FieldDef,
+        at: usize,
+        len: usize,
+    ) -> Result<Cow<'a, [u8]>, TemplateError> {
+        at.checked_add(len)
+            .and_then(|end| self.doc.get_range(at, end))
+            .ok_or_else(|| TemplateError::PastEnd(field.line, field.name.clone(), at))
+    }
+
+    fn count_node(&mut self) -> Result<(), TemplateError> {
+        self.nodes += 1;
+        if self.nodes > MAX_NODES {
+            return Err(TemplateError::TooManyFields);
+        }
+        Ok(())
+    }
+
+    /// 式の値（フィールドは内側の構造体から順に、読み終えたものを探す）
+    fn eval(&self, expr: &Expr, field: &FieldDef) -> Result<i128, TemplateError> {
+        Ok(match expr {
+            Expr::Num(value) => *value,
+            Expr::Field(path) => {
+                let name = path.join(".");
+                let node = self
+                    .scopes
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.iter().rev().find(|n| n.name == path[0]))
+                    .and_then(|node| {
+                        path[1..].iter().try_fold(node, |node, part| {
+                            node.children.iter().find(|c| &c.name == part)
+                        })
+                    })
+                    .ok_or_else(|| TemplateError::UnknownField(field.line, name.clone()))?;
+                node.number
+                    .ok_or(TemplateError::NotAnInteger(field.line, name))?
+            }
+            Expr::Add(a, b) => self.eval(a, field)?.saturating_add(self.eval(b, field)?),
+            Expr::Sub(a, b) => self.eval(a, field)?.saturating_sub(self.eval(b, field)?),
+            Expr::Mul(a, b) => self.eval(a, field)?.saturating_mul(self.eval(b, field)?),
+        })
+    }
+}
+
+/// 型名（配列の要素数を除く）
+fn type_name(ty: &TypeRef) -> String {
+    match ty {
+        TypeRef::Num(kind) => kind.name().to_string(),
+        TypeRef::Char => "char".to_string(),
+        TypeRef::Struct(name) => name.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "
+        struct Entry { u8 len; char name[len]; }
+        struct Header {
+            char magic[2];
+            be u16 count;
+            u8 table;
+            Entry entries[count] @ table;
+            u8 after;
+        }";
+
+    #[test]
+    fn test_apply() {
+        let template = Template::parse(TEMPLATE).unwrap();
+        let data = b"..HX\x00\x02\x06\xAA\x02ab\x03cde".to_vec();
+        let doc = Document::from_bytes(data);
+        let root = template.apply(&doc, 2).unwrap();
+        assert_eq!(root.range, 2..8);
+        assert_eq!(root.children[0].value, "\"HX\"");
+        assert_eq!(root.children[1].value, "2");
+        let entries = &root.children[3];
+        assert_eq!((entries.type_name.as_str(), entries.range.clone()), ("Entry[2]", 8..15));
+        assert_eq!(root.get(&[3, 1, 1]).unwrap().value, "\"cde\"");
+        assert_eq!(root.get(&[3, 1]).unwrap().name, "entries[1]");
+        // @ で置いたフィールドの後ろではなく、直前のフィールドの後ろに続く
+        assert_eq!(root.children[4].range, 7..8);
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let template = Template::parse(TEMPLATE).unwrap();
+        let doc = Document::from_bytes(b"HX\x00\x09\x05".to_vec());
+        assert_eq!(
+            template.apply(&doc, 0),
+            Err(TemplateError::PastEnd(2, "len".to_string(), 5))
+        );
+        let recursive = Template::parse("struct A { u8 x; A next; }").unwrap();
+        assert_eq!(
+            recursive.apply(&Document::from_bytes(vec![0; 1000]), 0),
+            Err(TemplateError::TooDeep)
+        );
+        let unknown = Template::parse("struct A { u8 x[y]; }").unwrap();
+        assert_eq!(
+            unknown.apply(&doc, 0),
+            Err(TemplateError::UnknownField(1, "y".to_string()))
+        );
+        let long = Template::parse("struct A { u8 data[20]; char text[100]; }").unwrap();
+        let doc = Document::from_bytes((0..40).collect());
+        assert_eq!(
+            long.apply(&doc, 0),
+            Err(TemplateError::PastEnd(1, "text".to_string(), 20))
+        );
+        let doc = Document::from_bytes((0..120).collect());
+        let root = long.apply(&doc, 0).unwrap();
+        assert_eq!(
+            root.children[0].value,
+            "00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F …"
+        );
+        assert_eq!(root.children[1].range, 20..120);
+    }
+}
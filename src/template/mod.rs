@@ -0,0 +1,121 @@
+//! 構造体テンプレート
+//! C の構造体に似た書式でバイナリのレイアウトを記述し、データに当てはめる
+//!
+//! ```text
+//! endian little;              // 既定のバイト順（省略時はリトルエンディアン）
+//!
+//! struct Entry {
+//!     u32  id;
+//!     u32  size;
+//!     u8   data[size];         // 前のフィールドを長さに使う
+//! }
+//!
+//! struct Header {              // 最後の構造体が全体
+//!     char magic[4];
+//!     be u16 count;            // フィールドごとのバイト順
+//!     u32  table;
+//!     Entry entries[count] @ table;   // テンプレートの先頭からのオフセット
+//! }
+//! ```
+
+mod eval;
+mod parse;
+
+pub use eval::Node;
+
+use thiserror::Error;
+
+use crate::buffer::NumKind;
+
+/// 構造体のネストの上限（自分自身を含む構造体の無限再帰を防ぐ）
+pub const MAX_DEPTH: usize = 64;
+
+/// 1回の適用で作るフィールドの上限
+pub const MAX_NODES: usize = 100_000;
+
+/// テンプレートの解釈・適用のエラー
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("Line {0}: {1}")]
+    Syntax(usize, String),
+    #[error("Line {0}: Unknown type {1:?}")]
+    UnknownType(usize, String),
+    #[error("Line {0}: Unknown field {1:?}")]
+    UnknownField(usize, String),
+    #[error("Line {0}: {1:?} is not an integer")]
+    NotAnInteger(usize, String),
+    #[error("Line {0}: Invalid length or offset {1}")]
+    InvalidValue(usize, i128),
+    #[error("Line {0}: {1} at {2:08X} is past the end of the data")]
+    PastEnd(usize, String, usize),
+    #[error("No struct defined")]
+    Empty,
+    #[error("Too many fields (over {MAX_NODES})")]
+    TooManyFields,
+    #[error("Structs nested too deeply (over {MAX_DEPTH})")]
+    TooDeep,
+}
+
+/// 解釈済みのテンプレート
+#[derive(Debug, Clone)]
+pub struct Template {
+    /// 定義順の構造体（最後のものを当てはめる）
+    structs: Vec<StructDef>,
+    /// 既定のバイト順
+    big_endian: bool,
+}
+
+/// 構造体の定義
+#[derive(Debug, Clone)]
+struct StructDef {
+    name: String,
+    fields: Vec<FieldDef>,
+}
+
+/// フィールドの定義
+#[derive(Debug, Clone)]
+struct FieldDef {
+    name: String,
+    ty: TypeRef,
+    /// バイト順の指定（None ならテンプレートの既定）
+    big_endian: Option<bool>,
+    /// 配列の要素数
+    count: Option<Expr>,
+    /// テンプレートの先頭からのオフセット
+    /// 指定すると後続のフィールドの位置は進めない
+    offset: Option<Expr>,
+    /// 定義した行（エラー表示用）
+    line: usize,
+}
+
+/// フィールドの型
+#[derive(Debug, Clone, PartialEq)]
+enum TypeRef {
+    Num(NumKind),
+    /// 1バイトの文字（配列は文字列として表示する）
+    Char,
+    Struct(String),
+}
+
+/// 配列の長さ・オフセットの式
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Num(i128),
+    /// 前のフィールドの値（`header.count` のように構造体の中も参照できる）
+    Field(Vec<String>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+}
+
+impl Template {
+    /// テンプレートを解釈
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        parse::parse(source)
+    }
+
+    /// ルート（最後に定義した）構造体の名前
+    pub fn root_name(&self) -> &str {
+        self.structs.last().map_or("", |s| s.name.as_str())
+    }
+}
@@ -0,0 +1,333 @@
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use super::{Expr, FieldDef, StructDef, Template, TemplateError, TypeRef};
+use crate::buffer::NumKind;
+
+/// 字句
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(i128),
+    Punct(char),
+}
+
+/// 字句に分ける（`//` と `/* */` のコメントを除く）
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => {
+                            line += usize::from(c == '\n');
+                            prev = c;
+                        }
+                        None => {
+                            return Err(TemplateError::Syntax(
+                                line,
+                                "Unterminated comment".to_string(),
+                            ));
+                        }
+                    }
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    ident.push(c);
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            c if c.is_ascii_digit() => {
+                let mut digits = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    digits.push(c);
+                }
+                let digits = digits.replace('_', "");
+                let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+                    Some(hex) => i128::from_str_radix(hex, 16).ok(),
+                    None => digits.parse().ok(),
+                };
+                let value = value.ok_or_else(|| {
+                    TemplateError::Syntax(line, format!("Invalid number {:?}", digits))
+                })?;
+                tokens.push((Token::Num(value), line));
+            }
+            '{' | '}' | '[' | ']' | '(' | ')' | ';' | '@' | '.' | '+' | '-' | '*' => {
+                tokens.push((Token::Punct(c), line));
+            }
+            c => return Err(TemplateError::Syntax(line, format!("Unexpected character {:?}", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+/// 字句の列を読み進める構文解析器
+struct Parser {
+    tokens: Peekable<IntoIter<(Token, usize)>>,
+    /// 最後に読んだ字句の行
+    line: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let (token, line) = self.tokens.next()?;
+        self.line = line;
+        Some(token)
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek().map(|(token, _)| token)
+    }
+
+    fn error(&self, message: impl Into<String>) -> TemplateError {
+        TemplateError::Syntax(self.line, message.into())
+    }
+
+    /// 次が記号 c なら読み進める
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), TemplateError> {
+        match self.next() {
+            Some(Token::Punct(found)) if found == c => Ok(()),
+            Some(token) => {
+                Err(self.error(format!("Expected '{}' but found {}", c, describe(&token))))
+            }
+            None => Err(self.error(format!("Expected '{}' at end of template", c))),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, TemplateError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(name),
+            Some(token) => {
+                Err(self.error(format!("Expected a name but found {}", describe(&token))))
+            }
+            None => Err(self.error("Expected a name at end of template")),
+        }
+    }
+
+    /// `struct 名前 { フィールド... }`
+    fn struct_def(&mut self) -> Result<StructDef, TemplateError> {
+        let name = self.ident()?;
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        while !self.eat('}') {
+            fields.push(self.field()?);
+        }
+        self.eat(';');
+        Ok(StructDef { name, fields })
+    }
+
+    /// `[le|be] 型 名前 [[要素数]] [@ オフセット];`
+    fn field(&mut self) -> Result<FieldDef, TemplateError> {
+        let mut ty = self.ident()?;
+        let big_endian = match ty.as_str() {
+            "le" => Some(false),
+            "be" => Some(true),
+            _ => None,
+        };
+        if big_endian.is_some() {
+            ty = self.ident()?;
+        }
+        let line = self.line;
+        let ty = match ty.as_str() {
+            "char" => TypeRef::Char,
+            name => {
+                NumKind::from_name(name).map_or_else(|| TypeRef::Struct(ty.clone()), TypeRef::Num)
+            }
+        };
+        let name = self.ident()?;
+        let count = if self.eat('[') {
+            let count = self.expr()?;
+            self.expect(']')?;
+            Some(count)
+        } else {
+            None
+        };
+        let offset = if self.eat('@') { Some(self.expr()?) } else { None };
+        self.expect(';')?;
+        Ok(FieldDef {
+            name,
+            ty,
+            big_endian,
+            count,
+            offset,
+            line,
+        })
+    }
+
+    /// 項を + / - でつないだ式
+    fn expr(&mut self) -> Result<Expr, TemplateError> {
+        let mut expr = self.term()?;
+        loop {
+            if self.eat('+') {
+                expr = Expr::Add(Box::new(expr), Box::new(self.term()?));
+            } else if self.eat('-') {
+                expr = Expr::Sub(Box::new(expr), Box::new(self.term()?));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// 因子を * でつないだ項
+    fn term(&mut self) -> Result<Expr, TemplateError> {
+        let mut expr = self.atom()?;
+        while self.eat('*') {
+            expr = Expr::Mul(Box::new(expr), Box::new(self.atom()?));
+        }
+        Ok(expr)
+    }
+
+    /// 数値・フィールドの参照・括弧で囲んだ式
+    fn atom(&mut self) -> Result<Expr, TemplateError> {
+        match self.next() {
+            Some(Token::Num(value)) => Ok(Expr::Num(value)),
+            Some(Token::Ident(name)) => {
+                let mut path = vec![name];
+                while self.eat('.') {
+                    path.push(self.ident()?);
+                }
+                Ok(Expr::Field(path))
+            }
+            Some(Token::Punct('(')) => {
+                let expr = self.expr()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(token) => Err(self.error(format!(
+                "Expected a number or field but found {}",
+                describe(&token)
+            ))),
+            None => Err(self.error("Expected a number or field at end of template")),
+        }
+    }
+}
+
+/// エラー表示用の字句の説明
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(name) => format!("{:?}", name),
+        Token::Num(value) => value.to_string(),
+        Token::Punct(c) => format!("'{}'", c),
+    }
+}
+
+/// テンプレートを解釈（構造体の定義と `endian little|big;`）
+pub fn parse(source: &str) -> Result<Template, TemplateError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?.into_iter().peekable(),
+        line: 1,
+    };
+    let mut structs: Vec<StructDef> = Vec::new();
+    let mut big_endian = false;
+    while let Some(token) = parser.next() {
+        match token {
+            Token::Ident(keyword) if keyword == "struct" => {
+                let def = parser.struct_def()?;
+                if structs.iter().any(|s| s.name == def.name) {
+                    return Err(parser.error(format!("Struct {:?} is defined twice", def.name)));
+                }
+                structs.push(def);
+            }
+            Token::Ident(keyword) if keyword == "endian" => {
+                big_endian = match parser.ident()?.as_str() {
+                    "little" => false,
+                    "big" => true,
+                    other => {
+                        return Err(
+                            parser.error(format!("Expected little or big but found {:?}", other))
+                        );
+                    }
+                };
+                parser.expect(';')?;
+            }
+            token => {
+                return Err(parser.error(format!(
+                    "Expected struct or endian but found {}",
+                    describe(&token)
+                )));
+            }
+        }
+    }
+    // 構造体の型は定義順によらず参照できる（未定義ならここでエラー）
+    for field in structs.iter().flat_map(|s| &s.fields) {
+        if let TypeRef::Struct(name) = &field.ty {
+            if !structs.iter().any(|s| &s.name == name) {
+                return Err(TemplateError::UnknownType(field.line, name.clone()));
+            }
+        }
+    }
+    if structs.is_empty() {
+        return Err(TemplateError::Empty);
+    }
+    Ok(Template { structs, big_endian })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let template = parse(
+            "endian big; /* header */\n\
+             struct Item { le u16 len; char name[len * 2 + 1]; }\n\
+             struct File { u8 count; Item items[count] @ 0x10; } // root",
+        )
+        .unwrap();
+        assert!(template.big_endian);
+        assert_eq!(template.root_name(), "File");
+        let items = &template.structs[1].fields[1];
+        assert_eq!(items.ty, TypeRef::Struct("Item".to_string()));
+        assert_eq!(items.offset, Some(Expr::Num(0x10)));
+        assert_eq!(items.line, 3);
+        let name = &template.structs[0].fields[1];
+        assert_eq!(
+            name.count,
+            Some(Expr::Add(
+                Box::new(Expr::Mul(
+                    Box::new(Expr::Field(vec!["len".to_string()])),
+                    Box::new(Expr::Num(2))
+                )),
+                Box::new(Expr::Num(1))
+            ))
+        );
+        assert_eq!(template.structs[0].fields[0].big_endian, Some(false));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("").unwrap_err(), TemplateError::Empty);
+        assert_eq!(
+            parse("struct A {\n  Missing m;\n}").unwrap_err(),
+            TemplateError::UnknownType(2, "Missing".to_string())
+        );
+        assert_eq!(
+            parse("struct A {\n  u8 x\n}").unwrap_err(),
+            TemplateError::Syntax(3, "Expected ';' but found '}'".to_string())
+        );
+        assert!(parse("struct A { u8 x[; }").is_err());
+        assert!(parse("struct A { u8 x; } struct A { u8 y; }").is_err());
+    }
+}
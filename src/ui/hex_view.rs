@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
@@ -18,8 +20,12 @@ pub enum ViewMode {
 
 /// HEX/ASCII表示ウィジェット
 pub struct HexView<'a> {
-    /// 表示するデータ
+    /// 表示範囲周辺のデータ（data_start から始まる部分列）
     data: &'a [u8],
+    /// data[0] のドキュメント上の位置
+    data_start: usize,
+    /// ドキュメント全体の長さ
+    data_len: usize,
     /// 表示開始オフセット
     offset: usize,
     /// 1行あたりのバイト数
@@ -28,6 +34,12 @@ pub struct HexView<'a> {
     cursor: usize,
     /// 選択範囲（開始, 終了）
     selection: Option<(usize, usize)>,
+    /// 差分として強調表示する範囲（比較モード用）
+    highlights: &'a [Range<usize>],
+    /// 保存時点から編集された範囲
+    modified: &'a [Range<usize>],
+    /// 検索中のパターンに一致する範囲
+    matches: &'a [Range<usize>],
     /// 現在の表示モード
     mode: ViewMode,
     /// 文字エンコーディング
@@ -37,19 +49,35 @@ pub struct HexView<'a> {
 }
 
 impl<'a> HexView<'a> {
+    /// 行境界をまたぐ文字のデコードに必要な前後のバイト数
+    pub const LOOKAROUND: usize = 4;
+
     pub fn new(data: &'a [u8]) -> Self {
         Self {
             data,
+            data_start: 0,
+            data_len: data.len(),
             offset: 0,
             bytes_per_row: 16,
             cursor: 0,
             selection: None,
+            highlights: &[],
+            modified: &[],
+            matches: &[],
             mode: ViewMode::Hex,
             encoding: CharEncoding::Utf8,
             addr_radix: 16,
         }
     }
 
+    /// data がドキュメントの一部分の場合に、その開始位置と全体の長さを指定
+    /// 表示範囲の前後 LOOKAROUND バイトを含んでいる必要がある
+    pub fn window(mut self, start: usize, total_len: usize) -> Self {
+        self.data_start = start;
+        self.data_len = total_len;
+        self
+    }
+
     pub fn offset(mut self, offset: usize) -> Self {
         self.offset = offset;
         self
@@ -70,6 +98,21 @@ impl<'a> HexView<'a> {
         self
     }
 
+    pub fn highlights(mut self, highlights: &'a [Range<usize>]) -> Self {
+        self.highlights = highlights;
+        self
+    }
+
+    pub fn modified(mut self, modified: &'a [Range<usize>]) -> Self {
+        self.modified = modified;
+        self
+    }
+
+    pub fn matches(mut self, matches: &'a [Range<usize>]) -> Self {
+        self.matches = matches;
+        self
+    }
+
     pub fn mode(mut self, mode: ViewMode) -> Self {
         self.mode = mode;
         self
@@ -80,6 +123,13 @@ impl<'a> HexView<'a> {
         self
     }
 
+    /// ドキュメント上の範囲 [start, end) に対応するスライスを取得
+    fn slice(&self, start: usize, end: usize) -> &[u8] {
+        let from = start.saturating_sub(self.data_start).min(self.data.len());
+        let to = end.saturating_sub(self.data_start).min(self.data.len());
+        &self.data[from..to.max(from)]
+    }
+
     /// アドレス文字列を生成
     fn format_addr(&self, addr: usize) -> String {
         if self.addr_radix == 16 {
@@ -89,6 +139,21 @@ impl<'a> HexView<'a> {
         }
     }
 
+    /// 差分として強調表示する位置かどうか
+    fn is_highlighted(&self, pos: usize) -> bool {
+        self.highlights.iter().any(|r| r.contains(&pos))
+    }
+
+    /// 保存時点から編集された位置かどうか
+    fn is_modified(&self, pos: usize) -> bool {
+        self.modified.iter().any(|r| r.contains(&pos))
+    }
+
+    /// 検索中のパターンに一致する位置かどうか
+    fn is_match(&self, pos: usize) -> bool {
+        self.matches.iter().any(|r| r.contains(&pos))
+    }
+
     /// バイト値に応じた色を取得
     fn byte_color(&self, byte: u8) -> Color {
         match byte {
@@ -101,18 +166,17 @@ impl<'a> HexView<'a> {
 
     /// 前の行からはみ出した文字の継続バイト数を計算
     fn count_continuation_bytes(&self, row_start: usize) -> usize {
-        if row_start == 0 || self.data.is_empty() {
+        if row_start == 0 || self.data_len == 0 {
             return 0;
         }
 
         // 前の数バイトを調べて、行境界をまたぐ文字があるかチェック
-        let lookahead = 4;
-        let check_start = row_start.saturating_sub(lookahead);
-        let end = row_start.min(self.data.len());
+        let check_start = row_start.saturating_sub(Self::LOOKAROUND);
+        let end = row_start.min(self.data_len);
         if check_start >= end {
             return 0;
         }
-        let check_bytes = &self.data[check_start..end];
+        let check_bytes = self.slice(check_start, end);
 
         if check_bytes.is_empty() {
             return 0;
@@ -133,31 +197,27 @@ impl<'a> HexView<'a> {
         }
 
         // 最後の文字が row_start を超えていれば、その分が継続バイト
-        if last_char_end > row_start {
-            last_char_end - row_start
-        } else {
-            0
-        }
+        last_char_end.saturating_sub(row_start)
     }
 
     /// 1行分のデータを描画
     fn render_row(&self, row_offset: usize, area: Rect, buf: &mut Buffer) {
         let row_start = self.offset + row_offset * self.bytes_per_row;
-        let row_end = (row_start + self.bytes_per_row).min(self.data.len());
+        let row_end = (row_start + self.bytes_per_row).min(self.data_len);
 
         // 前の行からはみ出した文字の継続バイト数
         let skip_bytes = self.count_continuation_bytes(row_start);
 
         // EOF行も描画可能にする（カーソルがEOF位置にある場合）
-        let eof_pos = self.data.len();
+        let eof_pos = self.data_len;
         let cursor_at_eof = self.cursor == eof_pos;
 
-        if row_start > self.data.len() {
+        if row_start > self.data_len {
             return;
         }
 
         // データがなく、かつカーソルもこの行にない場合はスキップ
-        if row_start >= self.data.len() && !cursor_at_eof {
+        if row_start >= self.data_len && !cursor_at_eof {
             return;
         }
 
@@ -170,22 +230,33 @@ impl<'a> HexView<'a> {
         x += addr_str.len() as u16 + 2;
 
         // HEX表示
+        let row_bytes = self.slice(row_start, row_end);
         for i in row_start..row_start + self.bytes_per_row {
             if i < row_end {
-                let byte = self.data[i];
+                let byte = row_bytes[i - row_start];
                 let hex = format!("{:02X}", byte);
 
-                let mut style = Style::default().fg(self.byte_color(byte));
+                let mut style = if self.is_modified(i) {
+                    Style::default().fg(Colors::MODIFIED)
+                } else {
+                    Style::default().fg(self.byte_color(byte))
+                };
 
                 // カーソル位置のハイライト
                 if i == self.cursor && self.mode == ViewMode::Hex {
                     style = style.bg(Colors::CURSOR_BG).fg(Colors::CURSOR);
                 }
                 // 選択範囲のハイライト
-                else if let Some((start, end)) = self.selection {
-                    if i >= start && i <= end {
-                        style = style.bg(Colors::SELECTION_BG);
-                    }
+                else if self.selection.is_some_and(|(start, end)| i >= start && i <= end) {
+                    style = style.bg(Colors::SELECTION_BG);
+                }
+                // 差分のハイライト
+                else if self.is_highlighted(i) {
+                    style = style.bg(Colors::DIFF_BG).fg(Colors::DIFF);
+                }
+                // 検索の一致のハイライト
+                else if self.is_match(i) {
+                    style = style.bg(Colors::MATCH_BG).fg(Colors::MATCH);
                 }
 
                 buf.set_string(x, y, &hex, style);
@@ -202,13 +273,8 @@ impl<'a> HexView<'a> {
 
         // ASCII表示（エンコーディングに従ってデコード）
         // 行末のマルチバイト文字を正しく表示するため、次の行のバイトも含めてデコード
-        let lookahead = 4; // UTF-8/UTF-16の最大バイト数
-        let decode_end = (row_end + lookahead).min(self.data.len());
-        let row_bytes = if decode_end > row_start {
-            &self.data[row_start..decode_end]
-        } else {
-            &[]
-        };
+        let decode_end = (row_end + Self::LOOKAROUND).min(self.data_len);
+        let row_bytes = self.slice(row_start, decode_end);
         let decoded = decode_for_display(row_bytes, self.encoding);
 
         let mut byte_idx = 0;
@@ -224,7 +290,11 @@ impl<'a> HexView<'a> {
             if byte_idx < decoded.len() {
                 if let Some(ref dc) = decoded[byte_idx] {
                     // この位置に文字がある
-                    let mut style = Style::default().fg(Colors::ASCII_NORMAL);
+                    let mut style = if self.is_modified(abs_idx) {
+                        Style::default().fg(Colors::MODIFIED)
+                    } else {
+                        Style::default().fg(Colors::ASCII_NORMAL)
+                    };
 
                     // カーソル位置のハイライト
                     let cursor_in_char = self.cursor >= abs_idx
@@ -233,10 +303,19 @@ impl<'a> HexView<'a> {
                         style = style.bg(Colors::CURSOR_BG).fg(Colors::CURSOR);
                     }
                     // 選択範囲のハイライト
-                    else if let Some((start, end)) = self.selection {
-                        if abs_idx >= start && abs_idx <= end {
-                            style = style.bg(Colors::SELECTION_BG);
-                        }
+                    else if self
+                        .selection
+                        .is_some_and(|(start, end)| abs_idx >= start && abs_idx <= end)
+                    {
+                        style = style.bg(Colors::SELECTION_BG);
+                    }
+                    // 差分のハイライト
+                    else if self.is_highlighted(abs_idx) {
+                        style = style.bg(Colors::DIFF_BG).fg(Colors::DIFF);
+                    }
+                    // 検索の一致のハイライト
+                    else if self.is_match(abs_idx) {
+                        style = style.bg(Colors::MATCH_BG).fg(Colors::MATCH);
                     }
 
                     // 文字を表示
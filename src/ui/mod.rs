@@ -21,5 +21,9 @@ impl Colors {
     pub const CURSOR_BG: Color = Color::Yellow;
     pub const SELECTION_BG: Color = Color::Blue;
     pub const MODIFIED: Color = Color::Magenta;
+    pub const DIFF: Color = Color::White;
+    pub const DIFF_BG: Color = Color::Red;
+    pub const MATCH: Color = Color::Black;
+    pub const MATCH_BG: Color = Color::Cyan;
     pub const HEADER: Color = Color::Yellow;
 }